@@ -1,14 +1,88 @@
 use crate::math::ast::*;
-use ordered_float::OrderedFloat;
+use crate::math::diff::differentiate;
+use crate::math::env::Environment;
+use crate::math::eval::{evaluate, factorial};
+use crate::math::functions;
+use crate::math::integrate::integrate;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 
 pub fn simplify(expr: &Expr) -> Expr {
     match expr {
-        Expr::Number(_) | Expr::Variable(_) => expr.clone(),
+        Expr::Number(_) | Expr::Variable(_) | Expr::Constant(_) | Expr::Hole(_) => expr.clone(),
+
+        // Nested lists, such as an expanded range, are spliced in
+        Expr::List(items) => Expr::List(
+            items
+                .iter()
+                .flat_map(|e| match simplify(e) {
+                    Expr::List(inner) => inner,
+                    other => vec![other],
+                })
+                .collect(),
+        ),
+
+        // Branches whose condition is a false constant are dropped, and the
+        // first true one ends the list
+        Expr::Piecewise(branches, otherwise) => {
+            let mut kept = Vec::new();
+            let mut otherwise = otherwise.as_deref().map(simplify);
+            for (value, cond) in branches {
+                let cond = cond.map(simplify);
+                match constant_truth(&cond) {
+                    Some(true) => {
+                        otherwise = Some(simplify(value));
+                        break;
+                    }
+                    Some(false) => {}
+                    None => kept.push((simplify(value), cond)),
+                }
+            }
+            match (kept.is_empty(), otherwise) {
+                (true, Some(value)) => value,
+                (true, None) => Expr::Number(Real::from(f64::NAN)),
+                (false, otherwise) => Expr::Piecewise(kept, otherwise.map(Box::new)),
+            }
+        }
+
+        Expr::Derivative(e, var, order) => {
+            simplify(&(0..*order).fold((**e).clone(), |d, _| differentiate(&d, var)))
+        }
+
+        Expr::Integral(e, var, None) => integrate(e, var)
+            .unwrap_or_else(|| Expr::Integral(Box::new(simplify(e)), var.clone(), None)),
+
+        // F(b) - F(a) by the antiderivative, kept exact, or numerically when
+        // there is none and the integral is a plain number
+        Expr::Integral(e, var, Some(bounds)) => {
+            let (a, b) = (simplify(&bounds.0), simplify(&bounds.1));
+            let at = |f: &Expr, x: &Expr| f.substitute(&HashMap::from([(var.clone(), x.clone())]));
+            if let Some(f) = integrate(e, var) {
+                let difference =
+                    Expr::BinaryOp(BinaryOp::Sub, Box::new(at(&f, &b)), Box::new(at(&f, &a)));
+                return simplify(&difference);
+            }
+            let e = simplify(e);
+            let numeric = is_constant(&at(&e, &Expr::Number(Real::from(0))))
+                && is_constant(&a)
+                && is_constant(&b);
+            let integral = Expr::Integral(Box::new(e), var.clone(), Some(Box::new((a, b))));
+            if numeric {
+                Expr::Number(Real::from(evaluate(&integral)))
+            } else {
+                integral
+            }
+        }
 
         Expr::UnaryOp(op, e) => {
             let se = simplify(e);
+            if let Some(list) = broadcast(std::slice::from_ref(&se), |xs| {
+                simplify(&Expr::UnaryOp(op.clone(), Box::new(xs[0].clone())))
+            }) {
+                return list;
+            }
             match (op, &se) {
-                (UnaryOp::Neg, Expr::Number(n)) => Expr::Number(OrderedFloat(-n.0)),
+                (UnaryOp::Neg, Expr::Number(n)) => Expr::Number(-n),
 
                 (UnaryOp::Neg, Expr::UnaryOp(UnaryOp::Neg, inner)) => *inner.clone(), // double negation
 
@@ -25,13 +99,46 @@ pub fn simplify(expr: &Expr) -> Expr {
                     Expr::BinaryOp(BinaryOp::Add, Box::new(neg_a), b.clone())
                 }
 
+                // Small factorials are exact in f64; larger ones stay symbolic
+                (UnaryOp::Factorial, Expr::Number(n))
+                    if n.to_i64().is_some_and(|k| (0..=20).contains(&k)) =>
+                {
+                    Expr::Number(Real::from(factorial(n.to_f64())))
+                }
+
+                (UnaryOp::Percent, Expr::Number(n)) => Expr::Number(n / &Real::from(100)),
+
                 _ => Expr::UnaryOp(op.clone(), Box::new(se)),
             }
         }
 
         Expr::BinaryOp(op, a, b) => {
-            let sa = simplify(a);
-            let sb = simplify(b);
+            // A long sum is simplified in two halves, so that the recursion
+            // goes only as deep as the halving rather than the sum is long
+            let (sa, sb) = match (op, &**a) {
+                (BinaryOp::Add, Expr::BinaryOp(BinaryOp::Add, ..)) => {
+                    let mut left = flatten_add(expr);
+                    let right = left.split_off(left.len().div_ceil(2));
+                    (
+                        simplify(&fold_binary_ops(BinaryOp::Add, left)),
+                        simplify(&fold_binary_ops(BinaryOp::Add, right)),
+                    )
+                }
+                _ => (simplify(a), simplify(b)),
+            };
+
+            if let Some(list) = broadcast(&[sa.clone(), sb.clone()], |xs| {
+                simplify(&Expr::BinaryOp(
+                    op.clone(),
+                    Box::new(xs[0].clone()),
+                    Box::new(xs[1].clone()),
+                ))
+            }) {
+                return list;
+            }
+            if let Some(z) = complex_arithmetic(op, &sa, &sb) {
+                return z;
+            }
 
             match op {
                 BinaryOp::Add => {
@@ -52,31 +159,27 @@ pub fn simplify(expr: &Expr) -> Expr {
                                 Expr::Number(n2),
                             ) = (&**s1, &**s2, &**e1, &**e2)
                             {
-                                if n1 == &OrderedFloat(2.0)
-                                    && n2 == &OrderedFloat(2.0)
+                                if n1.to_i64() == Some(2)
+                                    && n2.to_i64() == Some(2)
                                     && ((f1 == "sin" && f2 == "cos")
                                         || (f1 == "cos" && f2 == "sin"))
                                     && arg1 == arg2
                                 {
-                                    return Expr::Number(OrderedFloat(1.0));
+                                    return Expr::Number(Real::from(1));
                                 }
                             }
                         }
                     }
 
                     let combined_terms = combine_like_terms(terms);
-                    let (sum_consts, mut non_consts) = partition_consts(combined_terms);
-
-                    let mut result = if sum_consts.0.abs() > 1e-12 {
-                        vec![Expr::Number(sum_consts)]
-                    } else {
-                        vec![]
-                    };
-
-                    result.append(&mut non_consts);
+                    let (sum_consts, mut result) = partition_consts(combined_terms);
+                    if !sum_consts.is_zero() {
+                        result.push(Expr::Number(sum_consts));
+                    }
+                    sort_terms(&mut result);
 
                     if result.is_empty() {
-                        Expr::Number(OrderedFloat(0.0))
+                        Expr::Number(Real::from(0))
                     } else if result.len() == 1 {
                         result.into_iter().next().unwrap()
                     } else {
@@ -88,12 +191,67 @@ pub fn simplify(expr: &Expr) -> Expr {
                     let mut factors = flatten_mul(&sa);
                     factors.extend(flatten_mul(&sb));
 
-                    let (prod_consts, mut non_consts) = partition_consts_mul(factors);
+                    let (mut prod_consts, mut non_consts) = partition_consts_mul(factors);
 
-                    if prod_consts == OrderedFloat(0.0) {
-                        Expr::Number(OrderedFloat(0.0))
+                    // Signs move to the coefficient, so that `-a * x` sorts as `a x`
+                    for factor in non_consts.iter_mut() {
+                        while let Expr::UnaryOp(UnaryOp::Neg, inner) = factor {
+                            *factor = (**inner).clone();
+                            prod_consts = -prod_consts;
+                        }
+                    }
+
+                    // Pairs of imaginary units multiply to -1
+                    let i = Expr::Constant(Constant::I);
+                    let units = non_consts.iter().filter(|f| **f == i).count();
+                    if units >= 2 {
+                        non_consts.retain(|f| *f != i);
+                        if units % 4 >= 2 {
+                            prod_consts = -prod_consts;
+                        }
+                        if units % 2 == 1 {
+                            non_consts.push(i);
+                        }
+                    }
+
+                    // Powers of a common base, as in `x^2 x^3 = x^5`
+                    let (k, mut non_consts) = partition_consts_mul(collect_powers(non_consts));
+                    prod_consts = prod_consts * k;
+                    non_consts.sort_by(factor_order);
+
+                    // Quotients among the factors cancel against the rest,
+                    // as in `x (1/x) = 1`
+                    if !prod_consts.is_zero() {
+                        let mut top = vec![Expr::Number(prod_consts.clone())];
+                        let mut bottom = Vec::new();
+                        for factor in &non_consts {
+                            match factor {
+                                Expr::BinaryOp(BinaryOp::Div, n, d) => {
+                                    top.push((**n).clone());
+                                    bottom.push((**d).clone());
+                                }
+                                other => top.push(other.clone()),
+                            }
+                        }
+                        if !bottom.is_empty() {
+                            if let Some(cancelled) = cancel_powers(
+                                &fold_binary_ops(BinaryOp::Mul, top),
+                                &fold_binary_ops(BinaryOp::Mul, bottom),
+                            ) {
+                                return cancelled;
+                            }
+                        }
+                    }
+
+                    if prod_consts.is_zero() {
+                        Expr::Number(Real::from(0))
+                    } else if prod_consts.to_i64() == Some(-1) && !non_consts.is_empty() {
+                        // The sign goes back on the first factor, as in `-x y`
+                        non_consts[0] =
+                            Expr::UnaryOp(UnaryOp::Neg, Box::new(non_consts[0].clone()));
+                        fold_binary_ops(BinaryOp::Mul, non_consts)
                     } else {
-                        let mut result = if (prod_consts.0 - 1.0).abs() > 1e-12 {
+                        let mut result = if !prod_consts.is_one() {
                             vec![Expr::Number(prod_consts)]
                         } else {
                             vec![]
@@ -102,7 +260,7 @@ pub fn simplify(expr: &Expr) -> Expr {
                         result.append(&mut non_consts);
 
                         if result.is_empty() {
-                            Expr::Number(OrderedFloat(1.0))
+                            Expr::Number(Real::from(1))
                         } else if result.len() == 1 {
                             result.into_iter().next().unwrap()
                         } else {
@@ -113,7 +271,7 @@ pub fn simplify(expr: &Expr) -> Expr {
 
                 BinaryOp::Sub => {
                     if sa == sb {
-                        Expr::Number(OrderedFloat(0.0))
+                        Expr::Number(Real::from(0))
                     } else {
                         // Distribute negation over addition if sb is sum
                         match sb {
@@ -143,82 +301,870 @@ pub fn simplify(expr: &Expr) -> Expr {
                 }
 
                 BinaryOp::Div => match (&sa, &sb) {
-                    (Expr::Number(n1), Expr::Number(n2)) => Expr::Number(OrderedFloat(n1.0 / n2.0)),
+                    (Expr::Number(n1), Expr::Number(n2)) => Expr::Number(n1 / n2),
+                    // a / i = -a i
+                    (_, Expr::Constant(Constant::I)) => simplify(&Expr::UnaryOp(
+                        UnaryOp::Neg,
+                        Box::new(Expr::BinaryOp(BinaryOp::Mul, Box::new(sa), Box::new(sb))),
+                    )),
                     _ => {
-                        if sb == Expr::Number(OrderedFloat(1.0)) {
+                        if sb == Expr::Number(Real::from(1)) {
                             sa
-                        } else if sa == Expr::Number(OrderedFloat(0.0)) {
-                            Expr::Number(OrderedFloat(0.0))
+                        } else if sa == Expr::Number(Real::from(0)) {
+                            Expr::Number(Real::from(0))
+                        } else if let Some(cancelled) = cancel_powers(&sa, &sb) {
+                            cancelled
                         } else {
                             Expr::BinaryOp(BinaryOp::Div, Box::new(sa), Box::new(sb))
                         }
                     }
                 },
 
-                BinaryOp::Pow => match &sb {
-                    Expr::Number(n) if *n == OrderedFloat(0.0) => Expr::Number(OrderedFloat(1.0)),
-                    Expr::Number(n) if *n == OrderedFloat(1.0) => sa,
+                BinaryOp::Pow => match (&sa, &sb) {
+                    (_, Expr::Number(n)) if n.is_zero() => Expr::Number(Real::from(1)),
+                    (_, Expr::Number(n)) if n.is_one() => sa,
+                    // Powers of i cycle through i, -1, -i, 1
+                    (Expr::Constant(Constant::I), Expr::Number(n)) if n.to_i64().is_some() => {
+                        match n.to_i64().unwrap().rem_euclid(4) {
+                            0 => Expr::Number(Real::from(1)),
+                            1 => sa,
+                            2 => Expr::Number(Real::from(-1)),
+                            _ => Expr::UnaryOp(UnaryOp::Neg, Box::new(sa)),
+                        }
+                    }
+                    // (u^2k)^b = |u|^(2k b), as `(x^2)^(1/2) = |x|`
+                    (Expr::BinaryOp(BinaryOp::Pow, u, a), Expr::Number(b))
+                        if !b.is_integer() && is_even(a) =>
+                    {
+                        simplify(&Expr::BinaryOp(
+                            BinaryOp::Pow,
+                            Box::new(Expr::Function("abs".into(), vec![(**u).clone()])),
+                            Box::new(Expr::BinaryOp(BinaryOp::Mul, a.clone(), Box::new(sb))),
+                        ))
+                    }
+                    // (u^a)^b = u^(a b) where `combinable` allows, or for any b when u > 0
+                    (Expr::BinaryOp(BinaryOp::Pow, u, a), _)
+                        if is_positive(u) || combinable(&[(**a).clone()], &sb) =>
+                    {
+                        simplify(&Expr::BinaryOp(
+                            BinaryOp::Pow,
+                            u.clone(),
+                            Box::new(Expr::BinaryOp(BinaryOp::Mul, a.clone(), Box::new(sb))),
+                        ))
+                    }
+                    // (-a)^(k/2) = sqrt(-a)^k, with sqrt(-a) = sqrt(a) i
+                    (Expr::Number(a), Expr::Number(b))
+                        if a.is_negative()
+                            && !b.is_integer()
+                            && (b * &Real::from(2)).is_integer() =>
+                    {
+                        let root = Expr::Function("sqrt".into(), vec![sa.clone()]);
+                        let k = Expr::Number(b * &Real::from(2));
+                        simplify(&Expr::BinaryOp(BinaryOp::Pow, Box::new(root), Box::new(k)))
+                    }
+                    // Only exact powers fold, so `2^(1/2)` stays as it is
+                    (Expr::Number(a), Expr::Number(b)) if a.pow(b).is_exact() => {
+                        Expr::Number(a.pow(b))
+                    }
                     _ => Expr::BinaryOp(BinaryOp::Pow, Box::new(sa), Box::new(sb)),
                 },
             }
         }
 
-        Expr::Function(name, arg) => {
-            let sarg = simplify(arg);
-            if let Expr::Number(n) = &sarg {
-                let val = match name.as_str() {
-                    "sin" => n.0.sin(),
-                    "cos" => n.0.cos(),
-                    "tan" => n.0.tan(),
-                    "log" => {
-                        if n.0 > 0.0 {
-                            n.0.ln()
-                        } else {
-                            return Expr::Function(name.clone(), Box::new(sarg));
-                        }
-                    }
-                    "exp" => n.0.exp(),
-                    "abs" => n.0.abs(),
-                    _ => return Expr::Function(name.clone(), Box::new(sarg)),
-                };
-                Expr::Number(OrderedFloat(val))
-            } else {
-                Expr::Function(name.clone(), Box::new(sarg))
+        Expr::Function(name, args) => {
+            let sargs: Vec<Expr> = args.iter().map(simplify).collect();
+            let Some(f) = functions::lookup(name) else {
+                return Expr::Function(name.clone(), sargs);
+            };
+
+            if let Some(exact) = f.exact.as_ref().and_then(|exact| exact(&sargs)) {
+                return exact;
             }
+            if !f.takes_lists {
+                if let Some(list) =
+                    broadcast(&sargs, |xs| simplify(&Expr::Function(name.clone(), xs)))
+                {
+                    return list;
+                }
+            }
+            // Anything else, such as sqrt(2) or ln(2), is left for evaluate
+            // rather than rounded to a float
+            Expr::Function(name.clone(), sargs)
+        }
+    }
+}
+
+/// Normalize a relation by moving everything to the left-hand side,
+/// so `x^2 = 2x + 1` becomes `x^2 - 2x - 1 = 0`.
+pub fn simplify_relation(rel: &Relation) -> Relation {
+    let lhs = simplify(&Expr::BinaryOp(
+        BinaryOp::Sub,
+        Box::new(rel.lhs.clone()),
+        Box::new(rel.rhs.clone()),
+    ));
+    Relation {
+        op: rel.op,
+        lhs,
+        rhs: Expr::Number(Real::from(0)),
+    }
+}
+
+/// Simplify after expanding the variables and user functions of `env`.
+pub fn simplify_with_env(expr: &Expr, env: &Environment) -> Expr {
+    simplify(&env.inline(expr))
+}
+
+pub fn simplify_statement(stmt: &Statement) -> Statement {
+    match stmt {
+        Statement::Expr(e) => Statement::Expr(simplify(e)),
+        Statement::Relation(rel) => Statement::Relation(simplify_relation(rel)),
+        Statement::Assign(name, value) => Statement::Assign(name.clone(), simplify(value)),
+        Statement::Define(name, params, body) => {
+            Statement::Define(name.clone(), params.clone(), simplify(body))
         }
     }
 }
 
-// rest unchanged
+/// Multiply out products and whole powers of sums, and split quotients
+/// over the terms of their numerator, so `(x + 1)^2 / x` becomes
+/// `x + 2 + 1/x`. Like terms are then combined as `simplify` does.
+pub fn expand(expr: &Expr) -> Expr {
+    sum(expanded_terms(&simplify(expr)))
+}
 
-fn flatten_add(expr: &Expr) -> Vec<Expr> {
+pub fn expand_statement(stmt: &Statement) -> Statement {
+    match stmt {
+        Statement::Expr(e) => Statement::Expr(expand(e)),
+        Statement::Relation(rel) => Statement::Relation(Relation {
+            op: rel.op,
+            lhs: expand(&rel.lhs),
+            rhs: expand(&rel.rhs),
+        }),
+        Statement::Assign(name, value) => Statement::Assign(name.clone(), expand(value)),
+        Statement::Define(name, params, body) => {
+            Statement::Define(name.clone(), params.clone(), expand(body))
+        }
+    }
+}
+
+/// The terms of `expr` once expanded, none of them a sum.
+fn expanded_terms(expr: &Expr) -> Vec<Expr> {
+    let negated = |terms: Vec<Expr>| {
+        terms
+            .into_iter()
+            .map(|t| simplify(&Expr::UnaryOp(UnaryOp::Neg, Box::new(t))))
+            .collect()
+    };
     match expr {
         Expr::BinaryOp(BinaryOp::Add, a, b) => {
-            let mut v = flatten_add(a);
-            v.extend(flatten_add(b));
-            v
+            let mut terms = expanded_terms(a);
+            terms.extend(expanded_terms(b));
+            terms
+        }
+        Expr::BinaryOp(BinaryOp::Sub, a, b) => {
+            let mut terms = expanded_terms(a);
+            terms.extend(negated(expanded_terms(b)));
+            terms
+        }
+        Expr::UnaryOp(UnaryOp::Neg, e) => negated(expanded_terms(e)),
+
+        Expr::BinaryOp(BinaryOp::Mul, a, b) => distribute(&expanded_terms(a), &expanded_terms(b)),
+
+        // (a + b) / c = a/c + b/c
+        Expr::BinaryOp(BinaryOp::Div, a, b) => {
+            let den = expand(b);
+            expanded_terms(a)
+                .into_iter()
+                .map(|t| {
+                    simplify(&Expr::BinaryOp(
+                        BinaryOp::Div,
+                        Box::new(t),
+                        Box::new(den.clone()),
+                    ))
+                })
+                .collect()
+        }
+
+        Expr::BinaryOp(BinaryOp::Pow, base, exp) => {
+            let n = match &**exp {
+                Expr::Number(n) => n.to_i64().unwrap_or(0),
+                _ => 0,
+            };
+            let terms = expanded_terms(base);
+            if n >= 2 && multinomial_len(terms.len(), n) <= MAX_EXPANDED_TERMS {
+                multinomial(&terms, n as u32)
+            } else if n <= -2 || (n == -1 && terms.len() > 1) {
+                // The reciprocal of the expanded power
+                let power = Expr::BinaryOp(
+                    BinaryOp::Pow,
+                    base.clone(),
+                    Box::new(Expr::Number(Real::from(-n))),
+                );
+                vec![simplify(&Expr::BinaryOp(
+                    BinaryOp::Div,
+                    Box::new(Expr::Number(Real::from(1))),
+                    Box::new(expand(&power)),
+                ))]
+            } else {
+                vec![simplify(&Expr::BinaryOp(
+                    BinaryOp::Pow,
+                    Box::new(sum(terms)),
+                    Box::new(expand(exp)),
+                ))]
+            }
         }
+
+        Expr::Function(name, args) => vec![simplify(&Expr::Function(
+            name.clone(),
+            args.iter().map(expand).collect(),
+        ))],
+        Expr::List(items) => vec![Expr::List(items.iter().map(expand).collect())],
+
         _ => vec![expr.clone()],
     }
 }
 
-fn flatten_mul(expr: &Expr) -> Vec<Expr> {
+/// Every product of a term of `a` with a term of `b`, with like terms
+/// combined, or the product of the two sums left as it is when there are
+/// more than `MAX_EXPANDED_TERMS` of them.
+fn distribute(a: &[Expr], b: &[Expr]) -> Vec<Expr> {
+    if a.len() as f64 * b.len() as f64 > MAX_EXPANDED_TERMS {
+        return vec![simplify(&Expr::BinaryOp(
+            BinaryOp::Mul,
+            Box::new(sum(a.to_vec())),
+            Box::new(sum(b.to_vec())),
+        ))];
+    }
+    let products = a
+        .iter()
+        .flat_map(|x| {
+            b.iter().map(|y| {
+                simplify(&Expr::BinaryOp(
+                    BinaryOp::Mul,
+                    Box::new(x.clone()),
+                    Box::new(y.clone()),
+                ))
+            })
+        })
+        .collect();
+    flatten_add(&sum(products))
+}
+
+/// Expansions with more terms than this, such as `(a + b + c + d)^100`,
+/// are left as powers.
+const MAX_EXPANDED_TERMS: f64 = 10_000.0;
+
+/// The number of terms of `(t1 + ... + tm)^n` before like terms combine,
+/// `C(n + m - 1, m - 1)`.
+fn multinomial_len(m: usize, n: i64) -> f64 {
+    (1..m).fold(1.0, |len, k| len * (n + k as i64) as f64 / k as f64)
+}
+
+/// `(t1 + ... + tm)^n` by the multinomial theorem, as the sum over
+/// `k1 + ... + km = n` of `n! / (k1! ... km!) t1^k1 ... tm^km`.
+fn multinomial(terms: &[Expr], n: u32) -> Vec<Expr> {
+    // Each split of the power between the terms, with its coefficient
+    fn splits(m: usize, n: u32) -> Vec<(Real, Vec<u32>)> {
+        match m {
+            0 => vec![],
+            1 => vec![(Real::from(1), vec![n])],
+            _ => binomials(n)
+                .into_iter()
+                .zip(0..)
+                .flat_map(|(binomial, k)| {
+                    splits(m - 1, n - k).into_iter().map(move |(c, mut ks)| {
+                        ks.insert(0, k);
+                        (c * binomial.clone(), ks)
+                    })
+                })
+                .collect(),
+        }
+    }
+    let products = splits(terms.len(), n)
+        .into_iter()
+        .map(|(coefficient, ks)| {
+            let mut factors = vec![Expr::Number(coefficient)];
+            for (t, k) in terms.iter().zip(ks) {
+                if k > 0 {
+                    factors.extend(power_of_product(t, k));
+                }
+            }
+            simplify(&fold_binary_ops(BinaryOp::Mul, factors))
+        })
+        .collect();
+    flatten_add(&sum(products))
+}
+
+/// The factors of `t^k` with the power taken of each factor of `t`, as
+/// `(-2 x)^3 = -8 x^3`.
+fn power_of_product(t: &Expr, k: u32) -> Vec<Expr> {
+    let power = |f: Expr| {
+        Expr::BinaryOp(
+            BinaryOp::Pow,
+            Box::new(f),
+            Box::new(Expr::Number(Real::from(k as i64))),
+        )
+    };
+    flatten_mul(t)
+        .into_iter()
+        .flat_map(|f| match f {
+            Expr::UnaryOp(UnaryOp::Neg, e) => vec![power(Expr::Number(Real::from(-1))), power(*e)],
+            f => vec![power(f)],
+        })
+        .collect()
+}
+
+/// `C(n, 0)` to `C(n, n)`, exactly, each from the one before.
+fn binomials(n: u32) -> Vec<Real> {
+    let mut row = vec![Real::from(1)];
+    for k in 0..n {
+        let next = &row[k as usize] * &Real::from((n - k) as i64) / Real::from((k + 1) as i64);
+        row.push(next);
+    }
+    row
+}
+
+/// `terms` added and simplified, or zero when there are none.
+fn sum(terms: Vec<Expr>) -> Expr {
+    if terms.is_empty() {
+        Expr::Number(Real::from(0))
+    } else {
+        simplify(&fold_binary_ops(BinaryOp::Add, terms))
+    }
+}
+
+/// The right-hand side of an equation solved explicitly for `var`, when
+/// it is written as `var = f(...)` (or `f(...) = var`) with `var` not
+/// occurring in `f`.
+pub fn explicit_form<'a>(rel: &'a Relation, var: &str) -> Option<&'a Expr> {
+    if rel.op != RelOp::Eq {
+        return None;
+    }
+    match (&rel.lhs, &rel.rhs) {
+        (Expr::Variable(v), other) | (other, Expr::Variable(v))
+            if v == var && !other.contains_var(var) =>
+        {
+            Some(other)
+        }
+        _ => None,
+    }
+}
+
+/// The `n`th root of `x`, when it is rational.
+pub(crate) fn exact_root(x: &Real, n: &Real) -> Option<Expr> {
+    let root = x.pow(&(Real::from(1) / n.clone()));
+    root.is_exact().then_some(Expr::Number(root))
+}
+
+/// Whether `expr` is built from numbers and real constants by operators
+/// and builtin functions alone.
+fn is_constant(expr: &Expr) -> bool {
     match expr {
+        Expr::Constant(Constant::I) => false,
+        Expr::Number(_) | Expr::Constant(_) => true,
+        Expr::UnaryOp(_, e) => is_constant(e),
+        Expr::BinaryOp(_, a, b) => is_constant(a) && is_constant(b),
+        Expr::List(items) => items.iter().all(is_constant),
+        Expr::Function(name, args) => {
+            function_arity(name).is_some() && args.iter().all(is_constant)
+        }
+        _ => false,
+    }
+}
+
+/// Whether `rel` holds, when both sides are numbers or constants.
+fn constant_truth(rel: &Relation) -> Option<bool> {
+    let value = |e: &Expr| match e {
+        Expr::Number(n) => Some(n.to_f64()),
+        // `i` has no real value to compare
+        Expr::Constant(Constant::I) => None,
+        Expr::Constant(c) => Some(c.value()),
+        _ => None,
+    };
+    Some(rel.op.holds(value(&rel.lhs)?, value(&rel.rhs)?))
+}
+
+/// Apply `f` element-wise when some of `args` are lists, repeating the
+/// others against them and cutting lists to the shortest.
+fn broadcast(args: &[Expr], f: impl Fn(Vec<Expr>) -> Expr) -> Option<Expr> {
+    let len = args
+        .iter()
+        .filter_map(|a| match a {
+            Expr::List(items) => Some(items.len()),
+            _ => None,
+        })
+        .min()?;
+    let nth = |i: usize| -> Vec<Expr> {
+        args.iter()
+            .map(|a| match a {
+                Expr::List(items) => items[i].clone(),
+                other => other.clone(),
+            })
+            .collect()
+    };
+    Some(Expr::List((0..len).map(|i| f(nth(i))).collect()))
+}
+
+/// `expr` as a rational multiple of pi, if it has that shape.
+pub(crate) fn pi_multiple(expr: &Expr) -> Option<Real> {
+    match expr {
+        Expr::Number(n) if n.is_zero() => Some(Real::from(0)),
+        Expr::Constant(Constant::Pi) => Some(Real::from(1)),
+        Expr::Constant(Constant::Tau) => Some(Real::from(2)),
+        Expr::UnaryOp(UnaryOp::Neg, e) => pi_multiple(e).map(|k| -k),
+        Expr::BinaryOp(BinaryOp::Mul, a, b) => match (&**a, &**b) {
+            (Expr::Number(k), other) | (other, Expr::Number(k)) => {
+                pi_multiple(other).map(|m| &m * k)
+            }
+            _ => None,
+        },
+        Expr::BinaryOp(BinaryOp::Div, a, b) => match &**b {
+            Expr::Number(n) if !n.is_zero() => pi_multiple(a).map(|m| &m / n),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Largest whole power of a complex number, such as `(1 + i)^8`, multiplied
+/// out exactly.
+const MAX_COMPLEX_POWER: u32 = 64;
+
+/// `a op b` in the form `x + y i`, when `a` and `b` are complex numbers and
+/// not both real, as `(2 + 3i) / (1 - i) = -1/2 + 5/2 i`.
+fn complex_arithmetic(op: &BinaryOp, a: &Expr, b: &Expr) -> Option<Expr> {
+    let (z, w) = (complex_number(a)?, complex_number(b)?);
+    if z.1.is_zero() && w.1.is_zero() {
+        return None;
+    }
+    let (re, im) = match op {
+        BinaryOp::Add => (z.0 + w.0, z.1 + w.1),
+        BinaryOp::Sub => (z.0 - w.0, z.1 - w.1),
+        BinaryOp::Mul => complex_mul(&z, &w),
+        BinaryOp::Div => complex_div(&z, &w)?,
+        BinaryOp::Pow if w.1.is_zero() => {
+            let n =
+                w.0.to_i64()
+                    .filter(|n| n.unsigned_abs() <= MAX_COMPLEX_POWER as u64)?;
+            let one = (Real::from(1), Real::from(0));
+            let power = (0..n.unsigned_abs()).fold(one.clone(), |p, _| complex_mul(&p, &z));
+            if n < 0 {
+                complex_div(&one, &power)?
+            } else {
+                power
+            }
+        }
+        BinaryOp::Pow => return None,
+    };
+    Some(complex_expr(re, im))
+}
+
+/// `(re, im)` when `expr` is a complex number built from numbers and `i` by
+/// arithmetic.
+fn complex_number(expr: &Expr) -> Option<(Real, Real)> {
+    match expr {
+        Expr::Number(n) => Some((n.clone(), Real::from(0))),
+        Expr::Constant(Constant::I) => Some((Real::from(0), Real::from(1))),
+        Expr::UnaryOp(UnaryOp::Neg, e) => {
+            let (re, im) = complex_number(e)?;
+            Some((-re, -im))
+        }
+        Expr::BinaryOp(op @ (BinaryOp::Add | BinaryOp::Sub), a, b) => {
+            let (z, w) = (complex_number(a)?, complex_number(b)?);
+            Some(match op {
+                BinaryOp::Add => (z.0 + w.0, z.1 + w.1),
+                _ => (z.0 - w.0, z.1 - w.1),
+            })
+        }
         Expr::BinaryOp(BinaryOp::Mul, a, b) => {
-            let mut v = flatten_mul(a);
-            v.extend(flatten_mul(b));
-            v
+            Some(complex_mul(&complex_number(a)?, &complex_number(b)?))
         }
-        _ => vec![expr.clone()],
+        Expr::BinaryOp(BinaryOp::Div, a, b) => {
+            complex_div(&complex_number(a)?, &complex_number(b)?)
+        }
+        _ => None,
+    }
+}
+
+fn complex_mul(z: &(Real, Real), w: &(Real, Real)) -> (Real, Real) {
+    (
+        z.0.clone() * w.0.clone() - z.1.clone() * w.1.clone(),
+        z.0.clone() * w.1.clone() + z.1.clone() * w.0.clone(),
+    )
+}
+
+/// `z / w`, multiplying through by the conjugate of `w`.
+fn complex_div(z: &(Real, Real), w: &(Real, Real)) -> Option<(Real, Real)> {
+    let norm = w.0.clone() * w.0.clone() + w.1.clone() * w.1.clone();
+    if norm.is_zero() {
+        return None;
+    }
+    let (re, im) = complex_mul(z, &(w.0.clone(), -w.1.clone()));
+    Some((re / norm.clone(), im / norm))
+}
+
+/// `re + im i` as simplify writes it, with `i`, `-i` and zero parts
+/// written the short way.
+fn complex_expr(re: Real, im: Real) -> Expr {
+    let i = Expr::Constant(Constant::I);
+    let imaginary = if im.is_one() {
+        i
+    } else if (-im.clone()).is_one() {
+        Expr::UnaryOp(UnaryOp::Neg, Box::new(i))
+    } else {
+        Expr::BinaryOp(
+            BinaryOp::Mul,
+            Box::new(Expr::Number(im.clone())),
+            Box::new(i),
+        )
+    };
+    match (re.is_zero(), im.is_zero()) {
+        (_, true) => Expr::Number(re),
+        (true, false) => imaginary,
+        (false, false) => Expr::BinaryOp(
+            BinaryOp::Add,
+            Box::new(Expr::Number(re)),
+            Box::new(imaginary),
+        ),
+    }
+}
+
+/// `b` when `expr` is `b i` with `b` real, as far as it shows.
+pub(crate) fn imaginary_coefficient(expr: &Expr) -> Option<Expr> {
+    let i = Expr::Constant(Constant::I);
+    match expr {
+        Expr::UnaryOp(UnaryOp::Neg, e) => Some(simplify(&Expr::UnaryOp(
+            UnaryOp::Neg,
+            Box::new(imaginary_coefficient(e)?),
+        ))),
+        Expr::BinaryOp(BinaryOp::Div, a, b) if !b.contains_constant(Constant::I) => {
+            Some(simplify(&Expr::BinaryOp(
+                BinaryOp::Div,
+                Box::new(imaginary_coefficient(a)?),
+                b.clone(),
+            )))
+        }
+        _ => {
+            // One factor of i or -i
+            let minus_i = Expr::UnaryOp(UnaryOp::Neg, Box::new(i.clone()));
+            let mut factors = flatten_mul(expr);
+            let at = factors.iter().position(|f| *f == i || *f == minus_i)?;
+            let negative = factors.remove(at) == minus_i;
+            if factors.iter().any(|f| f.contains_constant(Constant::I)) {
+                return None;
+            }
+            let b = if factors.is_empty() {
+                Expr::Number(Real::from(1))
+            } else {
+                fold_binary_ops(BinaryOp::Mul, factors)
+            };
+            Some(if negative {
+                simplify(&Expr::UnaryOp(UnaryOp::Neg, Box::new(b)))
+            } else {
+                b
+            })
+        }
+    }
+}
+
+/// The canonical order of the terms of a sum: by descending total degree,
+/// then by the powers of the variables in alphabetical order, and constant
+/// terms last of their degree, so `y + x^2 + 1 + x y` is
+/// `x^2 + x y + y + 1` (see `term_rank`).
+fn sort_terms(terms: &mut Vec<Expr>) {
+    // The powers of each term, and their total, are found once rather than
+    // at every comparison
+    let mut keyed: Vec<(Real, BTreeMap<String, Real>, Expr)> = terms
+        .drain(..)
+        .map(|t| {
+            let p = powers(&t);
+            (p.values().fold(Real::from(0), |d, n| d + n.clone()), p, t)
+        })
+        .collect();
+    keyed.sort_by(|(da, pa, a), (db, pb, b)| db.cmp(da).then_with(|| term_order(a, pa, b, pb)));
+    terms.extend(keyed.into_iter().map(|(_, _, t)| t));
+}
+
+/// The order of two terms of the same degree with the powers `pa` and
+/// `pb`.
+fn term_order(
+    a: &Expr,
+    pa: &BTreeMap<String, Real>,
+    b: &Expr,
+    pb: &BTreeMap<String, Real>,
+) -> Ordering {
+    let lex = || {
+        let zero = Real::from(0);
+        pa.keys()
+            .chain(pb.keys())
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .map(|v| {
+                let exp = |p: &BTreeMap<String, Real>| p.get(v).unwrap_or(&zero).clone();
+                exp(pb).cmp(&exp(pa))
+            })
+            .find(|o| o.is_ne())
+            .unwrap_or(Ordering::Equal)
+    };
+    let monomial = |e: &Expr| extract_coefficient(e).map(|(_, base)| base);
+    lex()
+        .then_with(|| term_rank(a).cmp(&term_rank(b)))
+        .then_with(|| monomial(a).cmp(&monomial(b)))
+        .then_with(|| a.cmp(b))
+}
+
+/// Terms of the same degree come in the order: those with variables, real
+/// constants such as `pi`, the number, and last the imaginary part.
+fn term_rank(term: &Expr) -> u8 {
+    match term {
+        Expr::Number(_) => 2,
+        _ if is_constant(term) => 1,
+        _ if imaginary_coefficient(term).is_some_and(|b| is_constant(&b)) => 3,
+        _ => 0,
+    }
+}
+
+/// The power of each variable in a term, as `3 x^2 y` has `x^2` and `y`.
+/// Other factors, such as `sin(x)`, count as constants.
+fn powers(expr: &Expr) -> BTreeMap<String, Real> {
+    let merge = |mut a: BTreeMap<String, Real>, b: BTreeMap<String, Real>, sign: &Real| {
+        for (v, n) in b {
+            let total = a.get(&v).cloned().unwrap_or(Real::from(0)) + sign * &n;
+            a.insert(v, total);
+        }
+        a
+    };
+    match expr {
+        Expr::Variable(name) => BTreeMap::from([(name.clone(), Real::from(1))]),
+        Expr::UnaryOp(UnaryOp::Neg, e) => powers(e),
+        Expr::BinaryOp(BinaryOp::Mul, a, b) => merge(powers(a), powers(b), &Real::from(1)),
+        Expr::BinaryOp(BinaryOp::Div, a, b) => merge(powers(a), powers(b), &Real::from(-1)),
+        Expr::BinaryOp(BinaryOp::Pow, base, exp) => match &**exp {
+            Expr::Number(n) => merge(BTreeMap::new(), powers(base), n),
+            _ => BTreeMap::new(),
+        },
+        _ => BTreeMap::new(),
+    }
+}
+
+/// The canonical order of the factors of a product: numbers, constants,
+/// variables and their powers by name, `i`, then everything else, so
+/// `y * 2 * x^2 * x` is `2 x x^2 y` and the imaginary part of `a + bi`
+/// ends with `i`.
+fn factor_order(a: &Expr, b: &Expr) -> Ordering {
+    let split = |e: &Expr| -> (u8, Expr, Option<Expr>) {
+        let (base, exp) = match e {
+            Expr::BinaryOp(BinaryOp::Pow, base, exp) => ((**base).clone(), Some((**exp).clone())),
+            _ => (e.clone(), None),
+        };
+        let rank = match base {
+            Expr::Number(_) => 0,
+            Expr::Constant(Constant::I) => 3,
+            Expr::Constant(_) => 1,
+            Expr::Variable(_) => 2,
+            _ => 4,
+        };
+        (rank, base, exp)
+    };
+    split(a).cmp(&split(b))
+}
+
+/// `expr` as a base and exponent, with `x` as `x^1`.
+fn split_power(expr: &Expr) -> (Expr, Expr) {
+    match expr {
+        Expr::BinaryOp(BinaryOp::Pow, base, exp) => ((**base).clone(), (**exp).clone()),
+        _ => (expr.clone(), Expr::Number(Real::from(1))),
     }
 }
 
-fn partition_consts(terms: Vec<Expr>) -> (OrderedFloat<f64>, Vec<Expr>) {
-    let mut sum_consts = OrderedFloat(0.0);
+/// Whether `u^a` for each of `exps` may be replaced by `u^(sum of exps)`,
+/// which is `u^(a b)` when `exps` is `[a]` and the second factor `scale`
+/// is `b`.
+///
+/// The rule is that both sides agree at every real `u` but zero, with
+/// powers of negatives taken as the complex principal values `evaluate`
+/// gives. So `x^a x^b = x^(a + b)` for any `a` and `b`, and
+/// `x^(1/2) x^(1/2)` is `x`, while `(u^a)^b = u^(a b)` needs a whole `b`,
+/// or a number `a` in (-1, 1] that keeps `u^a` on the principal branch:
+/// `(x^3)^(1/3)` stays. At zero, a product exponent of zero makes
+/// `x/x = 1` as well, as `0^0 = 1` does.
+fn combinable(exps: &[Expr], scale: &Expr) -> bool {
+    let Expr::Number(scale) = scale else {
+        return false;
+    };
+    if scale.is_integer() {
+        return true;
+    }
+    match exps {
+        [Expr::Number(a)] => *a > Real::from(-1) && *a <= Real::from(1),
+        _ => false,
+    }
+}
+
+fn is_even(expr: &Expr) -> bool {
+    matches!(expr, Expr::Number(n) if n.to_i64().is_some_and(|k| k % 2 == 0))
+}
+
+/// Whether `expr` is a constant known to be positive, such as `2` or `e`.
+fn is_positive(expr: &Expr) -> bool {
+    is_constant(expr) && evaluate(expr) > 0.0
+}
+
+/// Merge the factors of a product that are powers of the same base.
+fn collect_powers(factors: Vec<Expr>) -> Vec<Expr> {
+    let mut groups: Vec<(Expr, Vec<Expr>, Vec<Expr>)> = Vec::new();
+    for factor in factors {
+        let (base, exp) = split_power(&factor);
+        match groups.iter_mut().find(|(b, _, _)| *b == base) {
+            Some((_, exps, originals)) => {
+                exps.push(exp);
+                originals.push(factor);
+            }
+            None => groups.push((base, vec![exp], vec![factor])),
+        }
+    }
+    groups
+        .into_iter()
+        .flat_map(|(base, exps, originals)| {
+            if exps.len() > 1 && combinable(&exps, &Expr::Number(Real::from(1))) {
+                vec![simplify(&Expr::BinaryOp(
+                    BinaryOp::Pow,
+                    Box::new(base),
+                    Box::new(fold_binary_ops(BinaryOp::Add, exps)),
+                ))]
+            } else {
+                originals
+            }
+        })
+        .collect()
+}
+
+/// `num / den` with the powers of bases common to both cancelled, as
+/// `x^3 / (2 x) = x^2 / 2`, or `None` if there are none.
+fn cancel_powers(num: &Expr, den: &Expr) -> Option<Expr> {
+    let mut negative = false;
+    let mut unsigned = |e: &Expr| -> Vec<Expr> {
+        flatten_mul(e)
+            .into_iter()
+            .map(|mut f| {
+                while let Expr::UnaryOp(UnaryOp::Neg, inner) = f {
+                    f = *inner;
+                    negative = !negative;
+                }
+                f
+            })
+            .collect()
+    };
+    let (mut top, mut bottom) = (unsigned(num), unsigned(den));
+    let mut changed = false;
+
+    // Numeric factors reduce to lowest terms, as `2 x / 4 = x / 2`
+    let rational = |f: &Expr| matches!(f, Expr::Number(Real::Rational(_)));
+    if let (Some(i), Some(j)) = (
+        top.iter().position(rational),
+        bottom.iter().position(rational),
+    ) {
+        if let (Expr::Number(a), Expr::Number(b)) = (&top[i], &bottom[j]) {
+            // A zero denominator gives a float, and is left alone
+            if let Real::Rational(ratio) = a / b {
+                let denom = Real::Rational(ratio.denom().clone().into());
+                if denom != *b {
+                    top[i] = Expr::Number(Real::Rational(ratio.numer().clone().into()));
+                    bottom[j] = Expr::Number(denom);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    bottom.retain_mut(|d| {
+        let (base, e) = split_power(d);
+        let Some(t) = top.iter_mut().find(|t| split_power(t).0 == base) else {
+            return true;
+        };
+        let en = split_power(t).1;
+        let minus_e = Expr::UnaryOp(UnaryOp::Neg, Box::new(e.clone()));
+        if !combinable(
+            &[en.clone(), simplify(&minus_e)],
+            &Expr::Number(Real::from(1)),
+        ) {
+            return true;
+        }
+        changed = true;
+        let difference = simplify(&Expr::BinaryOp(BinaryOp::Sub, Box::new(en), Box::new(e)));
+        let power = |exp: Expr| {
+            simplify(&Expr::BinaryOp(
+                BinaryOp::Pow,
+                Box::new(base.clone()),
+                Box::new(exp),
+            ))
+        };
+        match &difference {
+            // The larger power stays on the side it was on
+            Expr::Number(n) if n.is_negative() => {
+                *t = Expr::Number(Real::from(1));
+                *d = power(Expr::Number(-n));
+                true
+            }
+            _ => {
+                *t = power(difference);
+                false
+            }
+        }
+    });
+    if !changed {
+        return None;
+    }
+    let product = |factors: Vec<Expr>| {
+        if factors.is_empty() {
+            Expr::Number(Real::from(1))
+        } else {
+            fold_binary_ops(BinaryOp::Mul, factors)
+        }
+    };
+    let quotient = simplify(&Expr::BinaryOp(
+        BinaryOp::Div,
+        Box::new(simplify(&product(top))),
+        Box::new(simplify(&product(bottom))),
+    ));
+    Some(if negative {
+        simplify(&Expr::UnaryOp(UnaryOp::Neg, Box::new(quotient)))
+    } else {
+        quotient
+    })
+}
+
+fn flatten_add(expr: &Expr) -> Vec<Expr> {
+    flatten(expr, BinaryOp::Add)
+}
+
+fn flatten_mul(expr: &Expr) -> Vec<Expr> {
+    flatten(expr, BinaryOp::Mul)
+}
+
+/// The operands of a chain of `op`, left to right. A loop rather than
+/// recursion, as an expanded sum may be thousands of terms deep.
+fn flatten(expr: &Expr, op: BinaryOp) -> Vec<Expr> {
+    let mut operands = Vec::new();
+    let mut pending = vec![expr];
+    while let Some(e) = pending.pop() {
+        match e {
+            Expr::BinaryOp(o, a, b) if *o == op => {
+                pending.push(b);
+                pending.push(a);
+            }
+            _ => operands.push(e.clone()),
+        }
+    }
+    operands
+}
+
+fn partition_consts(terms: Vec<Expr>) -> (Real, Vec<Expr>) {
+    let mut sum_consts = Real::from(0);
     let mut non_consts = Vec::new();
     for term in terms {
         if let Expr::Number(n) = term {
-            sum_consts += n;
+            sum_consts = sum_consts + n;
         } else {
             non_consts.push(term);
         }
@@ -226,12 +1172,12 @@ fn partition_consts(terms: Vec<Expr>) -> (OrderedFloat<f64>, Vec<Expr>) {
     (sum_consts, non_consts)
 }
 
-fn partition_consts_mul(factors: Vec<Expr>) -> (OrderedFloat<f64>, Vec<Expr>) {
-    let mut prod_consts = OrderedFloat(1.0);
+fn partition_consts_mul(factors: Vec<Expr>) -> (Real, Vec<Expr>) {
+    let mut prod_consts = Real::from(1);
     let mut non_consts = Vec::new();
     for factor in factors {
         if let Expr::Number(n) = factor {
-            prod_consts *= n;
+            prod_consts = prod_consts * n;
         } else {
             non_consts.push(factor);
         }
@@ -239,14 +1185,13 @@ fn partition_consts_mul(factors: Vec<Expr>) -> (OrderedFloat<f64>, Vec<Expr>) {
     (prod_consts, non_consts)
 }
 
-fn fold_binary_ops(op: BinaryOp, mut exprs: Vec<Expr>) -> Expr {
-    if exprs.len() == 1 {
-        exprs.pop().unwrap()
-    } else {
-        let right = exprs.pop().unwrap();
-        let left = fold_binary_ops(op.clone(), exprs);
-        Expr::BinaryOp(op, Box::new(left), Box::new(right))
-    }
+/// `exprs` joined by `op` from the left, as `(a + b) + c`.
+pub(crate) fn fold_binary_ops(op: BinaryOp, exprs: Vec<Expr>) -> Expr {
+    let mut exprs = exprs.into_iter();
+    let first = exprs.next().unwrap();
+    exprs.fold(first, |left, right| {
+        Expr::BinaryOp(op.clone(), Box::new(left), Box::new(right))
+    })
 }
 
 fn normalize_double_neg(expr: &Expr) -> Expr {
@@ -267,10 +1212,10 @@ fn normalize_double_neg(expr: &Expr) -> Expr {
     }
 }
 
-fn extract_coefficient(expr: &Expr) -> Option<(OrderedFloat<f64>, Expr)> {
+fn extract_coefficient(expr: &Expr) -> Option<(Real, Expr)> {
     let expr = normalize_double_neg(expr);
     match expr {
-        Expr::Number(n) => Some((n, Expr::Number(OrderedFloat(1.0)))),
+        Expr::Number(n) => Some((n, Expr::Number(Real::from(1)))),
 
         Expr::UnaryOp(UnaryOp::Neg, inner) => {
             extract_coefficient(&inner).map(|(coef, base)| (-coef, base))
@@ -283,9 +1228,9 @@ fn extract_coefficient(expr: &Expr) -> Option<(OrderedFloat<f64>, Expr)> {
             match (left_coef, right_coef) {
                 (Some((lc, lb)), Some((rc, rb))) => {
                     let combined_coef = lc * rc;
-                    let combined_base = if lb == Expr::Number(OrderedFloat(1.0)) {
+                    let combined_base = if lb == Expr::Number(Real::from(1)) {
                         rb
-                    } else if rb == Expr::Number(OrderedFloat(1.0)) {
+                    } else if rb == Expr::Number(Real::from(1)) {
                         lb
                     } else {
                         Expr::BinaryOp(BinaryOp::Mul, Box::new(lb), Box::new(rb))
@@ -293,7 +1238,7 @@ fn extract_coefficient(expr: &Expr) -> Option<(OrderedFloat<f64>, Expr)> {
                     Some((combined_coef, combined_base))
                 }
                 (Some((lc, lb)), None) => {
-                    let combined_base = if lb == Expr::Number(OrderedFloat(1.0)) {
+                    let combined_base = if lb == Expr::Number(Real::from(1)) {
                         (*right).clone()
                     } else {
                         Expr::BinaryOp(BinaryOp::Mul, Box::new(lb), Box::new((*right).clone()))
@@ -301,7 +1246,7 @@ fn extract_coefficient(expr: &Expr) -> Option<(OrderedFloat<f64>, Expr)> {
                     Some((lc, combined_base))
                 }
                 (None, Some((rc, rb))) => {
-                    let combined_base = if rb == Expr::Number(OrderedFloat(1.0)) {
+                    let combined_base = if rb == Expr::Number(Real::from(1)) {
                         (*left).clone()
                     } else {
                         Expr::BinaryOp(BinaryOp::Mul, Box::new((*left).clone()), Box::new(rb))
@@ -312,48 +1257,89 @@ fn extract_coefficient(expr: &Expr) -> Option<(OrderedFloat<f64>, Expr)> {
             }
         }
 
-        _ => None,
+        // `x/2` is `1/2 x`, `2/x` is `2 x^-1` and `3y/(2x)` is `3/2 y/x`
+        Expr::BinaryOp(BinaryOp::Div, num, den) => {
+            let (num_coef, num_base) = extract_coefficient(&num)?;
+            let (den_coef, den_base) = extract_coefficient(&den)?;
+            if den_coef.is_zero() {
+                return None;
+            }
+            let one = Expr::Number(Real::from(1));
+            let base = if den_base == one {
+                num_base
+            } else if num_base == one {
+                Expr::BinaryOp(
+                    BinaryOp::Pow,
+                    Box::new(den_base),
+                    Box::new(Expr::Number(-Real::from(1))),
+                )
+            } else {
+                Expr::BinaryOp(BinaryOp::Div, Box::new(num_base), Box::new(den_base))
+            };
+            Some((num_coef / den_coef, base))
+        }
+
+        other => Some((Real::from(1), other)),
+    }
+}
+
+/// `coef` times `base`, undoing `extract_coefficient`: a reciprocal or
+/// quotient base keeps the coefficient in its numerator, as in `5/x`.
+fn with_coefficient(coef: &Real, base: Expr) -> Expr {
+    match base {
+        Expr::BinaryOp(BinaryOp::Pow, den, exp) if *exp == Expr::Number(-Real::from(1)) => {
+            Expr::BinaryOp(BinaryOp::Div, Box::new(Expr::Number(coef.clone())), den)
+        }
+        Expr::BinaryOp(BinaryOp::Div, num, den) => {
+            Expr::BinaryOp(BinaryOp::Div, Box::new(with_coefficient(coef, *num)), den)
+        }
+        base if coef.is_one() => base,
+        base if coef.to_i64() == Some(-1) => Expr::UnaryOp(UnaryOp::Neg, Box::new(base)),
+        base => {
+            let mut factors = vec![Expr::Number(coef.clone())];
+            factors.extend(flatten_mul(&base));
+            fold_binary_ops(BinaryOp::Mul, factors)
+        }
     }
 }
 
 fn combine_like_terms(terms: Vec<Expr>) -> Vec<Expr> {
     use std::collections::HashMap;
 
-    let mut counts: HashMap<Expr, OrderedFloat<f64>> = HashMap::new();
-    let mut order: Vec<Expr> = Vec::new();
+    // The summed coefficient of each base and how many terms share it
+    let mut counts: HashMap<Expr, (Real, usize)> = HashMap::new();
+    let mut order: Vec<(Expr, &Expr)> = Vec::new();
 
     for term in &terms {
-        if let Some((coef, base)) = extract_coefficient(term) {
-            if !counts.contains_key(&base) {
-                order.push(base.clone());
+        let (coef, base) =
+            extract_coefficient(term).unwrap_or_else(|| (Real::from(1), term.clone()));
+        match counts.get_mut(&base) {
+            Some(count) => {
+                count.0 = &count.0 + &coef;
+                count.1 += 1;
             }
-            *counts.entry(base).or_insert(OrderedFloat(0.0)) += coef;
-        } else {
-            if !counts.contains_key(term) {
-                order.push(term.clone());
+            None => {
+                order.push((base.clone(), term));
+                counts.insert(base, (coef, 1));
             }
-            *counts.entry(term.clone()).or_insert(OrderedFloat(0.0)) += OrderedFloat(1.0);
         }
     }
 
     let mut combined = Vec::new();
     let mut const_terms = Vec::new();
 
-    for base in order {
-        let coef = counts.get(&base).unwrap();
-        if coef.0.abs() < 1e-12 {
+    for (base, term) in order {
+        let (coef, shared) = counts.get(&base).unwrap();
+        if coef.is_zero() {
             continue;
         }
-        if base == Expr::Number(OrderedFloat(1.0)) {
-            const_terms.push(Expr::Number(*coef));
-        } else if *coef == OrderedFloat(1.0) {
-            combined.push(base);
+        if base == Expr::Number(Real::from(1)) {
+            const_terms.push(Expr::Number(coef.clone()));
+        } else if *shared == 1 {
+            // Nothing to combine, so the term keeps its own form
+            combined.push(term.clone());
         } else {
-            combined.push(Expr::BinaryOp(
-                BinaryOp::Mul,
-                Box::new(Expr::Number(*coef)),
-                Box::new(base),
-            ));
+            combined.push(with_coefficient(coef, base));
         }
     }
 
@@ -1,20 +1,110 @@
-use ordered_float::OrderedFloat;
+use crate::math::diff::differentiate;
+use crate::math::functions;
+pub use crate::math::number::Real;
+use std::collections::HashMap;
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+/// Expressions are totally ordered, variant by variant in the order below
+/// and then by their contents, which gives sums and products a canonical
+/// order of operands (see `algebra::simplify`).
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub enum Expr {
-    Number(OrderedFloat<f64>),
+    /// An exact rational, or a float after inexact evaluation.
+    Number(Real),
     Variable(String),
+    Constant(Constant),
     UnaryOp(UnaryOp, Box<Expr>),
     BinaryOp(BinaryOp, Box<Expr>, Box<Expr>),
-    Function(String, Box<Expr>),
+    /// A call by name. Names are not a fixed enum: built-in and registered
+    /// functions are looked up in `functions`, and any other name is a
+    /// user function.
+    Function(String, Vec<Expr>),
+    /// `[a, b, c]`; arithmetic and functions apply element-wise.
+    List(Vec<Expr>),
+    /// `a if c else b`: the value of the first branch whose condition
+    /// holds, else the fallback (undefined when there is none).
+    Piecewise(Vec<(Expr, Relation)>, Option<Box<Expr>>),
+    /// Where the recovering parser found nothing usable; evaluates to NaN.
+    Hole(Hole),
+    /// The `n`th derivative with respect to a variable, written `d/dx f`,
+    /// `d^2/dx^2 f` or `diff(f, x, 2)`.
+    Derivative(Box<Expr>, String, u32),
+    /// An integral with respect to a variable, written `int(f, x)`, or with
+    /// bounds (in which the variable is bound) `int(f, x, a, b)`.
+    Integral(Box<Expr>, String, Option<Box<(Expr, Expr)>>),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// A gap left in a partial parse, see `parser::parse_recovering`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Hole {
+    /// An operand that has not been typed yet, as after `x^`.
+    Missing,
+    /// Input that could not be read, such as a stray `#` or `1.2.3`.
+    Error,
+}
+
+/// Named mathematical constants, kept symbolic until numeric evaluation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Constant {
+    Pi,
+    E,
+    /// 2π
+    Tau,
+    /// The golden ratio
+    Phi,
+    Infinity,
+    /// The imaginary unit, `i^2 = -1`
+    I,
+}
+
+impl Constant {
+    /// The value as a real number, NaN for `i`, which evaluates to a
+    /// complex number instead.
+    pub fn value(&self) -> f64 {
+        match self {
+            Constant::Pi => std::f64::consts::PI,
+            Constant::E => std::f64::consts::E,
+            Constant::Tau => std::f64::consts::TAU,
+            Constant::Phi => (1.0 + 5f64.sqrt()) / 2.0,
+            Constant::Infinity => f64::INFINITY,
+            Constant::I => f64::NAN,
+        }
+    }
+
+    /// Plain-text spelling, as accepted by the parser.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Constant::Pi => "pi",
+            Constant::E => "e",
+            Constant::Tau => "tau",
+            Constant::Phi => "phi",
+            Constant::Infinity => "inf",
+            Constant::I => "i",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Constant> {
+        match name {
+            "pi" => Some(Constant::Pi),
+            "e" => Some(Constant::E),
+            "tau" => Some(Constant::Tau),
+            "phi" => Some(Constant::Phi),
+            "inf" | "infinity" => Some(Constant::Infinity),
+            "i" => Some(Constant::I),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum UnaryOp {
     Neg,
+    /// Postfix `!`, extended to non-integers through the gamma function
+    Factorial,
+    /// Postfix `%`, dividing by 100
+    Percent,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum BinaryOp {
     Add,
     Sub,
@@ -22,3 +112,450 @@ pub enum BinaryOp {
     Div,
     Pow,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assoc {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OperatorInfo {
+    pub op: BinaryOp,
+    pub symbol: char,
+    pub precedence: u8,
+    pub assoc: Assoc,
+}
+
+/// Binary operator precedence table shared by the parser and the formatters,
+/// from loosest to tightest binding:
+///
+/// | Operators                            | Precedence | Associativity |
+/// |--------------------------------------|-----------:|---------------|
+/// | `+` `-`                              | 1          | left          |
+/// | `*` `/` and implicit multiplication  | 2          | left          |
+/// | prefix `-` (see [`NEG_PRECEDENCE`])  | 3          | -             |
+/// | `^`                                  | 4          | right         |
+/// | postfix `!` `%`                      | 5          | -             |
+///
+/// Prefix minus binds looser than `^`, so `-x^2` is `-(x^2)`, while the
+/// right operand of `^` may itself start with a minus, as in `2^-1`.
+pub const OPERATORS: [OperatorInfo; 5] = [
+    OperatorInfo {
+        op: BinaryOp::Add,
+        symbol: '+',
+        precedence: 1,
+        assoc: Assoc::Left,
+    },
+    OperatorInfo {
+        op: BinaryOp::Sub,
+        symbol: '-',
+        precedence: 1,
+        assoc: Assoc::Left,
+    },
+    OperatorInfo {
+        op: BinaryOp::Mul,
+        symbol: '*',
+        precedence: 2,
+        assoc: Assoc::Left,
+    },
+    OperatorInfo {
+        op: BinaryOp::Div,
+        symbol: '/',
+        precedence: 2,
+        assoc: Assoc::Left,
+    },
+    OperatorInfo {
+        op: BinaryOp::Pow,
+        symbol: '^',
+        precedence: 4,
+        assoc: Assoc::Right,
+    },
+];
+
+/// Precedence of a range `a..b` and a conditional `a if c else b`, looser
+/// than every operator, so `1..n+1` is `1..(n+1)`.
+pub const RANGE_PRECEDENCE: u8 = 0;
+
+/// Precedence of prefix negation.
+pub const NEG_PRECEDENCE: u8 = 3;
+
+/// Precedence of the postfix operators `!` and `%`, which apply to the
+/// atom before them, so `2^3!` is `2^(3!)`.
+pub const POSTFIX_PRECEDENCE: u8 = 5;
+
+/// Precedence of anything that is not an operator application
+/// (numbers, variables, function calls).
+pub const ATOM_PRECEDENCE: u8 = u8::MAX;
+
+impl BinaryOp {
+    pub fn info(&self) -> &'static OperatorInfo {
+        OPERATORS.iter().find(|info| info.op == *self).unwrap()
+    }
+
+    pub fn precedence(&self) -> u8 {
+        self.info().precedence
+    }
+
+    pub fn assoc(&self) -> Assoc {
+        self.info().assoc
+    }
+
+    pub fn symbol(&self) -> char {
+        self.info().symbol
+    }
+
+    pub fn from_symbol(c: char) -> Option<BinaryOp> {
+        OPERATORS
+            .iter()
+            .find(|info| info.symbol == c)
+            .map(|info| info.op.clone())
+    }
+}
+
+impl Expr {
+    /// Precedence of the outermost operator, with negative number literals
+    /// binding like prefix negation.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Expr::BinaryOp(op, _, _) => op.precedence(),
+            Expr::UnaryOp(UnaryOp::Neg, _) => NEG_PRECEDENCE,
+            Expr::UnaryOp(_, _) => POSTFIX_PRECEDENCE,
+            Expr::Number(n) if n.is_negative() => NEG_PRECEDENCE,
+            // Printed as `n/d`
+            Expr::Number(n) if !n.is_terminating() => BinaryOp::Div.precedence(),
+            Expr::Function(name, args) if name == "range" && args.len() == 2 => RANGE_PRECEDENCE,
+            Expr::Piecewise(..) => RANGE_PRECEDENCE,
+            _ => ATOM_PRECEDENCE,
+        }
+    }
+}
+
+/// Comparison operators joining the two sides of a relation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RelOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl RelOp {
+    pub fn holds(&self, lhs: f64, rhs: f64) -> bool {
+        match self {
+            RelOp::Eq => lhs == rhs,
+            RelOp::Ne => lhs != rhs,
+            RelOp::Lt => lhs < rhs,
+            RelOp::Le => lhs <= rhs,
+            RelOp::Gt => lhs > rhs,
+            RelOp::Ge => lhs >= rhs,
+        }
+    }
+
+    /// The operator with its sides swapped, so `a < b` becomes `b > a`.
+    pub fn flip(&self) -> RelOp {
+        match self {
+            RelOp::Lt => RelOp::Gt,
+            RelOp::Le => RelOp::Ge,
+            RelOp::Gt => RelOp::Lt,
+            RelOp::Ge => RelOp::Le,
+            op => *op,
+        }
+    }
+
+    /// Plain-text spelling, as accepted by the parser.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            RelOp::Eq => "=",
+            RelOp::Ne => "!=",
+            RelOp::Lt => "<",
+            RelOp::Le => "<=",
+            RelOp::Gt => ">",
+            RelOp::Ge => ">=",
+        }
+    }
+}
+
+/// An equation or inequality such as `y = x^2` or `x > 3`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Relation {
+    pub op: RelOp,
+    pub lhs: Expr,
+    pub rhs: Expr,
+}
+
+/// A complete line of input.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Statement {
+    Expr(Expr),
+    Relation(Relation),
+    /// Variable assignment, `a = 3`
+    Assign(String, Expr),
+    /// User function definition, `f(x, y) = x^2 + y`
+    Define(String, Vec<String>, Expr),
+}
+
+impl Relation {
+    /// The relation with `f` applied to both sides.
+    pub fn map(&self, mut f: impl FnMut(&Expr) -> Expr) -> Relation {
+        Relation {
+            op: self.op,
+            lhs: f(&self.lhs),
+            rhs: f(&self.rhs),
+        }
+    }
+}
+
+impl Statement {
+    /// Classify `lhs = rhs`.
+    ///
+    /// `f(x, y) = ...` with distinct variable parameters defines a function
+    /// (unless `f` is a built-in), and `a = ...` assigns a variable when `a`
+    /// is not one of the plotting variables `x`/`y` and does not occur on
+    /// the right. Anything else is an equation.
+    pub fn equation(lhs: Expr, rhs: Expr) -> Statement {
+        match &lhs {
+            Expr::Function(name, args) if function_arity(name).is_none() => {
+                let params: Vec<String> = args
+                    .iter()
+                    .filter_map(|a| match a {
+                        Expr::Variable(v) => Some(v.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                let distinct = params
+                    .iter()
+                    .enumerate()
+                    .all(|(i, p)| !params[..i].contains(p));
+                if params.len() == args.len() && distinct && !name.ends_with('\'') {
+                    return Statement::Define(name.clone(), params, rhs);
+                }
+            }
+            Expr::Variable(name) if name != "x" && name != "y" && !rhs.contains_var(name) => {
+                return Statement::Assign(name.clone(), rhs);
+            }
+            _ => {}
+        }
+        Statement::Relation(Relation {
+            op: RelOp::Eq,
+            lhs,
+            rhs,
+        })
+    }
+}
+
+impl Expr {
+    /// Replace every variable named in `bindings` at once, so swapping
+    /// `x` and `y` works as expected.
+    pub fn substitute(&self, bindings: &HashMap<String, Expr>) -> Expr {
+        match self {
+            Expr::Variable(v) => bindings.get(v).cloned().unwrap_or_else(|| self.clone()),
+            Expr::Number(_) | Expr::Constant(_) | Expr::Hole(_) => self.clone(),
+            Expr::UnaryOp(op, e) => Expr::UnaryOp(op.clone(), Box::new(e.substitute(bindings))),
+            Expr::BinaryOp(op, a, b) => Expr::BinaryOp(
+                op.clone(),
+                Box::new(a.substitute(bindings)),
+                Box::new(b.substitute(bindings)),
+            ),
+            Expr::Function(name, args) => Expr::Function(
+                name.clone(),
+                args.iter().map(|a| a.substitute(bindings)).collect(),
+            ),
+            Expr::List(items) => Expr::List(items.iter().map(|a| a.substitute(bindings)).collect()),
+            Expr::Piecewise(branches, otherwise) => Expr::Piecewise(
+                branches
+                    .iter()
+                    .map(|(value, cond)| {
+                        (
+                            value.substitute(bindings),
+                            cond.map(|e| e.substitute(bindings)),
+                        )
+                    })
+                    .collect(),
+                otherwise.as_ref().map(|e| Box::new(e.substitute(bindings))),
+            ),
+            // Fixing the variable of a derivative only makes sense after
+            // taking it, so `d/dx x^2` at `x = 3` is 6 rather than `d/dx 9`
+            Expr::Derivative(e, var, order) if bindings.contains_key(var) => (0..*order)
+                .fold((**e).clone(), |d, _| differentiate(&d, var))
+                .substitute(bindings),
+            Expr::Derivative(e, var, order) => {
+                Expr::Derivative(Box::new(e.substitute(bindings)), var.clone(), *order)
+            }
+            // With its variable fixed at `c`, an indefinite integral becomes
+            // the one from 0 to `c`
+            Expr::Integral(e, var, None) if bindings.contains_key(var) => Expr::Integral(
+                e.clone(),
+                var.clone(),
+                Some(Box::new((
+                    Expr::Number(Real::from(0)),
+                    Expr::Variable(var.clone()),
+                ))),
+            )
+            .substitute(bindings),
+            Expr::Integral(e, var, None) => {
+                Expr::Integral(Box::new(e.substitute(bindings)), var.clone(), None)
+            }
+            // The variable of a definite integral is bound in the integrand
+            Expr::Integral(e, var, Some(bounds)) => {
+                let mut inner = bindings.clone();
+                inner.remove(var);
+                Expr::Integral(
+                    Box::new(e.substitute(&inner)),
+                    var.clone(),
+                    Some(Box::new((
+                        bounds.0.substitute(bindings),
+                        bounds.1.substitute(bindings),
+                    ))),
+                )
+            }
+        }
+    }
+
+    /// Whether this is `-1`, as a literal or a negated `1`.
+    pub fn is_minus_one(&self) -> bool {
+        match self {
+            Expr::Number(n) => n.to_i64() == Some(-1),
+            Expr::UnaryOp(UnaryOp::Neg, inner) => matches!(&**inner, Expr::Number(n) if n.is_one()),
+            _ => false,
+        }
+    }
+
+    /// Whether the variable `name` occurs anywhere in the expression.
+    pub fn contains_var(&self, name: &str) -> bool {
+        match self {
+            Expr::Variable(v) => v == name,
+            Expr::Number(_) | Expr::Constant(_) | Expr::Hole(_) => false,
+            Expr::UnaryOp(_, e) => e.contains_var(name),
+            Expr::BinaryOp(_, a, b) => a.contains_var(name) || b.contains_var(name),
+            Expr::Function(_, args) | Expr::List(args) => args.iter().any(|a| a.contains_var(name)),
+            Expr::Piecewise(branches, otherwise) => {
+                branches.iter().any(|(value, cond)| {
+                    value.contains_var(name)
+                        || cond.lhs.contains_var(name)
+                        || cond.rhs.contains_var(name)
+                }) || otherwise.as_ref().is_some_and(|e| e.contains_var(name))
+            }
+            Expr::Derivative(e, _, _) | Expr::Integral(e, _, None) => e.contains_var(name),
+            Expr::Integral(e, var, Some(bounds)) => {
+                (var != name && e.contains_var(name))
+                    || bounds.0.contains_var(name)
+                    || bounds.1.contains_var(name)
+            }
+        }
+    }
+
+    /// Whether the constant `c` occurs anywhere in the expression.
+    pub fn contains_constant(&self, c: Constant) -> bool {
+        match self {
+            Expr::Constant(k) => *k == c,
+            Expr::Number(_) | Expr::Variable(_) | Expr::Hole(_) => false,
+            Expr::UnaryOp(_, e) | Expr::Derivative(e, _, _) | Expr::Integral(e, _, None) => {
+                e.contains_constant(c)
+            }
+            Expr::BinaryOp(_, a, b) => a.contains_constant(c) || b.contains_constant(c),
+            Expr::Function(_, args) | Expr::List(args) => {
+                args.iter().any(|a| a.contains_constant(c))
+            }
+            Expr::Piecewise(branches, otherwise) => {
+                branches.iter().any(|(value, cond)| {
+                    value.contains_constant(c)
+                        || cond.lhs.contains_constant(c)
+                        || cond.rhs.contains_constant(c)
+                }) || otherwise.as_ref().is_some_and(|e| e.contains_constant(c))
+            }
+            Expr::Integral(e, _, Some(bounds)) => {
+                e.contains_constant(c)
+                    || bounds.0.contains_constant(c)
+                    || bounds.1.contains_constant(c)
+            }
+        }
+    }
+}
+
+/// Number of arguments a function accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exact(usize),
+    Range(usize, usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, n: usize) -> bool {
+        match *self {
+            Arity::Exact(k) => n == k,
+            Arity::Range(lo, hi) => lo <= n && n <= hi,
+            Arity::AtLeast(lo) => n >= lo,
+        }
+    }
+}
+
+impl std::fmt::Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Arity::Exact(k) => write!(f, "{}", k),
+            Arity::Range(lo, hi) => write!(f, "{} to {}", lo, hi),
+            Arity::AtLeast(lo) => write!(f, "at least {}", lo),
+        }
+    }
+}
+
+/// Functions that take lists as a whole (`sum([1, 2])`) or build them
+/// (`range`), rather than applying element-wise like `sin`.
+pub fn is_list_function(name: &str) -> bool {
+    functions::lookup(name).is_some_and(|f| f.takes_lists)
+}
+
+/// The inverse of a function such as `sin`, written `sin^-1`.
+pub fn inverse_function(name: &str) -> Option<String> {
+    functions::lookup(name)?.inverse.clone()
+}
+
+/// Arity of the built-in and registered functions, or `None` for names we
+/// know nothing about.
+pub fn function_arity(name: &str) -> Option<Arity> {
+    functions::lookup(name).map(|f| f.arity)
+}
+
+/// A variable name seen as a base and an optional subscript. Variables
+/// store the joined form, so `v_max` is the base `v` with subscript `max`
+/// and reads the same in plain input (`v_max`, `v_{max}`) and LaTeX
+/// (`v_{max}`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol<'a> {
+    pub base: &'a str,
+    pub subscript: Option<&'a str>,
+}
+
+impl<'a> Symbol<'a> {
+    /// Split `name` at its first `_`.
+    pub fn parse(name: &'a str) -> Self {
+        match name.split_once('_') {
+            Some((base, subscript)) if !base.is_empty() && !subscript.is_empty() => Symbol {
+                base,
+                subscript: Some(subscript),
+            },
+            _ => Symbol {
+                base: name,
+                subscript: None,
+            },
+        }
+    }
+
+    /// The variable name for `base` with an optional `subscript`.
+    pub fn join(base: &str, subscript: Option<&str>) -> String {
+        match subscript {
+            Some(sub) => format!("{}_{}", base, sub),
+            None => base.to_string(),
+        }
+    }
+}
+
+/// Greek letters (other than the constants `pi`, `tau` and `phi`) that are
+/// read as single variables, `\theta` in LaTeX or `theta` in plain input.
+pub const GREEK_LETTERS: [&str; 10] = [
+    "alpha", "beta", "gamma", "delta", "epsilon", "theta", "lambda", "mu", "sigma", "omega",
+];
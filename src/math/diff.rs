@@ -1,17 +1,21 @@
 use crate::math::algebra::simplify;
 use crate::math::ast::*;
-use ordered_float::OrderedFloat; // Adjust path as needed
+use crate::math::env::Environment;
+use crate::math::functions;
+use std::collections::HashMap;
 
 pub fn differentiate(expr: &Expr, var: &str) -> Expr {
-    simplify(&expr);
     let diffed = match expr {
-        Expr::Number(_) => Expr::Number(OrderedFloat(0.0)),
+        Expr::Number(_) | Expr::Constant(_) => Expr::Number(Real::from(0)),
+
+        // Nothing is known about a gap in the input, including its derivative
+        Expr::Hole(hole) => Expr::Hole(*hole),
 
         Expr::Variable(name) => {
             if name == var {
-                Expr::Number(OrderedFloat(1.0))
+                Expr::Number(Real::from(1))
             } else {
-                Expr::Number(OrderedFloat(0.0))
+                Expr::Number(Real::from(0))
             }
         }
 
@@ -19,6 +23,19 @@ pub fn differentiate(expr: &Expr, var: &str) -> Expr {
             Expr::UnaryOp(UnaryOp::Neg, Box::new(differentiate(e, var)))
         }
 
+        Expr::UnaryOp(UnaryOp::Percent, e) => Expr::BinaryOp(
+            BinaryOp::Div,
+            Box::new(differentiate(e, var)),
+            Box::new(Expr::Number(Real::from(100))),
+        ),
+
+        Expr::UnaryOp(UnaryOp::Factorial, e) if !e.contains_var(var) => Expr::Number(Real::from(0)),
+
+        // Would need the digamma function
+        Expr::UnaryOp(UnaryOp::Factorial, _) => {
+            Expr::Function("diff_not_supported".into(), vec![expr.clone()])
+        }
+
         Expr::BinaryOp(op, a, b) => match op {
             BinaryOp::Add => Expr::BinaryOp(
                 BinaryOp::Add,
@@ -67,7 +84,7 @@ pub fn differentiate(expr: &Expr, var: &str) -> Expr {
                     Box::new(Expr::BinaryOp(
                         BinaryOp::Pow,
                         v.clone(),
-                        Box::new(Expr::Number(OrderedFloat(2.0))),
+                        Box::new(Expr::Number(Real::from(2))),
                     )),
                 )
             }
@@ -77,50 +94,154 @@ pub fn differentiate(expr: &Expr, var: &str) -> Expr {
                     BinaryOp::Mul,
                     Box::new(Expr::BinaryOp(
                         BinaryOp::Mul,
-                        Box::new(Expr::Number(*n)),
+                        Box::new(Expr::Number(n.clone())),
                         Box::new(Expr::BinaryOp(
                             BinaryOp::Pow,
                             a.clone(),
-                            Box::new(Expr::Number(OrderedFloat(n.0 - 1.0))),
+                            Box::new(Expr::Number(n - &Real::from(1))),
                         )),
                     )),
                     Box::new(differentiate(a, var)),
                 ),
 
-                _ => Expr::Function("diff_not_supported".into(), Box::new(expr.clone())),
-            },
-        },
-
-        Expr::Function(name, arg) => {
-            let d_arg = differentiate(arg, var);
-            let inner = arg.clone();
-            match name.as_str() {
-                "sin" => Expr::BinaryOp(
-                    BinaryOp::Mul,
-                    Box::new(Expr::Function("cos".into(), inner.clone())),
-                    Box::new(d_arg),
+                // An exponent such as `1/3` that folds to an exact number
+                (_, c) if matches!(simplify(c), Expr::Number(_)) => differentiate(
+                    &Expr::BinaryOp(BinaryOp::Pow, a.clone(), Box::new(simplify(c))),
+                    var,
                 ),
 
-                "cos" => Expr::BinaryOp(
+                // d/dx c^u = c^u ln(c) u'
+                (c, _) if !c.contains_var(var) => Expr::BinaryOp(
                     BinaryOp::Mul,
-                    Box::new(Expr::UnaryOp(
-                        UnaryOp::Neg,
-                        Box::new(Expr::Function("sin".into(), inner.clone())),
+                    Box::new(Expr::BinaryOp(
+                        BinaryOp::Mul,
+                        Box::new(expr.clone()),
+                        Box::new(Expr::Function("ln".into(), vec![c.clone()])),
                     )),
-                    Box::new(d_arg),
+                    Box::new(differentiate(b, var)),
                 ),
 
-                "exp" => Expr::BinaryOp(
+                // d/dx f^g = f^g (g' ln(f) + g f' / f)
+                (f, g) => Expr::BinaryOp(
                     BinaryOp::Mul,
-                    Box::new(Expr::Function("exp".into(), inner.clone())),
-                    Box::new(d_arg),
+                    Box::new(expr.clone()),
+                    Box::new(Expr::BinaryOp(
+                        BinaryOp::Add,
+                        Box::new(Expr::BinaryOp(
+                            BinaryOp::Mul,
+                            Box::new(differentiate(g, var)),
+                            Box::new(Expr::Function("ln".into(), vec![f.clone()])),
+                        )),
+                        Box::new(Expr::BinaryOp(
+                            BinaryOp::Div,
+                            Box::new(Expr::BinaryOp(
+                                BinaryOp::Mul,
+                                Box::new(g.clone()),
+                                Box::new(differentiate(f, var)),
+                            )),
+                            Box::new(f.clone()),
+                        )),
+                    )),
                 ),
+            },
+        },
+
+        Expr::List(items) => Expr::List(items.iter().map(|e| differentiate(e, var)).collect()),
 
-                "log" => Expr::BinaryOp(BinaryOp::Div, Box::new(d_arg), inner),
+        // Branch by branch, ignoring the jumps and kinks where conditions change
+        Expr::Piecewise(branches, otherwise) => Expr::Piecewise(
+            branches
+                .iter()
+                .map(|(value, cond)| (differentiate(value, var), cond.clone()))
+                .collect(),
+            otherwise.as_ref().map(|e| Box::new(differentiate(e, var))),
+        ),
+
+        Expr::Derivative(e, v, order) => differentiate(
+            &(0..*order).fold((**e).clone(), |d, _| differentiate(&d, v)),
+            var,
+        ),
+
+        // Fundamental theorem of calculus, or differentiation under the
+        // integral sign for another variable
+        Expr::Integral(e, v, None) if v == var => (**e).clone(),
+        Expr::Integral(e, v, None) => {
+            Expr::Integral(Box::new(differentiate(e, var)), v.clone(), None)
+        }
 
-                _ => Expr::Function("diff_not_supported".into(), Box::new(expr.clone())),
+        // Leibniz rule, d/dx int(f, t, a, b) = f(b) b' - f(a) a' + int(f_x, t, a, b)
+        Expr::Integral(e, v, Some(bounds)) => {
+            let (a, b) = &**bounds;
+            let at = |x: &Expr| e.substitute(&HashMap::from([(v.clone(), x.clone())]));
+            let ends = Expr::BinaryOp(
+                BinaryOp::Sub,
+                Box::new(Expr::BinaryOp(
+                    BinaryOp::Mul,
+                    Box::new(at(b)),
+                    Box::new(differentiate(b, var)),
+                )),
+                Box::new(Expr::BinaryOp(
+                    BinaryOp::Mul,
+                    Box::new(at(a)),
+                    Box::new(differentiate(a, var)),
+                )),
+            );
+            if v == var {
+                ends
+            } else {
+                Expr::BinaryOp(
+                    BinaryOp::Add,
+                    Box::new(ends),
+                    Box::new(Expr::Integral(
+                        Box::new(differentiate(e, var)),
+                        v.clone(),
+                        Some(bounds.clone()),
+                    )),
+                )
             }
         }
+
+        Expr::Function(name, args) => match functions::lookup(name) {
+            Some(f) => f
+                .derivative
+                .as_ref()
+                .and_then(|derivative| derivative(args, var))
+                .unwrap_or_else(|| Expr::Function("diff_not_supported".into(), vec![expr.clone()])),
+
+            // Chain rule through an undefined function, d/dx f(u) = f'(u) u'
+            None => match args.as_slice() {
+                [arg] if function_arity(name).is_none() => Expr::BinaryOp(
+                    BinaryOp::Mul,
+                    Box::new(Expr::Function(format!("{}'", name), vec![arg.clone()])),
+                    Box::new(differentiate(arg, var)),
+                ),
+                _ => Expr::Function("diff_not_supported".into(), vec![expr.clone()]),
+            },
+        },
     };
     simplify(&diffed)
 }
+
+/// Differentiate after expanding the variables and user functions of
+/// `env`, so `f(x^2)` with `f(u) = sin(u)` gives `2 x cos(x^2)`.
+pub fn differentiate_with_env(expr: &Expr, var: &str, env: &Environment) -> Expr {
+    differentiate(&env.inline(expr), var)
+}
+
+/// dy/dx along the curve `rel`, by implicit differentiation of
+/// `F(x, y) = lhs - rhs = 0`: `dy/dx = -F_x / F_y`.
+pub fn implicit_derivative(rel: &Relation, x: &str, y: &str) -> Expr {
+    let f = Expr::BinaryOp(
+        BinaryOp::Sub,
+        Box::new(rel.lhs.clone()),
+        Box::new(rel.rhs.clone()),
+    );
+    simplify(&Expr::UnaryOp(
+        UnaryOp::Neg,
+        Box::new(Expr::BinaryOp(
+            BinaryOp::Div,
+            Box::new(differentiate(&f, x)),
+            Box::new(differentiate(&f, y)),
+        )),
+    ))
+}
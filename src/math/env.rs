@@ -0,0 +1,369 @@
+use crate::math::ast::*;
+use crate::math::diff::differentiate;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// A user function, `name(params...) = body`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDef {
+    pub params: Vec<String>,
+    pub body: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvError {
+    /// The definition would refer back to itself, e.g. `a = b` after `b = a`.
+    Cyclic(String),
+    /// Built-in functions and constants cannot be redefined.
+    Builtin(String),
+}
+
+impl fmt::Display for EnvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvError::Cyclic(name) => write!(f, "'{}' is defined in terms of itself", name),
+            EnvError::Builtin(name) => write!(f, "'{}' is built in and cannot be redefined", name),
+        }
+    }
+}
+
+impl std::error::Error for EnvError {}
+
+/// Variable bindings and user function definitions that persist across
+/// statements.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Environment {
+    vars: HashMap<String, Expr>,
+    functions: HashMap<String, FunctionDef>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run definitions in order, returning the environment they build and
+    /// the remaining (non-definition) statements.
+    pub fn from_program(program: &[Statement]) -> Result<(Environment, Vec<Statement>), EnvError> {
+        let mut env = Environment::new();
+        let mut rest = Vec::new();
+        for stmt in program {
+            if !env.execute(stmt)? {
+                rest.push(stmt.clone());
+            }
+        }
+        Ok((env, rest))
+    }
+
+    /// Bind `name` to a number, e.g. the plotting variable at each sample.
+    pub fn set_var(&mut self, name: &str, value: f64) {
+        match self.vars.get_mut(name) {
+            Some(slot) => *slot = Expr::Number(Real::from(value)),
+            None => {
+                self.vars
+                    .insert(name.to_string(), Expr::Number(Real::from(value)));
+            }
+        }
+    }
+
+    pub fn assign(&mut self, name: &str, value: Expr) -> Result<(), EnvError> {
+        if Constant::from_name(name).is_some() {
+            return Err(EnvError::Builtin(name.to_string()));
+        }
+        let previous = self.vars.insert(name.to_string(), value);
+        if self.depends_on_var(&self.vars[name], name, &mut HashSet::new()) {
+            match previous {
+                Some(p) => self.vars.insert(name.to_string(), p),
+                None => self.vars.remove(name),
+            };
+            return Err(EnvError::Cyclic(name.to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn define(&mut self, name: &str, params: Vec<String>, body: Expr) -> Result<(), EnvError> {
+        if function_arity(name).is_some() {
+            return Err(EnvError::Builtin(name.to_string()));
+        }
+        let previous = self
+            .functions
+            .insert(name.to_string(), FunctionDef { params, body });
+        let body = &self.functions[name].body;
+        if self.depends_on_function(body, name, &mut HashSet::new()) {
+            match previous {
+                Some(p) => self.functions.insert(name.to_string(), p),
+                None => self.functions.remove(name),
+            };
+            return Err(EnvError::Cyclic(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Record an assignment or definition. Returns `false` (and does
+    /// nothing) for other statements.
+    pub fn execute(&mut self, stmt: &Statement) -> Result<bool, EnvError> {
+        match stmt {
+            Statement::Assign(name, value) => self.assign(name, value.clone())?,
+            Statement::Define(name, params, body) => {
+                self.define(name, params.clone(), body.clone())?
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    pub fn var(&self, name: &str) -> Option<&Expr> {
+        self.vars.get(name)
+    }
+
+    pub fn function(&self, name: &str) -> Option<&FunctionDef> {
+        self.functions.get(name)
+    }
+
+    /// The definition of `f'`, `f''`, ... for a single-parameter user
+    /// function `f`.
+    pub fn derivative_of(&self, name: &str) -> Option<FunctionDef> {
+        let base = name.trim_end_matches('\'');
+        let order = name.len() - base.len();
+        let def = self.functions.get(base)?;
+        match (order, def.params.as_slice()) {
+            (0, _) => None,
+            (_, [param]) => {
+                let body = (0..order)
+                    .fold(self.inline_scoped(&def.body, &def.params), |body, _| {
+                        differentiate(&body, param)
+                    });
+                Some(FunctionDef {
+                    params: def.params.clone(),
+                    body,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Replace bound variables by their values and expand calls to user
+    /// functions (and their derivatives `f'`), leaving everything else as is.
+    pub fn inline(&self, expr: &Expr) -> Expr {
+        self.inline_scoped(expr, &[])
+    }
+
+    /// `inline`, leaving the variables in `shadowed` (function parameters)
+    /// alone.
+    fn inline_scoped(&self, expr: &Expr, shadowed: &[String]) -> Expr {
+        match expr {
+            Expr::Variable(name) if shadowed.contains(name) => expr.clone(),
+            Expr::Variable(name) => match self.vars.get(name) {
+                Some(value) => self.inline(value),
+                None => expr.clone(),
+            },
+            Expr::Number(_) | Expr::Constant(_) | Expr::Hole(_) => expr.clone(),
+            Expr::UnaryOp(op, e) => {
+                Expr::UnaryOp(op.clone(), Box::new(self.inline_scoped(e, shadowed)))
+            }
+            Expr::BinaryOp(op, a, b) => Expr::BinaryOp(
+                op.clone(),
+                Box::new(self.inline_scoped(a, shadowed)),
+                Box::new(self.inline_scoped(b, shadowed)),
+            ),
+            Expr::List(items) => Expr::List(
+                items
+                    .iter()
+                    .map(|e| self.inline_scoped(e, shadowed))
+                    .collect(),
+            ),
+            Expr::Piecewise(branches, otherwise) => Expr::Piecewise(
+                branches
+                    .iter()
+                    .map(|(value, cond)| {
+                        (
+                            self.inline_scoped(value, shadowed),
+                            cond.map(|e| self.inline_scoped(e, shadowed)),
+                        )
+                    })
+                    .collect(),
+                otherwise
+                    .as_ref()
+                    .map(|e| Box::new(self.inline_scoped(e, shadowed))),
+            ),
+            // A bound variable of differentiation is only substituted after
+            // differentiating, and an indefinite integral then runs from 0 to
+            // its value
+            Expr::Derivative(e, var, order)
+                if !shadowed.contains(var) && self.vars.contains_key(var) =>
+            {
+                let d = (0..*order).fold(self.inline_scoped(e, &with(shadowed, var)), |d, _| {
+                    differentiate(&d, var)
+                });
+                self.inline_scoped(&d, shadowed)
+            }
+            Expr::Derivative(e, var, order) => Expr::Derivative(
+                Box::new(self.inline_scoped(e, &with(shadowed, var))),
+                var.clone(),
+                *order,
+            ),
+            Expr::Integral(e, var, None)
+                if !shadowed.contains(var) && self.vars.contains_key(var) =>
+            {
+                let definite = Expr::Integral(
+                    e.clone(),
+                    var.clone(),
+                    Some(Box::new((
+                        Expr::Number(Real::from(0)),
+                        Expr::Variable(var.clone()),
+                    ))),
+                );
+                self.inline_scoped(&definite, shadowed)
+            }
+            Expr::Integral(e, var, bounds) => Expr::Integral(
+                Box::new(self.inline_scoped(e, &with(shadowed, var))),
+                var.clone(),
+                bounds.as_ref().map(|bounds| {
+                    Box::new((
+                        self.inline_scoped(&bounds.0, shadowed),
+                        self.inline_scoped(&bounds.1, shadowed),
+                    ))
+                }),
+            ),
+            Expr::Function(name, args) => {
+                let args: Vec<Expr> = args
+                    .iter()
+                    .map(|a| self.inline_scoped(a, shadowed))
+                    .collect();
+                let def = match self.functions.get(name) {
+                    Some(def) => Some(def.clone()),
+                    None => self.derivative_of(name),
+                };
+                match def {
+                    Some(def) if def.params.len() == args.len() => {
+                        let bindings: HashMap<String, Expr> =
+                            def.params.iter().cloned().zip(args).collect();
+                        // Substituted arguments are already inlined, so only
+                        // the body's free variables are looked up
+                        self.inline_scoped(&def.body.substitute(&bindings), shadowed)
+                    }
+                    _ => Expr::Function(name.clone(), args),
+                }
+            }
+        }
+    }
+
+    pub fn inline_statement(&self, stmt: &Statement) -> Statement {
+        match stmt {
+            Statement::Expr(e) => Statement::Expr(self.inline(e)),
+            Statement::Relation(rel) => Statement::Relation(Relation {
+                op: rel.op,
+                lhs: self.inline(&rel.lhs),
+                rhs: self.inline(&rel.rhs),
+            }),
+            Statement::Assign(name, value) => Statement::Assign(name.clone(), self.inline(value)),
+            Statement::Define(name, params, body) => Statement::Define(
+                name.clone(),
+                params.clone(),
+                self.inline_scoped(body, params),
+            ),
+        }
+    }
+
+    /// Whether evaluating `expr` would look up the variable `target`.
+    fn depends_on_var(&self, expr: &Expr, target: &str, seen: &mut HashSet<String>) -> bool {
+        match expr {
+            Expr::Variable(v) => {
+                v == target
+                    || (seen.insert(v.clone())
+                        && self
+                            .vars
+                            .get(v)
+                            .is_some_and(|value| self.depends_on_var(value, target, seen)))
+            }
+            Expr::Number(_) | Expr::Constant(_) | Expr::Hole(_) => false,
+            Expr::UnaryOp(_, e) => self.depends_on_var(e, target, seen),
+            Expr::BinaryOp(_, a, b) => {
+                self.depends_on_var(a, target, seen) || self.depends_on_var(b, target, seen)
+            }
+            Expr::List(items) => items.iter().any(|e| self.depends_on_var(e, target, seen)),
+            Expr::Piecewise(..) => piecewise_parts(expr)
+                .into_iter()
+                .any(|e| self.depends_on_var(e, target, seen)),
+            Expr::Derivative(e, _, _) | Expr::Integral(e, _, None) => {
+                self.depends_on_var(e, target, seen)
+            }
+            Expr::Integral(e, var, Some(bounds)) => {
+                (var != target && self.depends_on_var(e, target, seen))
+                    || self.depends_on_var(&bounds.0, target, seen)
+                    || self.depends_on_var(&bounds.1, target, seen)
+            }
+            Expr::Function(name, args) => {
+                args.iter().any(|a| self.depends_on_var(a, target, seen))
+                    || self.functions.get(name).is_some_and(|def| {
+                        !def.params.iter().any(|p| p == target)
+                            && self.depends_on_var(&def.body, target, seen)
+                    })
+            }
+        }
+    }
+
+    /// Whether evaluating `expr` would call the user function `target`.
+    fn depends_on_function(&self, expr: &Expr, target: &str, seen: &mut HashSet<String>) -> bool {
+        match expr {
+            Expr::Variable(v) => {
+                seen.insert(v.clone())
+                    && self
+                        .vars
+                        .get(v)
+                        .is_some_and(|value| self.depends_on_function(value, target, seen))
+            }
+            Expr::Number(_) | Expr::Constant(_) | Expr::Hole(_) => false,
+            Expr::UnaryOp(_, e) => self.depends_on_function(e, target, seen),
+            Expr::BinaryOp(_, a, b) => {
+                self.depends_on_function(a, target, seen)
+                    || self.depends_on_function(b, target, seen)
+            }
+            Expr::List(items) => items
+                .iter()
+                .any(|e| self.depends_on_function(e, target, seen)),
+            Expr::Piecewise(..) => piecewise_parts(expr)
+                .into_iter()
+                .any(|e| self.depends_on_function(e, target, seen)),
+            Expr::Derivative(e, _, _) | Expr::Integral(e, _, None) => {
+                self.depends_on_function(e, target, seen)
+            }
+            Expr::Integral(e, _, Some(bounds)) => {
+                self.depends_on_function(e, target, seen)
+                    || self.depends_on_function(&bounds.0, target, seen)
+                    || self.depends_on_function(&bounds.1, target, seen)
+            }
+            Expr::Function(name, args) => {
+                let base = name.trim_end_matches('\'');
+                base == target
+                    || args
+                        .iter()
+                        .any(|a| self.depends_on_function(a, target, seen))
+                    || (seen.insert(format!("{}()", base))
+                        && self
+                            .functions
+                            .get(base)
+                            .is_some_and(|def| self.depends_on_function(&def.body, target, seen)))
+            }
+        }
+    }
+}
+
+/// The values, condition sides and fallback of a piecewise expression.
+fn piecewise_parts(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::Piecewise(branches, otherwise) => branches
+            .iter()
+            .flat_map(|(value, cond)| [value, &cond.lhs, &cond.rhs])
+            .chain(otherwise.as_deref())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// `shadowed` with `var` added.
+fn with(shadowed: &[String], var: &str) -> Vec<String> {
+    let mut all = shadowed.to_vec();
+    all.push(var.to_string());
+    all
+}
@@ -0,0 +1,334 @@
+use crate::math::ast::Arity;
+use std::fmt;
+
+/// A half-open range of byte offsets into the parser input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// Zero-width span at a single byte offset, used for "missing" tokens.
+    pub fn point(at: usize) -> Self {
+        Span { start: at, end: at }
+    }
+
+    /// The same range measured in `char`s instead of bytes, for UIs that
+    /// index strings by character.
+    pub fn char_range(&self, source: &str) -> (usize, usize) {
+        let start = source[..self.start.min(source.len())].chars().count();
+        let end = source[..self.end.min(source.len())].chars().count();
+        (start, end)
+    }
+}
+
+/// Something the parser would have accepted at the point of failure.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Expected {
+    Char(char),
+    Expression,
+    Operator,
+    Relation,
+    EndOfInput,
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Char(c) => write!(f, "'{}'", c),
+            Expected::Expression => write!(f, "expression"),
+            Expected::Operator => write!(f, "operator"),
+            Expected::Relation => write!(f, "relation"),
+            Expected::EndOfInput => write!(f, "end of input"),
+        }
+    }
+}
+
+/// Why a numeric literal was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberError {
+    /// No digits, as in `.` or `0x`.
+    MissingDigits,
+    /// More than one `.`, as in `1.2.3`.
+    MultipleDecimalPoints,
+    /// A `_` separator not between two digits, as in `1__0` or `1_`.
+    MisplacedSeparator,
+    /// A digit outside the literal's radix, as in `0b102`.
+    InvalidDigit,
+}
+
+impl fmt::Display for NumberError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NumberError::MissingDigits => write!(f, "expected digits"),
+            NumberError::MultipleDecimalPoints => write!(f, "more than one decimal point"),
+            NumberError::MisplacedSeparator => {
+                write!(f, "'_' must separate two digits")
+            }
+            NumberError::InvalidDigit => write!(f, "digit out of range for this base"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    UnexpectedChar {
+        found: char,
+        span: Span,
+        expected: Vec<Expected>,
+    },
+    UnexpectedEnd {
+        span: Span,
+        expected: Vec<Expected>,
+    },
+    UnclosedDelimiter {
+        open: char,
+        open_span: Span,
+        span: Span,
+    },
+    InvalidNumber {
+        literal: String,
+        reason: NumberError,
+        span: Span,
+    },
+    ArityMismatch {
+        name: String,
+        expected: Arity,
+        found: usize,
+        span: Span,
+    },
+    UnknownCommand {
+        name: String,
+        span: Span,
+    },
+    /// An argument of the wrong kind, such as `diff(x^2, 2)` where the
+    /// variable should be.
+    InvalidArgument {
+        name: String,
+        expected: &'static str,
+        span: Span,
+    },
+}
+
+impl ParseError {
+    /// Location of the offending input.
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::UnexpectedChar { span, .. }
+            | ParseError::UnexpectedEnd { span, .. }
+            | ParseError::UnclosedDelimiter { span, .. }
+            | ParseError::InvalidNumber { span, .. }
+            | ParseError::ArityMismatch { span, .. }
+            | ParseError::UnknownCommand { span, .. }
+            | ParseError::InvalidArgument { span, .. } => *span,
+        }
+    }
+
+    /// The same error with every span moved `by` bytes to the right, for
+    /// input parsed as a slice of a larger string.
+    pub fn offset(self, by: usize) -> Self {
+        self.map_spans(|pos| pos + by)
+    }
+
+    /// The same error with every span offset passed through `f`.
+    pub fn map_spans(mut self, f: impl Fn(usize) -> usize) -> Self {
+        let map = |span: &mut Span| *span = Span::new(f(span.start), f(span.end));
+        match &mut self {
+            ParseError::UnclosedDelimiter {
+                open_span, span, ..
+            } => {
+                map(open_span);
+                map(span);
+            }
+            ParseError::UnexpectedChar { span, .. }
+            | ParseError::UnexpectedEnd { span, .. }
+            | ParseError::InvalidNumber { span, .. }
+            | ParseError::ArityMismatch { span, .. }
+            | ParseError::UnknownCommand { span, .. }
+            | ParseError::InvalidArgument { span, .. } => map(span),
+        }
+        self
+    }
+
+    pub fn expected(&self) -> &[Expected] {
+        match self {
+            ParseError::UnexpectedChar { expected, .. }
+            | ParseError::UnexpectedEnd { expected, .. } => expected,
+            _ => &[],
+        }
+    }
+
+    /// Render a rustc-style diagnostic with the offending input underlined.
+    ///
+    /// ```text
+    /// error: unexpected character '#'
+    ///  --> 1:5
+    ///   |
+    /// 1 | x + # 2
+    ///   |     ^ expected expression
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        render_diagnostic(
+            "error",
+            &self.to_string(),
+            &self.label(),
+            self.span(),
+            source,
+        )
+    }
+
+    fn label(&self) -> String {
+        match self {
+            ParseError::UnclosedDelimiter { open, .. } => {
+                format!("expected closing delimiter for '{}'", open)
+            }
+            ParseError::InvalidNumber { reason, .. } => reason.to_string(),
+            ParseError::InvalidArgument { expected, .. } => format!("expected {}", expected),
+            ParseError::ArityMismatch { found, .. } => format!(
+                "called with {} argument{}",
+                found,
+                if *found == 1 { "" } else { "s" }
+            ),
+            _ => match self.expected() {
+                [] => String::new(),
+                expected => format!("expected {}", join_expected(expected)),
+            },
+        }
+    }
+}
+
+/// Input that parsed, but perhaps not as the user meant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseWarning {
+    /// A run of letters read as a product, as `xy` is read as `x y`.
+    SplitIdentifier {
+        ident: String,
+        parts: Vec<String>,
+        span: Span,
+    },
+}
+
+impl ParseWarning {
+    pub fn span(&self) -> Span {
+        match self {
+            ParseWarning::SplitIdentifier { span, .. } => *span,
+        }
+    }
+
+    /// See [`ParseError::offset`].
+    pub fn offset(self, by: usize) -> Self {
+        self.map_spans(|pos| pos + by)
+    }
+
+    /// See [`ParseError::map_spans`].
+    pub fn map_spans(mut self, f: impl Fn(usize) -> usize) -> Self {
+        match &mut self {
+            ParseWarning::SplitIdentifier { span, .. } => {
+                *span = Span::new(f(span.start), f(span.end));
+            }
+        }
+        self
+    }
+
+    /// Render like [`ParseError::render`], as a warning.
+    pub fn render(&self, source: &str) -> String {
+        let label = match self {
+            ParseWarning::SplitIdentifier { ident, .. } => {
+                format!("declare '{}' as a variable to keep it whole", ident)
+            }
+        };
+        render_diagnostic("warning", &self.to_string(), &label, self.span(), source)
+    }
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseWarning::SplitIdentifier { ident, parts, .. } => {
+                write!(f, "read '{}' as '{}'", ident, parts.join(" "))
+            }
+        }
+    }
+}
+
+fn render_diagnostic(
+    severity: &str,
+    message: &str,
+    label: &str,
+    span: Span,
+    source: &str,
+) -> String {
+    let (line_no, line_start) = source[..span.start.min(source.len())]
+        .char_indices()
+        .filter(|&(_, c)| c == '\n')
+        .fold((1, 0), |(n, _), (i, _)| (n + 1, i + 1));
+    let line_end = source[line_start..]
+        .find('\n')
+        .map_or(source.len(), |i| line_start + i);
+    let line = &source[line_start..line_end];
+
+    let col = source[line_start..span.start.min(line_end)].chars().count();
+    let width = source[span.start.min(line_end)..span.end.clamp(span.start, line_end)]
+        .chars()
+        .count()
+        .max(1);
+
+    let gutter = line_no.to_string();
+    let pad = " ".repeat(gutter.len());
+
+    let mut out = format!("{}: {}\n", severity, message);
+    out.push_str(&format!("{}--> {}:{}\n", pad, line_no, col + 1));
+    out.push_str(&format!("{} |\n", pad));
+    out.push_str(&format!("{} | {}\n", gutter, line));
+    out.push_str(&format!(
+        "{} | {}{}",
+        pad,
+        " ".repeat(col),
+        "^".repeat(width)
+    ));
+    if !label.is_empty() {
+        out.push(' ');
+        out.push_str(label);
+    }
+    out
+}
+
+fn join_expected(expected: &[Expected]) -> String {
+    let names: Vec<String> = expected.iter().map(|e| e.to_string()).collect();
+    match names.as_slice() {
+        [] => String::new(),
+        [one] => one.clone(),
+        [init @ .., last] => format!("{} or {}", init.join(", "), last),
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedChar { found, .. } => {
+                write!(f, "unexpected character '{}'", found)
+            }
+            ParseError::UnexpectedEnd { .. } => write!(f, "unexpected end of input"),
+            ParseError::UnclosedDelimiter { open, .. } => write!(f, "unclosed '{}'", open),
+            ParseError::InvalidNumber { literal, .. } => {
+                write!(f, "invalid number '{}'", literal)
+            }
+            ParseError::ArityMismatch { name, expected, .. } => {
+                write!(f, "'{}' takes {} argument(s)", name, expected)
+            }
+            ParseError::UnknownCommand { name, .. } => {
+                write!(f, "unknown LaTeX command '\\{}'", name)
+            }
+            ParseError::InvalidArgument { name, .. } => {
+                write!(f, "invalid argument to '{}'", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
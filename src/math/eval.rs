@@ -1,41 +1,476 @@
 use crate::math::ast::*;
+use crate::math::diff::differentiate;
+use crate::math::env::Environment;
+use crate::math::functions;
+use crate::math::integrate::{integrate, integrate_numeric};
 use std::collections::HashMap;
 
+/// Longest list a range such as `1..10` expands to.
+pub const MAX_RANGE_LEN: usize = 10_000;
+
+pub use num_complex::Complex64;
+
+/// Imaginary parts this small relative to the real part are rounding
+/// error, as in `exp(i pi) = -1 + 1.2e-16 i`, and read as real.
+const IMAGINARY_TOLERANCE: f64 = 1e-12;
+
+/// The result of evaluating an expression: a number, or the elements of a
+/// list. Evaluation works in complex numbers, `Value<Complex64>`, and the
+/// real API sees their real values, `Value<f64>`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value<T = f64> {
+    Number(T),
+    List(Vec<T>),
+}
+
+impl<T: Copy> Value<T> {
+    /// The elements, with a number as a list of one.
+    pub fn to_vec(&self) -> Vec<T> {
+        match self {
+            Value::Number(x) => vec![*x],
+            Value::List(xs) => xs.clone(),
+        }
+    }
+
+    fn map<U>(self, f: impl Fn(T) -> U) -> Value<U> {
+        match self {
+            Value::Number(x) => Value::Number(f(x)),
+            Value::List(xs) => Value::List(xs.into_iter().map(f).collect()),
+        }
+    }
+}
+
+impl Value {
+    /// The number, or NaN for a list.
+    pub fn as_number(&self) -> f64 {
+        match self {
+            Value::Number(x) => *x,
+            Value::List(_) => f64::NAN,
+        }
+    }
+}
+
+impl Value<Complex64> {
+    /// The number, or NaN for a list.
+    pub fn as_complex(&self) -> Complex64 {
+        match self {
+            Value::Number(z) => *z,
+            Value::List(_) => Complex64::new(f64::NAN, f64::NAN),
+        }
+    }
+
+    /// The real values, with NaN for numbers off the real line.
+    pub fn to_real(self) -> Value {
+        self.map(real)
+    }
+}
+
+/// `z` as a real number, or NaN if it has an imaginary part.
+fn real(z: Complex64) -> f64 {
+    if z.im == 0.0 || z.im.abs() <= IMAGINARY_TOLERANCE * z.re.abs().max(1.0) {
+        z.re
+    } else {
+        f64::NAN
+    }
+}
+
+/// Apply `f` element-wise: numbers are repeated against lists, and lists
+/// are cut to the shortest.
+fn broadcast(args: &[Value<Complex64>], f: impl Fn(&[Complex64]) -> Complex64) -> Value<Complex64> {
+    let len = args
+        .iter()
+        .filter_map(|a| match a {
+            Value::List(xs) => Some(xs.len()),
+            Value::Number(_) => None,
+        })
+        .min();
+    let nth = |i: usize| -> Vec<Complex64> {
+        args.iter()
+            .map(|a| match a {
+                Value::Number(x) => *x,
+                Value::List(xs) => xs[i],
+            })
+            .collect()
+    };
+    match len {
+        None => Value::Number(f(&nth(0))),
+        Some(len) => Value::List((0..len).map(|i| f(&nth(i))).collect()),
+    }
+}
+
+/// The real value of `expr`, or NaN when it is not real, as `2i` is not;
+/// `evaluate_complex` gives those.
 pub fn evaluate(expr: &Expr) -> f64 {
-    evaluate_with_env(expr, &HashMap::new())
+    evaluate_with_env(expr, &Environment::new())
+}
+
+/// Evaluate with the variables and user functions of `env`. Unbound
+/// variables are 0 and unknown functions NaN, as is a list and a number
+/// that is not real, such as `sqrt(-1)`.
+pub fn evaluate_with_env(expr: &Expr, env: &Environment) -> f64 {
+    evaluate_value(expr, env).as_number()
+}
+
+/// `evaluate_with_env`, keeping lists. Operators and functions of one
+/// number apply element-wise, so `[1, 2] * 3` is `[3, 6]`.
+pub fn evaluate_value(expr: &Expr, env: &Environment) -> Value {
+    eval_scoped(expr, env, &HashMap::new()).to_real()
+}
+
+pub fn evaluate_complex(expr: &Expr) -> Complex64 {
+    evaluate_complex_with_env(expr, &Environment::new())
 }
 
-pub fn evaluate_with_env(expr: &Expr, vars: &HashMap<String, f64>) -> f64 {
+/// `evaluate_with_env` in complex numbers, so `sqrt(-4)` is `2i` and
+/// `exp(i pi)` is `-1`.
+pub fn evaluate_complex_with_env(expr: &Expr, env: &Environment) -> Complex64 {
+    eval_scoped(expr, env, &HashMap::new()).as_complex()
+}
+
+/// `locals` are the parameters of the user function being evaluated, which
+/// shadow variables of the same name in `env`.
+fn eval_scoped(
+    expr: &Expr,
+    env: &Environment,
+    locals: &HashMap<&str, Complex64>,
+) -> Value<Complex64> {
+    let nan = Complex64::new(f64::NAN, 0.0);
     match expr {
-        Expr::Number(n) => n.0, // unwrap OrderedFloat<f64> here
+        Expr::Number(n) => Value::Number(n.to_f64().into()),
+
+        Expr::Variable(name) => match (locals.get(name.as_str()), env.var(name)) {
+            (Some(z), _) => Value::Number(*z),
+            (None, Some(value)) => eval_scoped(value, env, &HashMap::new()),
+            (None, None) => Value::Number(0.0.into()),
+        },
 
-        Expr::Variable(name) => *vars.get(name).unwrap_or(&0.0),
+        Expr::Constant(Constant::I) => Value::Number(Complex64::i()),
+        Expr::Constant(c) => Value::Number(c.value().into()),
 
-        Expr::UnaryOp(UnaryOp::Neg, e) => -evaluate_with_env(e, vars),
+        Expr::Hole(_) => Value::Number(nan),
+
+        // A real number stays on the upper side of the branch cuts, so that
+        // sqrt(-1) is i rather than -i
+        Expr::UnaryOp(UnaryOp::Neg, e) => {
+            eval_scoped(e, env, locals).map(|z| if z.im == 0.0 { (-z.re).into() } else { -z })
+        }
+        Expr::UnaryOp(UnaryOp::Factorial, e) => {
+            eval_scoped(e, env, locals).map(|z| real_only(&[z], |xs| factorial(xs[0])).into())
+        }
+        Expr::UnaryOp(UnaryOp::Percent, e) => eval_scoped(e, env, locals).map(|z| z / 100.0),
 
         Expr::BinaryOp(op, a, b) => {
-            let left = evaluate_with_env(a, vars);
-            let right = evaluate_with_env(b, vars);
-            match op {
-                BinaryOp::Add => left + right,
-                BinaryOp::Sub => left - right,
-                BinaryOp::Mul => left * right,
-                BinaryOp::Div => left / right,
-                BinaryOp::Pow => left.powf(right),
+            let operands = [eval_scoped(a, env, locals), eval_scoped(b, env, locals)];
+            broadcast(&operands, |zs| arithmetic(op, zs[0], zs[1]))
+        }
+
+        // Elements that are themselves lists, such as a range, are spliced in
+        Expr::List(items) => Value::List(
+            items
+                .iter()
+                .flat_map(|e| eval_scoped(e, env, locals).to_vec())
+                .collect(),
+        ),
+
+        Expr::Piecewise(branches, otherwise) => {
+            let taken = branches
+                .iter()
+                .find(|(_, cond)| holds_scoped(cond, env, locals));
+            match (taken, otherwise) {
+                (Some((value, _)), _) => eval_scoped(value, env, locals),
+                (None, Some(fallback)) => eval_scoped(fallback, env, locals),
+                (None, None) => Value::Number(nan),
             }
         }
 
-        Expr::Function(f, arg) => {
-            let x = evaluate_with_env(arg, vars);
-            match f.as_str() {
-                "sin" => x.sin(),
-                "cos" => x.cos(),
-                "tan" => x.tan(),
-                "log" => x.ln(),
-                "exp" => x.exp(),
-                "abs" => x.abs(),
-                _ => panic!("Unknown function: {}", f),
+        Expr::Derivative(e, var, order) => {
+            let d = (0..*order).fold((**e).clone(), |d, _| differentiate(&d, var));
+            eval_scoped(&d, env, locals)
+        }
+
+        // The indefinite integral is taken from 0, so that it agrees with the
+        // value `substitute` gives it once the variable is fixed
+        Expr::Integral(e, var, bounds) => {
+            let (a, b) = match bounds {
+                Some(bounds) => (
+                    eval_scoped(&bounds.0, env, locals),
+                    eval_scoped(&bounds.1, env, locals),
+                ),
+                None => (
+                    Value::Number(0.0.into()),
+                    eval_scoped(&Expr::Variable(var.clone()), env, locals),
+                ),
+            };
+            let antiderivative = integrate(e, var);
+            broadcast(&[a, b], |zs| {
+                let (a, b) = (real(zs[0]), real(zs[1]));
+                definite_integral(e, antiderivative.as_ref(), var, a, b, env, locals)
+            })
+        }
+
+        Expr::Function(f, args) => {
+            let values: Vec<Value<Complex64>> =
+                args.iter().map(|a| eval_scoped(a, env, locals)).collect();
+            match (f.as_str(), values.as_slice()) {
+                ("dot", [a, b]) => {
+                    Value::Number(a.to_vec().iter().zip(b.to_vec()).map(|(x, y)| x * y).sum())
+                }
+                ("range", [a, b]) => range(real(a.as_complex()), real(b.as_complex())),
+                // Functions of lists take the elements of lists as arguments
+                _ if is_list_function(f) => {
+                    let zs: Vec<Complex64> = values.iter().flat_map(Value::to_vec).collect();
+                    Value::Number(match functions::lookup(f) {
+                        Some(info) if info.arity.accepts(values.len()) => {
+                            info.numeric.as_ref().map_or(nan, |numeric| numeric(&zs))
+                        }
+                        _ => nan,
+                    })
+                }
+                _ => broadcast(&values, |zs| apply(f, zs, env)),
             }
         }
     }
 }
+
+/// `a op b`, in real arithmetic while that stays real, so rounding is as
+/// for `f64`, and in complex arithmetic otherwise.
+fn arithmetic(op: &BinaryOp, a: Complex64, b: Complex64) -> Complex64 {
+    if a.im == 0.0 && b.im == 0.0 {
+        let (x, y) = (a.re, b.re);
+        let value = match op {
+            BinaryOp::Add => x + y,
+            BinaryOp::Sub => x - y,
+            BinaryOp::Mul => x * y,
+            BinaryOp::Div => x / y,
+            BinaryOp::Pow => x.powf(y),
+        };
+        // A negative number to a fractional power leaves the real line
+        let left_reals = value.is_nan() && *op == BinaryOp::Pow && !x.is_nan() && !y.is_nan();
+        if !left_reals {
+            return value.into();
+        }
+    }
+    match op {
+        BinaryOp::Add => a + b,
+        BinaryOp::Sub => a - b,
+        BinaryOp::Mul => a * b,
+        BinaryOp::Div => a / b,
+        // Whole powers by multiplication, so that i^2 is exactly -1
+        BinaryOp::Pow if b.im == 0.0 && b.re.fract() == 0.0 && b.re.abs() <= i32::MAX as f64 => {
+            a.powi(b.re as i32)
+        }
+        BinaryOp::Pow => a.powc(b),
+    }
+}
+
+/// The integral of `e` over `var` from `a` to `b`, by the antiderivative if
+/// there is one and numerically otherwise, for the imaginary part only once
+/// the integrand leaves the real line.
+fn definite_integral(
+    e: &Expr,
+    antiderivative: Option<&Expr>,
+    var: &str,
+    a: f64,
+    b: f64,
+    env: &Environment,
+    locals: &HashMap<&str, Complex64>,
+) -> Complex64 {
+    let mut scope: HashMap<&str, Complex64> = locals.iter().map(|(k, v)| (*k, *v)).collect();
+    let mut at = |f: &Expr, x: f64| {
+        scope.insert(var, x.into());
+        eval_scoped(f, env, &scope).as_complex()
+    };
+    match antiderivative {
+        Some(f) => at(f, b) - at(f, a),
+        None => {
+            let mut complex = false;
+            let re = integrate_numeric(
+                |x| {
+                    let z = at(e, x);
+                    complex |= z.im != 0.0;
+                    z.re
+                },
+                a,
+                b,
+            );
+            let im = if complex {
+                integrate_numeric(|x| at(e, x).im, a, b)
+            } else {
+                0.0
+            };
+            Complex64::new(re, im)
+        }
+    }
+}
+
+fn holds_scoped(rel: &Relation, env: &Environment, locals: &HashMap<&str, Complex64>) -> bool {
+    let lhs = eval_scoped(&rel.lhs, env, locals).as_complex();
+    let rhs = eval_scoped(&rel.rhs, env, locals).as_complex();
+    // Equality compares complex numbers too, so `i = i` holds; only the
+    // ordering needs both sides real
+    let equal = lhs == rhs || real(lhs - rhs) == 0.0;
+    match rel.op {
+        RelOp::Eq => equal,
+        RelOp::Ne => !equal,
+        _ => rel.op.holds(real(lhs), real(rhs)),
+    }
+}
+
+/// `a, a + 1, ...` up to `b`, or NaN if that is not a list of at most
+/// `MAX_RANGE_LEN` numbers.
+fn range(a: f64, b: f64) -> Value<Complex64> {
+    let len = (b - a).floor() + 1.0;
+    if !len.is_finite() || len > MAX_RANGE_LEN as f64 {
+        return Value::Number(f64::NAN.into());
+    }
+    Value::List(
+        (0..len.max(0.0) as usize)
+            .map(|i| (a + i as f64).into())
+            .collect(),
+    )
+}
+
+/// Apply a function of numbers to `zs`: a built-in or registered one, see
+/// `functions`, or else a user function of `env`.
+fn apply(f: &str, zs: &[Complex64], env: &Environment) -> Complex64 {
+    if let Some(info) = functions::lookup(f) {
+        return match &info.numeric {
+            Some(numeric) if info.arity.accepts(zs.len()) => numeric(zs),
+            // Known function, wrong number of arguments
+            _ => f64::NAN.into(),
+        };
+    }
+    let def = match env.function(f) {
+        Some(def) => def.clone(),
+        None => match env.derivative_of(f) {
+            Some(def) => def,
+            None => return f64::NAN.into(),
+        },
+    };
+    if def.params.len() != zs.len() {
+        return f64::NAN.into();
+    }
+    let scope = def
+        .params
+        .iter()
+        .map(String::as_str)
+        .zip(zs.iter().copied())
+        .collect();
+    eval_scoped(&def.body, env, &scope).as_complex()
+}
+
+/// `real(x)` for a real `z` where that is defined, else `complex(z)`.
+pub fn lift(z: Complex64, real: fn(f64) -> f64, complex: fn(Complex64) -> Complex64) -> Complex64 {
+    if z.im == 0.0 {
+        let x = real(z.re);
+        if !x.is_nan() || z.re.is_nan() {
+            return x.into();
+        }
+    }
+    complex(z)
+}
+
+/// `f` of the real values of `zs`, or NaN if one of them is not real.
+pub fn real_only(zs: &[Complex64], f: impl Fn(&[f64]) -> f64) -> f64 {
+    let xs: Vec<f64> = zs.iter().map(|z| real(*z)).collect();
+    if xs.iter().zip(zs).any(|(x, z)| x.is_nan() && !z.re.is_nan()) {
+        return f64::NAN;
+    }
+    f(&xs)
+}
+
+/// Whether `rel` holds for the variable values in `env`.
+pub fn evaluate_relation(rel: &Relation, env: &Environment) -> bool {
+    holds_scoped(rel, env, &HashMap::new())
+}
+
+/// The branch each piecewise part of `expr` takes for the variable values
+/// in `env` (the number of branches for the fallback), in the order they
+/// occur. Where this changes between two points the value may jump.
+pub fn branches_taken(expr: &Expr, env: &Environment) -> Vec<usize> {
+    let mut taken = Vec::new();
+    collect_branches(expr, env, &mut taken);
+    taken
+}
+
+fn collect_branches(expr: &Expr, env: &Environment, taken: &mut Vec<usize>) {
+    match expr {
+        Expr::Number(_) | Expr::Variable(_) | Expr::Constant(_) | Expr::Hole(_) => {}
+        // Branches inside are taken over a whole range of the variable
+        Expr::Derivative(..) | Expr::Integral(..) => {}
+        Expr::UnaryOp(_, e) => collect_branches(e, env, taken),
+        Expr::BinaryOp(_, a, b) => {
+            collect_branches(a, env, taken);
+            collect_branches(b, env, taken);
+        }
+        Expr::Function(_, items) | Expr::List(items) => {
+            for e in items {
+                collect_branches(e, env, taken);
+            }
+        }
+        Expr::Piecewise(branches, otherwise) => {
+            let index = branches
+                .iter()
+                .position(|(_, cond)| evaluate_relation(cond, env))
+                .unwrap_or(branches.len());
+            taken.push(index);
+            for (value, _) in branches {
+                collect_branches(value, env, taken);
+            }
+            if let Some(fallback) = otherwise {
+                collect_branches(fallback, env, taken);
+            }
+        }
+    }
+}
+
+/// Real `n`th root, taking odd roots of negative numbers.
+pub fn nth_root(x: f64, n: f64) -> f64 {
+    if x < 0.0 && n.fract() == 0.0 && n.rem_euclid(2.0) == 1.0 {
+        -(-x).powf(1.0 / n)
+    } else {
+        x.powf(1.0 / n)
+    }
+}
+
+/// `x!`, as `gamma(x + 1)` for non-integers. Exact (as far as `f64` goes)
+/// for small non-negative integers, NaN for negative integers.
+pub fn factorial(x: f64) -> f64 {
+    if x.fract() == 0.0 && (0.0..=170.0).contains(&x) {
+        (1..=x as u32).map(f64::from).product()
+    } else if x.fract() == 0.0 && x < 0.0 {
+        f64::NAN
+    } else {
+        gamma(x + 1.0)
+    }
+}
+
+/// The gamma function by the Lanczos approximation (g = 7), reflected for
+/// `x < 1/2`.
+pub fn gamma(x: f64) -> f64 {
+    const G: f64 = 7.0;
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        // Reflection formula, gamma(x) gamma(1 - x) = pi / sin(pi x)
+        return std::f64::consts::PI / ((std::f64::consts::PI * x).sin() * gamma(1.0 - x));
+    }
+    let x = x - 1.0;
+    let t = x + G + 0.5;
+    let sum = COEFFICIENTS[1..]
+        .iter()
+        .enumerate()
+        .fold(COEFFICIENTS[0], |acc, (i, c)| {
+            acc + c / (x + i as f64 + 1.0)
+        });
+    (2.0 * std::f64::consts::PI).sqrt() * t.powf(x + 0.5) * (-t).exp() * sum
+}
@@ -0,0 +1,747 @@
+use crate::math::algebra::{fold_binary_ops, simplify};
+use crate::math::ast::*;
+use crate::math::poly::Poly;
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+
+/// Rational roots are only searched for when the constant term and the
+/// leading coefficient are at most this large; Zassenhaus finds the linear
+/// factors of the others.
+const MAX_ROOT_CANDIDATE: u64 = 1_000_000;
+
+/// Polynomials of a higher total degree, or with a coefficient of more
+/// bits, are left unfactored: `x^1000 - 1` would take minutes, and
+/// factoring runs on every keystroke.
+const MAX_FACTOR_DEGREE: u32 = 100;
+const MAX_FACTOR_BITS: u64 = 512;
+
+/// Subsets of the modular factors tried by Zassenhaus before what is left
+/// is kept as one factor. Recombination is exponential in the number of
+/// modular factors, as for `x^64 - 1` modulo a prime splitting it into 32.
+const MAX_RECOMBINATIONS: usize = 20_000;
+
+/// `expr` as a product of irreducible polynomials with whole coefficients
+/// and a rational constant, as `(x - 2) (x - 3)` for `x^2 - 5 x + 6`, or
+/// simplified if it is not a polynomial or no factor splits off.
+///
+/// Polynomials in one variable factor completely over the rationals, by
+/// the square-free decomposition, rational roots and Berlekamp–Zassenhaus.
+/// With several variables, contents, repeated factors and the factors of
+/// homogeneous polynomials in two variables come out, and the rest is left
+/// as it is.
+pub fn factor(expr: &Expr) -> Expr {
+    // Multiplying out `(x + 1)^1000` alone takes seconds
+    if degree_bound(expr) > u64::from(MAX_FACTOR_DEGREE) {
+        return simplify(expr);
+    }
+    let Some(p) = Poly::from_expr(expr) else {
+        return simplify(expr);
+    };
+    let (constant, factors) = factor_poly(&p);
+    // Nothing split off, so the input, maybe already partly factored as
+    // `(x^2 - y^2) (x + y + 1)`, is better than its expansion
+    if let [(_, 1)] = factors.as_slice() {
+        return simplify(expr);
+    }
+    let mut items: Vec<Expr> = factors
+        .into_iter()
+        .map(|(f, e)| match e {
+            1 => f.to_expr(),
+            _ => Expr::BinaryOp(
+                BinaryOp::Pow,
+                Box::new(f.to_expr()),
+                Box::new(Expr::Number(Real::from(e as i64))),
+            ),
+        })
+        .collect();
+    if items.is_empty() {
+        return Expr::Number(Real::Rational(constant));
+    }
+    if constant == -BigRational::one() {
+        items[0] = Expr::UnaryOp(UnaryOp::Neg, Box::new(items[0].clone()));
+    } else if !constant.is_one() {
+        items.insert(0, Expr::Number(Real::Rational(constant)));
+    }
+    fold_binary_ops(BinaryOp::Mul, items)
+}
+
+/// A bound on the total degree of `expr` as a polynomial, found without
+/// multiplying it out.
+fn degree_bound(expr: &Expr) -> u64 {
+    match expr {
+        Expr::Variable(_) => 1,
+        Expr::UnaryOp(_, e) => degree_bound(e),
+        Expr::BinaryOp(BinaryOp::Add | BinaryOp::Sub, a, b) => degree_bound(a).max(degree_bound(b)),
+        Expr::BinaryOp(BinaryOp::Mul, a, b) => degree_bound(a).saturating_add(degree_bound(b)),
+        Expr::BinaryOp(BinaryOp::Div, a, _) => degree_bound(a),
+        Expr::BinaryOp(BinaryOp::Pow, a, b) => match &**b {
+            Expr::Number(n) => n
+                .to_i64()
+                .and_then(|n| u64::try_from(n).ok())
+                .map_or(0, |n| degree_bound(a).saturating_mul(n)),
+            _ => 0,
+        },
+        _ => 0,
+    }
+}
+
+pub fn factor_statement(stmt: &Statement) -> Statement {
+    match stmt {
+        Statement::Expr(e) => Statement::Expr(factor(e)),
+        Statement::Relation(rel) => Statement::Relation(Relation {
+            op: rel.op,
+            lhs: factor(&rel.lhs),
+            rhs: factor(&rel.rhs),
+        }),
+        Statement::Assign(name, value) => Statement::Assign(name.clone(), factor(value)),
+        Statement::Define(name, params, body) => {
+            Statement::Define(name.clone(), params.clone(), factor(body))
+        }
+    }
+}
+
+/// The factors of `p` with their multiplicities, each with whole
+/// coefficients without a common factor and a positive leading number,
+/// and the constant that makes up the rest. Zero has no factors, and a
+/// polynomial past `MAX_FACTOR_DEGREE` or `MAX_FACTOR_BITS` is its only
+/// factor.
+pub fn factor_poly(p: &Poly) -> (BigRational, Vec<(Poly, u32)>) {
+    if p.is_zero() {
+        return (BigRational::zero(), Vec::new());
+    }
+    let parts = if p.total_degree() > MAX_FACTOR_DEGREE || p.coefficient_bits() > MAX_FACTOR_BITS {
+        vec![(p.clone(), 1)]
+    } else {
+        split(p)
+    };
+    let mut factors: Vec<(Poly, u32)> = Vec::new();
+    for (f, e) in parts {
+        let f = f.integer_primitive();
+        match factors.iter_mut().find(|(g, _)| *g == f) {
+            Some((_, n)) => *n += e,
+            None => factors.push((f, e)),
+        }
+    }
+    // Variables first, then by degree, and linear factors by their root, as
+    // `x (x - 2) (x - 3)`
+    factors.sort_by_cached_key(|(f, _)| {
+        let variable = f.total_degree() == 1
+            && f.vars().len() == 1
+            && f.coefficient(&f.vars()[0], 0).is_zero();
+        let root = match f.vars() {
+            [v] if f.degree(v) == 1 => {
+                Some(-f.coefficient(v, 0).leading_number() / f.leading_number())
+            }
+            _ => None,
+        };
+        (!variable, f.total_degree(), root, f.to_expr())
+    });
+    let lead = factors.iter().fold(BigRational::one(), |l, (f, e)| {
+        l * num_traits::Pow::pow(f.leading_number(), *e)
+    });
+    (p.leading_number() / lead, factors)
+}
+
+/// Factors of `p` and their multiplicities, not yet normalized, leaving out
+/// constants.
+fn split(p: &Poly) -> Vec<(Poly, u32)> {
+    let Some(var) = p.vars().first().cloned() else {
+        return Vec::new();
+    };
+    let (content, primitive) = p.content_and_primitive(&var);
+    let mut factors = split(&content);
+    for (a, n) in square_free(&primitive, &var) {
+        factors.extend(split_square_free(&a, &var).into_iter().map(|f| (f, n)));
+    }
+    factors
+}
+
+/// Yun's square-free decomposition of `f`, primitive in `var`: the
+/// `(a, n)` with `f` the product of the `a^n`, each `a` without repeated
+/// factors and coprime to the others.
+fn square_free(f: &Poly, var: &str) -> Vec<(Poly, u32)> {
+    let mut parts = Vec::new();
+    let df = f.derivative(var);
+    let g = f.gcd(&df);
+    let mut b = f.exact_div(&g).unwrap();
+    let mut d = &df.exact_div(&g).unwrap() - &b.derivative(var);
+    let mut n = 1;
+    while b.degree(var) > 0 {
+        let a = b.gcd(&d);
+        b = b.exact_div(&a).unwrap();
+        d = &d.exact_div(&a).unwrap() - &b.derivative(var);
+        if a.degree(var) > 0 {
+            parts.push((a, n));
+        }
+        n += 1;
+    }
+    parts
+}
+
+/// The factors of `f`, square-free and primitive in `var`.
+fn split_square_free(f: &Poly, var: &str) -> Vec<Poly> {
+    let f = f.integer_primitive();
+    match f.vars() {
+        [_] => {
+            let dense: Vec<BigInt> = f
+                .coefficients(var)
+                .iter()
+                .map(|c| c.to_constant().unwrap_or_default().to_integer())
+                .collect();
+            factor_square_free(dense)
+                .iter()
+                .map(|g| from_dense(g, var))
+                .collect()
+        }
+        // f(x, y) = y^n f(x/y, 1) for homogeneous f of degree n
+        [x, y] if homogeneous(&f) => {
+            let one = BigRational::one();
+            split_square_free(&f.substitute(y, &one), x)
+                .into_iter()
+                .map(|g| {
+                    let d = g.degree(x);
+                    (0..=d).fold(Poly::zero(), |h, i| {
+                        let term = &g.coefficient(x, i) * &Poly::monomial(x, i);
+                        &h + &(&term * &Poly::monomial(y, d - i))
+                    })
+                })
+                .collect()
+        }
+        _ => vec![f],
+    }
+}
+
+/// Whether all the terms of `f` have the same total degree.
+fn homogeneous(f: &Poly) -> bool {
+    let n = f.total_degree();
+    let [x, y] = f.vars() else {
+        return false;
+    };
+    // The coefficient of x^i has only a y^(n - i) term
+    (0..=f.degree(x)).all(|i| {
+        let c = f.coefficient(x, i);
+        c == &c.coefficient(y, n - i) * &Poly::monomial(y, n - i)
+    })
+}
+
+fn from_dense(f: &[BigInt], var: &str) -> Poly {
+    f.iter().enumerate().fold(Poly::zero(), |p, (i, c)| {
+        let c = Poly::constant(BigRational::from_integer(c.clone()));
+        &p + &(&c * &Poly::monomial(var, i as u32))
+    })
+}
+
+/// Dense polynomials with whole coefficients, the constant term first and
+/// without trailing zeros.
+type ZPoly = Vec<BigInt>;
+
+/// The irreducible factors over the integers of `f`, square-free and
+/// primitive.
+fn factor_square_free(f: ZPoly) -> Vec<ZPoly> {
+    let (mut factors, rest) = linear_factors(f);
+    if degree(&rest) > 1 {
+        factors.extend(zassenhaus(&rest));
+    } else if degree(&rest) == 1 {
+        factors.push(rest);
+    }
+    factors
+}
+
+fn degree(f: &[BigInt]) -> usize {
+    f.len().saturating_sub(1)
+}
+
+fn trim(mut f: ZPoly) -> ZPoly {
+    while f.last().is_some_and(|c| c.is_zero()) {
+        f.pop();
+    }
+    f
+}
+
+fn mul(a: &[BigInt], b: &[BigInt]) -> ZPoly {
+    if a.is_empty() || b.is_empty() {
+        return Vec::new();
+    }
+    let mut c = vec![BigInt::zero(); a.len() + b.len() - 1];
+    for (i, x) in a.iter().enumerate() {
+        for (j, y) in b.iter().enumerate() {
+            c[i + j] += x * y;
+        }
+    }
+    trim(c)
+}
+
+/// `f / g` when `g` divides `f` over the integers.
+fn exact_div(f: &[BigInt], g: &[BigInt]) -> Option<ZPoly> {
+    let mut r = f.to_vec();
+    let lead = g.last()?;
+    if r.len() < g.len() {
+        return r.is_empty().then(Vec::new);
+    }
+    let mut q = vec![BigInt::zero(); r.len() - g.len() + 1];
+    for k in (0..q.len()).rev() {
+        let c = &r[k + g.len() - 1];
+        if !c.is_multiple_of(lead) {
+            return None;
+        }
+        let c = c / lead;
+        for (j, gj) in g.iter().enumerate() {
+            r[k + j] -= &c * gj;
+        }
+        q[k] = c;
+    }
+    r.iter().all(|c| c.is_zero()).then(|| trim(q))
+}
+
+/// The positive divisors of `n`, unless it is too large to try them.
+fn divisors(n: &BigInt) -> Option<Vec<BigInt>> {
+    let n = n.abs().to_u64().filter(|&n| n <= MAX_ROOT_CANDIDATE)?;
+    Some(
+        (1..=n)
+            .take_while(|d| d * d <= n)
+            .filter(|d| n % d == 0)
+            .flat_map(|d| [d, n / d])
+            .map(BigInt::from)
+            .collect(),
+    )
+}
+
+/// The factors `q x - p` of `f` for its rational roots `p/q`, and what is
+/// left of `f`.
+fn linear_factors(mut f: ZPoly) -> (Vec<ZPoly>, ZPoly) {
+    let mut factors = Vec::new();
+    if f[0].is_zero() {
+        factors.push(vec![BigInt::zero(), BigInt::one()]);
+        f.remove(0);
+    }
+    let (Some(ps), Some(qs)) = (divisors(&f[0]), divisors(f.last().unwrap())) else {
+        return (factors, f);
+    };
+    for q in &qs {
+        for p in &ps {
+            for p in [p.clone(), -p] {
+                if degree(&f) == 0 || !p.gcd(q).is_one() {
+                    continue;
+                }
+                let candidate = vec![-p, q.clone()];
+                if let Some(rest) = exact_div(&f, &candidate) {
+                    factors.push(candidate);
+                    f = rest;
+                }
+            }
+        }
+    }
+    (factors, f)
+}
+
+/// Berlekamp–Zassenhaus: factor `f` modulo a prime, lift the factors
+/// to a power of the prime past the size of any factor's coefficients, and
+/// find the products of them that divide `f`, smallest first, for up to
+/// `MAX_RECOMBINATIONS` products.
+fn zassenhaus(f: &[BigInt]) -> Vec<ZPoly> {
+    let n = degree(f);
+    let lead = f[n].clone();
+    let (p, modular) = choose_prime(f);
+    if modular.len() == 1 {
+        return vec![f.to_vec()];
+    }
+
+    // Mignotte: a factor's coefficients are at most 2^n |f|_2 |lc| in size
+    let largest = f.iter().map(|c| c.abs()).max().unwrap();
+    let bound = (BigInt::one() << n) * BigInt::from(n + 1) * largest * lead.abs();
+    let prime = BigInt::from(p);
+    let (mut k, mut modulus) = (1, prime.clone());
+    while modulus <= &bound * 2 {
+        modulus *= &prime;
+        k += 1;
+    }
+    let mut lifted = hensel(f, &modular, p, k);
+
+    let mut factors = Vec::new();
+    let mut f = f.to_vec();
+    let mut size = 1;
+    let mut budget = MAX_RECOMBINATIONS;
+    'sizes: while 2 * size <= lifted.len() {
+        for subset in Combinations::new(lifted.len(), size) {
+            if budget == 0 {
+                break 'sizes;
+            }
+            budget -= 1;
+            let product = subset
+                .iter()
+                .fold(vec![f.last().unwrap().clone()], |g, &i| {
+                    reduce(&mul(&g, &lifted[i]), &modulus)
+                });
+            let g = primitive(product);
+            if let Some(rest) = exact_div(&f, &g) {
+                factors.push(g);
+                f = rest;
+                for &i in subset.iter().rev() {
+                    lifted.remove(i);
+                }
+                continue 'sizes;
+            }
+        }
+        size += 1;
+    }
+    factors.push(primitive(f));
+    factors
+}
+
+/// The subsets of `0..n` with `k` members, as ordered index lists in
+/// lexicographic order, made one at a time.
+struct Combinations {
+    n: usize,
+    next: Option<Vec<usize>>,
+}
+
+impl Combinations {
+    fn new(n: usize, k: usize) -> Self {
+        Combinations {
+            n,
+            next: (k <= n).then(|| (0..k).collect()),
+        }
+    }
+}
+
+impl Iterator for Combinations {
+    type Item = Vec<usize>;
+
+    fn next(&mut self) -> Option<Vec<usize>> {
+        let current = self.next.take()?;
+        // Advance the last index that can still move right, and put the
+        // ones after it just behind it
+        let k = current.len();
+        let mut following = current.clone();
+        if let Some(i) = (0..k).rev().find(|&i| following[i] < self.n - k + i) {
+            following[i] += 1;
+            for j in i + 1..k {
+                following[j] = following[j - 1] + 1;
+            }
+            self.next = Some(following);
+        }
+        Some(current)
+    }
+}
+
+/// `f` with coefficients in `(-m/2, m/2]`.
+fn reduce(f: &[BigInt], m: &BigInt) -> ZPoly {
+    let half = m / 2;
+    trim(
+        f.iter()
+            .map(|c| {
+                let c = c.mod_floor(m);
+                if c > half {
+                    c - m
+                } else {
+                    c
+                }
+            })
+            .collect(),
+    )
+}
+
+/// `f` over the gcd of its coefficients, with a positive leading one.
+fn primitive(f: ZPoly) -> ZPoly {
+    let g = f.iter().fold(BigInt::zero(), |g, c| g.gcd(c));
+    let g = if f.last().is_some_and(|c| c.is_negative()) {
+        -g
+    } else {
+        g
+    };
+    f.into_iter().map(|c| c / &g).collect()
+}
+
+/// A small prime not dividing the leading coefficient of `f` and keeping it
+/// square-free, with the factors of `f` modulo it. Of the first few such
+/// primes, the one giving the fewest factors is taken, to keep the
+/// recombination short.
+fn choose_prime(f: &[BigInt]) -> (u64, Vec<PPoly>) {
+    let mut best: Option<(u64, Vec<PPoly>)> = None;
+    let mut tried = 0;
+    for p in (3u64..).filter(|&p| (2..p).take_while(|d| d * d <= p).all(|d| p % d != 0)) {
+        let fp = to_p(f, p);
+        if degree_p(&fp) != degree(f) || degree_p(&gcd_p(&fp, &derivative_p(&fp, p), p)) > 0 {
+            continue;
+        }
+        let monic = scale_p(&fp, inverse(*fp.last().unwrap(), p), p);
+        let factors = berlekamp(&monic, p);
+        if best.as_ref().is_none_or(|(_, b)| factors.len() < b.len()) {
+            best = Some((p, factors));
+        }
+        tried += 1;
+        if tried == 3 {
+            break;
+        }
+    }
+    best.unwrap()
+}
+
+/// Lift the monic factors `factors` of `f` modulo `p` to monic factors
+/// modulo `p^k`, splitting off one factor at a time.
+fn hensel(f: &[BigInt], factors: &[PPoly], p: u64, k: u32) -> Vec<ZPoly> {
+    let modulus = num_traits::Pow::pow(BigInt::from(p), k);
+    let lead = f.last().unwrap();
+    let Some((g, rest)) = factors.split_first() else {
+        return Vec::new();
+    };
+    if rest.is_empty() {
+        let inverse = lead.extended_gcd(&modulus).x;
+        return vec![reduce(
+            &f.iter().map(|c| c * &inverse).collect::<Vec<_>>(),
+            &modulus,
+        )];
+    }
+    let h = rest
+        .iter()
+        .fold(to_p(std::slice::from_ref(lead), p), |h, r| mul_p(&h, r, p));
+    let (g, h) = hensel_step(f, g, &h, p, k);
+    let mut lifted = vec![g];
+    lifted.extend(hensel(&reduce(&h, &modulus), rest, p, k));
+    lifted
+}
+
+/// From `f = g h` modulo `p`, with `g` monic and coprime to `h`, the `g`
+/// and `h` with `f = g h` modulo `p^k`, one power of `p` at a time.
+fn hensel_step(f: &[BigInt], g0: &PPoly, h0: &PPoly, p: u64, k: u32) -> (ZPoly, ZPoly) {
+    let (s, t) = bezout_p(g0, h0, p);
+    let prime = BigInt::from(p);
+    let (mut g, mut h) = (from_p(g0), from_p(h0));
+    let mut m = prime.clone();
+    for _ in 1..k {
+        // f - g h = m e, and g dh + h dg = e modulo p keeps f = g h modulo m p
+        let gh = mul(&g, &h);
+        let difference: ZPoly = (0..f.len().max(gh.len()))
+            .map(|i| f.get(i).cloned().unwrap_or_default() - gh.get(i).cloned().unwrap_or_default())
+            .map(|c| c / &m)
+            .collect();
+        let e = to_p(&difference, p);
+        let (q, dg) = div_rem_p(&mul_p(&t, &e, p), g0, p);
+        let dh = add_p(&mul_p(&s, &e, p), &mul_p(&q, h0, p), p);
+        g = add_scaled(&g, &from_p(&dg), &m);
+        h = add_scaled(&h, &from_p(&dh), &m);
+        m *= &prime;
+    }
+    (g, h)
+}
+
+/// `a + m b`.
+fn add_scaled(a: &[BigInt], b: &[BigInt], m: &BigInt) -> ZPoly {
+    trim(
+        (0..a.len().max(b.len()))
+            .map(|i| {
+                a.get(i).cloned().unwrap_or_default() + m * b.get(i).cloned().unwrap_or_default()
+            })
+            .collect(),
+    )
+}
+
+/// Dense polynomials modulo a small prime, the constant term first and
+/// without trailing zeros.
+type PPoly = Vec<u64>;
+
+fn trim_p(mut f: PPoly) -> PPoly {
+    while f.last() == Some(&0) {
+        f.pop();
+    }
+    f
+}
+
+fn degree_p(f: &[u64]) -> usize {
+    f.len().saturating_sub(1)
+}
+
+fn to_p(f: &[BigInt], p: u64) -> PPoly {
+    let prime = BigInt::from(p);
+    trim_p(
+        f.iter()
+            .map(|c| c.mod_floor(&prime).to_u64().unwrap())
+            .collect(),
+    )
+}
+
+fn from_p(f: &[u64]) -> ZPoly {
+    f.iter().map(|&c| BigInt::from(c)).collect()
+}
+
+fn inverse(a: u64, p: u64) -> u64 {
+    // Fermat: a^(p - 2) a = 1
+    let (mut result, mut base, mut e) = (1, a % p, p - 2);
+    while e > 0 {
+        if e & 1 == 1 {
+            result = result * base % p;
+        }
+        base = base * base % p;
+        e >>= 1;
+    }
+    result
+}
+
+fn scale_p(f: &[u64], c: u64, p: u64) -> PPoly {
+    trim_p(f.iter().map(|x| x * c % p).collect())
+}
+
+fn add_p(a: &[u64], b: &[u64], p: u64) -> PPoly {
+    trim_p(
+        (0..a.len().max(b.len()))
+            .map(|i| (a.get(i).unwrap_or(&0) + b.get(i).unwrap_or(&0)) % p)
+            .collect(),
+    )
+}
+
+fn sub_p(a: &[u64], b: &[u64], p: u64) -> PPoly {
+    add_p(a, &scale_p(b, p - 1, p), p)
+}
+
+fn mul_p(a: &[u64], b: &[u64], p: u64) -> PPoly {
+    if a.is_empty() || b.is_empty() {
+        return Vec::new();
+    }
+    let mut c = vec![0; a.len() + b.len() - 1];
+    for (i, x) in a.iter().enumerate() {
+        for (j, y) in b.iter().enumerate() {
+            c[i + j] = (c[i + j] + x * y) % p;
+        }
+    }
+    trim_p(c)
+}
+
+fn div_rem_p(a: &[u64], b: &[u64], p: u64) -> (PPoly, PPoly) {
+    let mut r = a.to_vec();
+    if r.len() < b.len() {
+        return (Vec::new(), r);
+    }
+    let lead = inverse(*b.last().unwrap(), p);
+    let mut q = vec![0; r.len() - b.len() + 1];
+    for k in (0..q.len()).rev() {
+        let c = r[k + b.len() - 1] * lead % p;
+        for (j, bj) in b.iter().enumerate() {
+            r[k + j] = (r[k + j] + (p - c) * bj) % p;
+        }
+        q[k] = c;
+    }
+    (trim_p(q), trim_p(r))
+}
+
+fn derivative_p(f: &[u64], p: u64) -> PPoly {
+    trim_p(
+        f.iter()
+            .enumerate()
+            .skip(1)
+            .map(|(i, c)| (i as u64 % p) * c % p)
+            .collect(),
+    )
+}
+
+/// The monic gcd.
+fn gcd_p(a: &[u64], b: &[u64], p: u64) -> PPoly {
+    let (mut a, mut b) = (a.to_vec(), b.to_vec());
+    while !b.is_empty() {
+        let r = div_rem_p(&a, &b, p).1;
+        a = b;
+        b = r;
+    }
+    match a.last() {
+        Some(&c) => scale_p(&a, inverse(c, p), p),
+        None => a,
+    }
+}
+
+/// The `s` and `t` with `s a + t b = 1`, for coprime `a` and `b`.
+fn bezout_p(a: &[u64], b: &[u64], p: u64) -> (PPoly, PPoly) {
+    let (mut r0, mut r1) = (a.to_vec(), b.to_vec());
+    let (mut s0, mut s1) = (vec![1], Vec::new());
+    let (mut t0, mut t1) = (Vec::new(), vec![1]);
+    while !r1.is_empty() {
+        let (q, r) = div_rem_p(&r0, &r1, p);
+        let s = sub_p(&s0, &mul_p(&q, &s1, p), p);
+        let t = sub_p(&t0, &mul_p(&q, &t1, p), p);
+        (r0, r1) = (r1, r);
+        (s0, s1) = (s1, s);
+        (t0, t1) = (t1, t);
+    }
+    let c = inverse(r0[0], p);
+    (scale_p(&s0, c, p), scale_p(&t0, c, p))
+}
+
+/// Berlekamp's factorization of `f`, monic and square-free modulo `p`.
+/// The `g` with `g^p = g` modulo `f` form a space with one dimension per
+/// irreducible factor, and `gcd(f, g - s)` for the constants `s` splits
+/// the factors apart.
+fn berlekamp(f: &[u64], p: u64) -> Vec<PPoly> {
+    let n = degree_p(f);
+
+    // Row i of Q is x^(i p) modulo f
+    let xp = (0..p).fold(vec![1], |acc, _| {
+        div_rem_p(&mul_p(&acc, &[0, 1], p), f, p).1
+    });
+    let mut rows = vec![vec![1]];
+    for i in 1..n {
+        rows.push(div_rem_p(&mul_p(&rows[i - 1], &xp, p), f, p).1);
+    }
+
+    // The v with v (Q - I) = 0, by row reduction of the transpose
+    let mut a: Vec<Vec<u64>> = (0..n)
+        .map(|j| {
+            (0..n)
+                .map(|i| {
+                    let q = rows[i].get(j).copied().unwrap_or(0);
+                    (q + if i == j { p - 1 } else { 0 }) % p
+                })
+                .collect()
+        })
+        .collect();
+    let mut pivots = Vec::new();
+    let mut row = 0;
+    for col in 0..n {
+        let Some(r) = (row..n).find(|&r| a[r][col] != 0) else {
+            continue;
+        };
+        a.swap(row, r);
+        let c = inverse(a[row][col], p);
+        a[row] = a[row].iter().map(|x| x * c % p).collect();
+        for r in 0..n {
+            if r != row && a[r][col] != 0 {
+                let c = a[r][col];
+                a[r] = (0..n)
+                    .map(|j| (a[r][j] + (p - c) * a[row][j]) % p)
+                    .collect();
+            }
+        }
+        pivots.push(col);
+        row += 1;
+    }
+    let basis: Vec<PPoly> = (0..n)
+        .filter(|col| !pivots.contains(col))
+        .map(|free| {
+            let mut v = vec![0; n];
+            v[free] = 1;
+            for (r, &col) in pivots.iter().enumerate() {
+                v[col] = (p - a[r][free]) % p;
+            }
+            trim_p(v)
+        })
+        .collect();
+
+    let mut factors = vec![f.to_vec()];
+    for v in basis.iter().filter(|v| degree_p(v) > 0) {
+        if factors.len() == basis.len() {
+            break;
+        }
+        factors = factors
+            .into_iter()
+            .flat_map(|u| {
+                if degree_p(&u) <= 1 {
+                    return vec![u];
+                }
+                (0..p)
+                    .map(|s| gcd_p(&u, &sub_p(v, &[s], p), p))
+                    .filter(|g| degree_p(g) > 0)
+                    .collect()
+            })
+            .collect();
+    }
+    factors
+}
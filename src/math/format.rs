@@ -1,65 +1,359 @@
 use crate::math::ast::*;
+use crate::math::functions;
 pub fn format_expr_latex(expr: &Expr) -> String {
     match expr {
-        Expr::Number(n) => {
-            // Extract inner f64
-            let val = n.0;
-            if val.fract() == 0.0 {
-                format!("{}", val as i64)
-            } else {
-                format!("{}", val)
-            }
+        // Fractions that would not terminate as decimals, such as 1/3
+        Expr::Number(n @ Real::Rational(r)) if !n.is_terminating() => format!(
+            "{}\\frac{{{}}}{{{}}}",
+            if n.is_negative() { "-" } else { "" },
+            r.numer().magnitude(),
+            r.denom()
+        ),
+        Expr::Number(n) => n.to_string(),
+        Expr::Variable(v) => format_name_latex(v),
+        Expr::Hole(Hole::Missing) => "\\square".to_string(),
+        Expr::Hole(Hole::Error) => "\\text{?}".to_string(),
+        Expr::Constant(c) => match c {
+            Constant::Pi => "\\pi",
+            Constant::E => "e",
+            Constant::Tau => "\\tau",
+            Constant::Phi => "\\varphi",
+            Constant::Infinity => "\\infty",
+            Constant::I => "i",
+        }
+        .to_string(),
+        Expr::UnaryOp(UnaryOp::Neg, e) => {
+            let wrap = latex_precedence(e) < NEG_PRECEDENCE || is_negative(e);
+            format!("-{}", latex_paren(e, wrap))
+        }
+        Expr::UnaryOp(op, e) => {
+            let wrap = e.precedence() < POSTFIX_PRECEDENCE || is_negative(e);
+            let symbol = if *op == UnaryOp::Percent { "\\%" } else { "!" };
+            format!("{}{}", latex_paren(e, wrap), symbol)
         }
-        Expr::Variable(v) => v.clone(),
-        Expr::UnaryOp(UnaryOp::Neg, e) => format!("-{}", format_expr_latex(e)),
         Expr::BinaryOp(op, a, b) => match op {
-            BinaryOp::Add => format!("{} + {}", format_expr_latex(a), format_expr_latex(b)),
-            BinaryOp::Sub => format!("{} - {}", format_expr_latex(a), format_expr_latex(b)),
-            BinaryOp::Mul => format!("{} {}", format_expr_latex(a), format_expr_latex(b)),
+            BinaryOp::Add | BinaryOp::Sub => {
+                // Display a + (-b) as a - b
+                let negated;
+                let (sign, rhs) = match (op, &**b) {
+                    (BinaryOp::Add, Expr::UnaryOp(UnaryOp::Neg, inner)) => ("-", &**inner),
+                    (BinaryOp::Add, Expr::Number(n)) if n.is_negative() => {
+                        negated = Expr::Number(-n);
+                        ("-", &negated)
+                    }
+                    // And a + (-2) b, such as the imaginary part of 1 - 2i
+                    (BinaryOp::Add, Expr::BinaryOp(BinaryOp::Mul, k, rest)) if is_negative(k) => {
+                        negated = Expr::BinaryOp(BinaryOp::Mul, Box::new(negate(k)), rest.clone());
+                        ("-", &negated)
+                    }
+                    (BinaryOp::Add, _) => ("+", &**b),
+                    _ => ("-", &**b),
+                };
+                let wrap = latex_precedence(rhs) <= op.precedence() || is_negative(rhs);
+                format!(
+                    "{} {} {}",
+                    format_expr_latex(a),
+                    sign,
+                    latex_paren(rhs, wrap)
+                )
+            }
+            BinaryOp::Mul => {
+                let lhs = latex_paren(a, latex_precedence(a) < op.precedence());
+                let wrap = latex_precedence(b) <= op.precedence() || is_negative(b);
+                let rhs = latex_paren(b, wrap);
+                // Juxtaposed digits would read as one number
+                if rhs.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+                    format!("{} \\cdot {}", lhs, rhs)
+                } else if matches!((&**a, &**b), (Expr::Number(_), Expr::Constant(Constant::I))) {
+                    // The imaginary part of a + bi
+                    format!("{}i", lhs)
+                } else {
+                    format!("{} {}", lhs, rhs)
+                }
+            }
             BinaryOp::Div => format!(
                 "\\frac{{{}}}{{{}}}",
                 format_expr_latex(a),
                 format_expr_latex(b)
             ),
-            BinaryOp::Pow => format!("{}^{{{}}}", format_expr_latex(a), format_expr_latex(b)),
+            BinaryOp::Pow => {
+                let wrap = a.precedence() <= op.precedence();
+                format!("{}^{{{}}}", latex_paren(a, wrap), format_expr_latex(b))
+            }
         },
-        Expr::Function(name, arg) => {
-            let latex_name = match name.as_str() {
-                "sin" => "\\sin",
-                "cos" => "\\cos",
-                "tan" => "\\tan",
-                "log" => "\\log",
-                "exp" => "\\exp",
-                "abs" => "\\left|",
-                _ => name,
+        Expr::List(items) => {
+            let items: Vec<String> = items.iter().map(format_expr_latex).collect();
+            format!("\\left[{}\\right]", items.join(", "))
+        }
+        Expr::Piecewise(branches, otherwise) => {
+            let mut rows: Vec<String> = branches
+                .iter()
+                .map(|(value, cond)| {
+                    format!(
+                        "{} & {}",
+                        format_expr_latex(value),
+                        format_relation_latex(cond)
+                    )
+                })
+                .collect();
+            if let Some(fallback) = otherwise {
+                rows.push(format!(
+                    "{} & \\text{{otherwise}}",
+                    format_expr_latex(fallback)
+                ));
+            }
+            format!("\\begin{{cases}} {} \\end{{cases}}", rows.join(" \\\\ "))
+        }
+        Expr::Derivative(e, var, 1) => format!(
+            "\\frac{{d}}{{d{}}}\\left({}\\right)",
+            format_name_latex(var),
+            format_expr_latex(e)
+        ),
+        Expr::Derivative(e, var, order) => format!(
+            "\\frac{{d^{{{}}}}}{{d{}^{{{}}}}}\\left({}\\right)",
+            order,
+            format_name_latex(var),
+            order,
+            format_expr_latex(e)
+        ),
+        Expr::Integral(e, var, bounds) => {
+            let limits = match bounds {
+                Some(bounds) => format!(
+                    "_{{{}}}^{{{}}}",
+                    format_expr_latex(&bounds.0),
+                    format_expr_latex(&bounds.1)
+                ),
+                None => String::new(),
             };
-            if name == "abs" {
-                format!("{}{}\\right|", latex_name, format_expr_latex(arg))
-            } else {
-                format!("{}\\left({}\\right)", latex_name, format_expr_latex(arg))
+            format!(
+                "\\int{} {} \\, d{}",
+                limits,
+                format_expr_latex(e),
+                format_name_latex(var)
+            )
+        }
+        Expr::Function(name, args) => {
+            let f = functions::lookup(name);
+            if let Some(layout) = f.as_ref().and_then(|f| f.latex.as_ref()?(args)) {
+                return layout;
             }
+            let latex_name = match f.as_ref().map(|f| &f.latex_command) {
+                Some(Some(command)) => command.clone(),
+                Some(None) => format!("\\operatorname{{{}}}", name),
+                // A user function is named like a variable, unless its name
+                // is a word such as `sinh`
+                None if is_word(name) => {
+                    format!("\\operatorname{{{}}}", name.replace('_', "\\_"))
+                }
+                None => format_name_latex(name),
+            };
+            let latex_args = args
+                .iter()
+                .map(format_expr_latex)
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("{}\\left({}\\right)", latex_name, latex_args)
+        }
+    }
+}
+
+pub fn format_relation_latex(rel: &Relation) -> String {
+    let op = match rel.op {
+        RelOp::Eq => "=",
+        RelOp::Ne => "\\neq",
+        RelOp::Lt => "<",
+        RelOp::Le => "\\leq",
+        RelOp::Gt => ">",
+        RelOp::Ge => "\\geq",
+    };
+    format!(
+        "{} {} {}",
+        format_expr_latex(&rel.lhs),
+        op,
+        format_expr_latex(&rel.rhs)
+    )
+}
+
+pub fn format_statement_latex(stmt: &Statement) -> String {
+    match stmt {
+        Statement::Expr(e) => format_expr_latex(e),
+        Statement::Relation(rel) => format_relation_latex(rel),
+        Statement::Assign(name, value) => {
+            format!("{} = {}", format_name_latex(name), format_expr_latex(value))
         }
+        Statement::Define(name, params, body) => format!(
+            "{}\\left({}\\right) = {}",
+            format_name_latex(name),
+            params
+                .iter()
+                .map(|p| format_name_latex(p))
+                .collect::<Vec<_>>()
+                .join(", "),
+            format_expr_latex(body)
+        ),
     }
 }
 
 pub fn format_expr(expr: &Expr) -> String {
     match expr {
-        Expr::Number(n) => {
-            let val = n.0;
-            val.to_string()
-        }
+        Expr::Number(n) => n.to_string(),
         Expr::Variable(v) => v.clone(),
-        Expr::UnaryOp(UnaryOp::Neg, e) => format!("-{}", format_expr(e)),
+        Expr::Hole(_) => "?".to_string(),
+        Expr::Constant(c) => c.name().to_string(),
+        Expr::UnaryOp(UnaryOp::Neg, e) => {
+            format!(
+                "-{}",
+                paren(format_expr(e), e.precedence() < NEG_PRECEDENCE)
+            )
+        }
+        Expr::UnaryOp(op, e) => {
+            let symbol = if *op == UnaryOp::Percent { '%' } else { '!' };
+            format!(
+                "{}{}",
+                paren(format_expr(e), e.precedence() < POSTFIX_PRECEDENCE),
+                symbol
+            )
+        }
         Expr::BinaryOp(op, a, b) => {
-            let op_str = match op {
-                BinaryOp::Add => "+",
-                BinaryOp::Sub => "-",
-                BinaryOp::Mul => "*",
-                BinaryOp::Div => "/",
-                BinaryOp::Pow => "^",
+            let prec = op.precedence();
+            let (wrap_a, wrap_b) = match op.assoc() {
+                Assoc::Left => (a.precedence() < prec, b.precedence() <= prec),
+                Assoc::Right => (a.precedence() <= prec, b.precedence() < prec),
             };
-            format!("({} {} {})", format_expr(a), op_str, format_expr(b))
+            format!(
+                "{} {} {}",
+                paren(format_expr(a), wrap_a),
+                op.symbol(),
+                paren(format_expr(b), wrap_b)
+            )
+        }
+        Expr::List(items) => {
+            let items: Vec<String> = items.iter().map(format_expr).collect();
+            format!("[{}]", items.join(", "))
+        }
+        Expr::Piecewise(branches, otherwise) => {
+            let branches: Vec<String> = branches
+                .iter()
+                .map(|(value, cond)| {
+                    let nested = matches!(value, Expr::Piecewise(..));
+                    format!(
+                        "{} if {}",
+                        paren(format_expr(value), nested),
+                        format_relation(cond)
+                    )
+                })
+                .collect();
+            match otherwise {
+                Some(fallback) => {
+                    format!("{} else {}", branches.join(" else "), format_expr(fallback))
+                }
+                None => branches.join(" else "),
+            }
+        }
+        Expr::Derivative(e, var, 1) => format!("diff({}, {})", format_expr(e), var),
+        Expr::Derivative(e, var, order) => {
+            format!("diff({}, {}, {})", format_expr(e), var, order)
+        }
+        Expr::Integral(e, var, None) => format!("int({}, {})", format_expr(e), var),
+        Expr::Integral(e, var, Some(bounds)) => format!(
+            "int({}, {}, {}, {})",
+            format_expr(e),
+            var,
+            format_expr(&bounds.0),
+            format_expr(&bounds.1)
+        ),
+        Expr::Function(name, args) => {
+            let f = functions::lookup(name);
+            if let Some(layout) = f.as_ref().and_then(|f| f.format.as_ref()?(args)) {
+                return layout;
+            }
+            let args: Vec<String> = args.iter().map(format_expr).collect();
+            format!("{}({})", name, args.join(", "))
+        }
+    }
+}
+
+/// Whether `name` is a word rather than a letter, Greek or not, with a
+/// subscript.
+fn is_word(name: &str) -> bool {
+    let symbol = Symbol::parse(name);
+    symbol.base.chars().count() > 1 && !GREEK_LETTERS.contains(&symbol.base)
+}
+
+/// A variable or user function name, with Greek letters as commands and
+/// the subscript lowered: `theta_max` is `\theta_{max}`.
+fn format_name_latex(name: &str) -> String {
+    let symbol = Symbol::parse(name);
+    let base = if GREEK_LETTERS.contains(&symbol.base) {
+        format!("\\{}", symbol.base)
+    } else {
+        symbol.base.to_string()
+    };
+    match symbol.subscript {
+        Some(sub) => format!("{}_{{{}}}", base, sub.replace('_', "\\_")),
+        None => base,
+    }
+}
+
+/// Precedence as it reads in LaTeX output, where `\\frac{..}{..}` is
+/// self-delimiting and never needs parentheses as an operand.
+fn latex_precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::BinaryOp(BinaryOp::Div, _, _) => ATOM_PRECEDENCE,
+        Expr::Number(n) if !n.is_negative() => ATOM_PRECEDENCE,
+        _ => expr.precedence(),
+    }
+}
+
+fn is_negative(expr: &Expr) -> bool {
+    match expr {
+        Expr::UnaryOp(UnaryOp::Neg, _) => true,
+        Expr::Number(n) => n.is_negative(),
+        _ => false,
+    }
+}
+
+/// The negation of a negative number or `-e`.
+fn negate(expr: &Expr) -> Expr {
+    match expr {
+        Expr::Number(n) => Expr::Number(-n),
+        Expr::UnaryOp(UnaryOp::Neg, e) => (**e).clone(),
+        _ => Expr::UnaryOp(UnaryOp::Neg, Box::new(expr.clone())),
+    }
+}
+
+fn latex_paren(expr: &Expr, wrap: bool) -> String {
+    if wrap {
+        format!("\\left({}\\right)", format_expr_latex(expr))
+    } else {
+        format_expr_latex(expr)
+    }
+}
+
+fn paren(s: String, wrap: bool) -> String {
+    if wrap {
+        format!("({})", s)
+    } else {
+        s
+    }
+}
+
+pub fn format_relation(rel: &Relation) -> String {
+    format!(
+        "{} {} {}",
+        format_expr(&rel.lhs),
+        rel.op.symbol(),
+        format_expr(&rel.rhs)
+    )
+}
+
+pub fn format_statement(stmt: &Statement) -> String {
+    match stmt {
+        Statement::Expr(e) => format_expr(e),
+        Statement::Relation(rel) => format_relation(rel),
+        Statement::Assign(name, value) => format!("{} = {}", name, format_expr(value)),
+        Statement::Define(name, params, body) => {
+            format!("{}({}) = {}", name, params.join(", "), format_expr(body))
         }
-        Expr::Function(name, arg) => format!("{}({})", name, format_expr(arg)),
     }
 }
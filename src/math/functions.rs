@@ -0,0 +1,614 @@
+use crate::math::algebra::{
+    exact_root, fold_binary_ops, imaginary_coefficient, pi_multiple, simplify,
+};
+use crate::math::ast::*;
+use crate::math::diff::differentiate;
+use crate::math::eval::{lift, nth_root, real_only, Complex64, MAX_RANGE_LEN};
+use crate::math::format::{format_expr, format_expr_latex};
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, RwLock};
+
+/// The value at numeric arguments, with the arguments and the result
+/// complex so that functions may leave the real line, as `sqrt(-1)` does.
+pub type NumericFn = Arc<dyn Fn(&[Complex64]) -> Complex64 + Send + Sync>;
+/// Whether the function is real and defined at real arguments.
+pub type DomainFn = Arc<dyn Fn(&[f64]) -> bool + Send + Sync>;
+/// The derivative of the call with these arguments with respect to a
+/// variable, chain rule included, or `None` for no rule.
+pub type DerivativeFn = Arc<dyn Fn(&[Expr], &str) -> Option<Expr> + Send + Sync>;
+/// An exact value at special (simplified) arguments, such as `sin(pi) = 0`.
+pub type ExactFn = Arc<dyn Fn(&[Expr]) -> Option<Expr> + Send + Sync>;
+/// A LaTeX layout of the call, or `None` for the usual `\name\left(..\right)`.
+pub type LatexFn = Arc<dyn Fn(&[Expr]) -> Option<String> + Send + Sync>;
+/// A plain-text layout of the call, or `None` for the usual `name(..)`.
+pub type FormatFn = Arc<dyn Fn(&[Expr]) -> Option<String> + Send + Sync>;
+
+/// Everything the parsers, evaluator, simplifier, differentiator and
+/// formatters know about a function. The built-in functions are described
+/// this way, and `register` adds more; calls stay `Expr::Function(name,
+/// args)`, with the name looked up here.
+#[derive(Clone)]
+pub struct FunctionInfo {
+    pub name: String,
+    pub arity: Arity,
+    /// Takes lists as a whole (`sum([1, 2])`) or builds them (`range`),
+    /// rather than applying element-wise like `sin`.
+    pub takes_lists: bool,
+    pub numeric: Option<NumericFn>,
+    /// Everywhere when `None`.
+    pub domain: Option<DomainFn>,
+    pub derivative: Option<DerivativeFn>,
+    pub exact: Option<ExactFn>,
+    /// The function `f^-1` stands for, as `asin` for `sin`.
+    pub inverse: Option<String>,
+    /// A layout of its own, such as `a..b`, for `format_expr`.
+    pub format: Option<FormatFn>,
+    /// The command for the name, such as `\sin`, or `\operatorname{..}`
+    /// when `None`.
+    pub latex_command: Option<String>,
+    /// A layout of its own, such as `\sqrt{x}`, taking over from the command.
+    pub latex: Option<LatexFn>,
+}
+
+impl FunctionInfo {
+    /// A function known only by name and arity; the builder methods below
+    /// fill in the rest.
+    pub fn new(name: &str, arity: Arity) -> Self {
+        FunctionInfo {
+            name: name.to_string(),
+            arity,
+            takes_lists: false,
+            numeric: None,
+            domain: None,
+            derivative: None,
+            exact: None,
+            inverse: None,
+            format: None,
+            latex_command: None,
+            latex: None,
+        }
+    }
+
+    pub fn takes_lists(mut self) -> Self {
+        self.takes_lists = true;
+        self
+    }
+
+    pub fn numeric(
+        mut self,
+        f: impl Fn(&[Complex64]) -> Complex64 + Send + Sync + 'static,
+    ) -> Self {
+        self.numeric = Some(Arc::new(f));
+        self
+    }
+
+    /// A numeric implementation on the real line, NaN off it.
+    pub fn real(self, f: impl Fn(&[f64]) -> f64 + Send + Sync + 'static) -> Self {
+        self.numeric(move |zs| real_only(zs, &f).into())
+    }
+
+    pub fn domain(mut self, f: impl Fn(&[f64]) -> bool + Send + Sync + 'static) -> Self {
+        self.domain = Some(Arc::new(f));
+        self
+    }
+
+    pub fn derivative(
+        mut self,
+        f: impl Fn(&[Expr], &str) -> Option<Expr> + Send + Sync + 'static,
+    ) -> Self {
+        self.derivative = Some(Arc::new(f));
+        self
+    }
+
+    /// The derivative of a function of one argument `u` as `f'(u)`, which
+    /// the chain rule multiplies by `u'`.
+    pub fn derivative_of_arg(self, f: impl Fn(&Expr) -> Expr + Send + Sync + 'static) -> Self {
+        self.derivative(move |args, var| match args {
+            [u] => Some(mul(f(u), differentiate(u, var))),
+            _ => None,
+        })
+    }
+
+    pub fn exact(mut self, f: impl Fn(&[Expr]) -> Option<Expr> + Send + Sync + 'static) -> Self {
+        self.exact = Some(Arc::new(f));
+        self
+    }
+
+    pub fn inverse(mut self, name: &str) -> Self {
+        self.inverse = Some(name.to_string());
+        self
+    }
+
+    pub fn format(mut self, f: impl Fn(&[Expr]) -> Option<String> + Send + Sync + 'static) -> Self {
+        self.format = Some(Arc::new(f));
+        self
+    }
+
+    pub fn latex_command(mut self, command: &str) -> Self {
+        self.latex_command = Some(command.to_string());
+        self
+    }
+
+    pub fn latex(mut self, f: impl Fn(&[Expr]) -> Option<String> + Send + Sync + 'static) -> Self {
+        self.latex = Some(Arc::new(f));
+        self
+    }
+
+    /// Whether the arguments are in the domain, checking the arity too.
+    pub fn defined_at(&self, xs: &[f64]) -> bool {
+        self.arity.accepts(xs.len()) && self.domain.as_ref().is_none_or(|d| d(xs))
+    }
+}
+
+static REGISTRY: LazyLock<RwLock<HashMap<String, Arc<FunctionInfo>>>> = LazyLock::new(|| {
+    RwLock::new(
+        builtins()
+            .into_iter()
+            .map(|f| (f.name.clone(), Arc::new(f)))
+            .collect(),
+    )
+});
+
+/// Add a function, or replace the one of the same name, for every
+/// subsystem from now on.
+pub fn register(info: FunctionInfo) {
+    let mut registry = REGISTRY.write().unwrap_or_else(|e| e.into_inner());
+    registry.insert(info.name.clone(), Arc::new(info));
+}
+
+/// The function called `name`, built in or registered.
+pub fn lookup(name: &str) -> Option<Arc<FunctionInfo>> {
+    let registry = REGISTRY.read().unwrap_or_else(|e| e.into_inner());
+    registry.get(name).cloned()
+}
+
+/// The function a LaTeX command such as `\arcsin` (without the backslash)
+/// stands for.
+pub fn from_latex_command(command: &str) -> Option<String> {
+    let registry = REGISTRY.read().unwrap_or_else(|e| e.into_inner());
+    let command = format!("\\{}", command);
+    registry
+        .values()
+        .find(|f| f.latex_command.as_deref() == Some(command.as_str()))
+        .map(|f| f.name.clone())
+}
+
+fn builtins() -> Vec<FunctionInfo> {
+    let one = Arity::Exact(1);
+    vec![
+        FunctionInfo::new("sin", one)
+            .numeric(|zs| lift(zs[0], f64::sin, Complex64::sin))
+            .derivative_of_arg(|u| call("cos", u))
+            .exact(|args| exact_trig("sin", args))
+            .inverse("asin")
+            .latex_command("\\sin"),
+        FunctionInfo::new("cos", one)
+            .numeric(|zs| lift(zs[0], f64::cos, Complex64::cos))
+            .derivative_of_arg(|u| neg(call("sin", u)))
+            .exact(|args| exact_trig("cos", args))
+            .inverse("acos")
+            .latex_command("\\cos"),
+        FunctionInfo::new("tan", one)
+            .numeric(|zs| lift(zs[0], f64::tan, Complex64::tan))
+            .derivative(over(|u| square(&call("cos", u))))
+            .exact(|args| exact_trig("tan", args))
+            .inverse("atan")
+            .latex_command("\\tan"),
+        // d/dx asin(u) = u' / sqrt(1 - u^2), and acos is its negation
+        FunctionInfo::new("asin", one)
+            .numeric(|zs| lift(zs[0], f64::asin, Complex64::asin))
+            .domain(|xs| xs[0].abs() <= 1.0)
+            .derivative(over(sqrt_one_minus_square))
+            .exact(|args| at_number(args, 0, 0))
+            .latex_command("\\arcsin"),
+        FunctionInfo::new("acos", one)
+            .numeric(|zs| lift(zs[0], f64::acos, Complex64::acos))
+            .domain(|xs| xs[0].abs() <= 1.0)
+            .derivative(|args, var| Some(neg(over(sqrt_one_minus_square)(args, var)?)))
+            .exact(|args| at_number(args, 1, 0))
+            .latex_command("\\arccos"),
+        FunctionInfo::new("atan", one)
+            .numeric(|zs| lift(zs[0], f64::atan, Complex64::atan))
+            .derivative(over(|u| add(number(1), square(u))))
+            .exact(|args| at_number(args, 0, 0))
+            .latex_command("\\arctan"),
+        FunctionInfo::new("exp", one)
+            .numeric(|zs| lift(zs[0], f64::exp, Complex64::exp))
+            .derivative_of_arg(|u| call("exp", u))
+            .exact(|args| match args {
+                [Expr::Number(n)] if n.is_zero() => Some(number(1)),
+                [Expr::Number(n)] if n.is_one() => Some(Expr::Constant(Constant::E)),
+                [arg] => euler(arg),
+                _ => None,
+            })
+            .latex_command("\\exp"),
+        FunctionInfo::new("ln", one)
+            .numeric(|zs| lift(zs[0], f64::ln, Complex64::ln))
+            .domain(|xs| xs[0] > 0.0)
+            .derivative(over(Expr::clone))
+            .exact(|args| match args {
+                [Expr::Constant(Constant::E)] => Some(number(1)),
+                _ => at_number(args, 1, 0),
+            })
+            .latex_command("\\ln"),
+        // `log(x)` is the natural logarithm, `log(b, x)` the base-`b` one
+        FunctionInfo::new("log", Arity::Range(1, 2))
+            .numeric(|zs| match zs {
+                [z] => lift(*z, f64::ln, Complex64::ln),
+                [base, z] => match real_only(zs, |xs| xs[1].log(xs[0])) {
+                    x if x.is_nan() => z.ln() / base.ln(),
+                    x => x.into(),
+                },
+                _ => f64::NAN.into(),
+            })
+            .domain(|xs| match xs {
+                [x] => *x > 0.0,
+                [b, x] => *b > 0.0 && *b != 1.0 && *x > 0.0,
+                _ => false,
+            })
+            .derivative(|args, var| match args {
+                [u] => Some(div(differentiate(u, var), u.clone())),
+                // log_b(x) = ln(x) / ln(b)
+                [base, u] => Some(differentiate(&div(call("log", u), call("log", base)), var)),
+                _ => None,
+            })
+            .exact(|args| match args {
+                [Expr::Constant(Constant::E)] => Some(number(1)),
+                [base, arg] if base == arg => Some(number(1)),
+                [_, arg] => at_number(std::slice::from_ref(arg), 1, 0),
+                _ => at_number(args, 1, 0),
+            })
+            .latex_command("\\log")
+            .latex(|args| match args {
+                [base, arg] => Some(format!(
+                    "\\log_{{{}}}\\left({}\\right)",
+                    format_expr_latex(base),
+                    format_expr_latex(arg)
+                )),
+                _ => None,
+            }),
+        FunctionInfo::new("sqrt", one)
+            .numeric(|zs| lift(zs[0], f64::sqrt, Complex64::sqrt))
+            .domain(|xs| xs[0] >= 0.0)
+            .derivative(over(|u| mul(number(2), call("sqrt", u))))
+            .exact(|args| match args {
+                // sqrt(-x) = sqrt(x) i
+                [Expr::Number(x)] if x.to_f64() < 0.0 => Some(simplify(&mul(
+                    call("sqrt", &Expr::Number(-x)),
+                    Expr::Constant(Constant::I),
+                ))),
+                // Perfect squares only; sqrt(2) stays as it is
+                [Expr::Number(x)] => exact_root(x, &Real::from(2)),
+                _ => None,
+            })
+            .latex(|args| match args {
+                [arg] => Some(format!("\\sqrt{{{}}}", format_expr_latex(arg))),
+                _ => None,
+            }),
+        // d/dx |u| = u u' / |u|
+        FunctionInfo::new("abs", one)
+            .numeric(|zs| zs[0].norm().into())
+            .derivative(|args, var| match args {
+                [u] => Some(div(mul(u.clone(), differentiate(u, var)), call("abs", u))),
+                _ => None,
+            })
+            .exact(|args| match args {
+                [Expr::Number(n)] => Some(Expr::Number(n.abs())),
+                _ => None,
+            })
+            .latex(|args| match args {
+                [arg] => Some(format!("\\left|{}\\right|", format_expr_latex(arg))),
+                _ => None,
+            }),
+        // d/dx atan2(y, x) = (x y' - y x') / (x^2 + y^2)
+        FunctionInfo::new("atan2", Arity::Exact(2))
+            .real(|xs| xs[0].atan2(xs[1]))
+            .derivative(|args, var| match args {
+                [y, x] => Some(div(
+                    sub(
+                        mul(x.clone(), differentiate(y, var)),
+                        mul(y.clone(), differentiate(x, var)),
+                    ),
+                    add(square(x), square(y)),
+                )),
+                _ => None,
+            })
+            .exact(|args| match args {
+                [Expr::Number(y), Expr::Number(x)] if y.is_zero() && x.to_f64() > 0.0 => {
+                    Some(number(0))
+                }
+                _ => None,
+            }),
+        // root(x, n) = x^(1/n), taking odd roots of negative numbers
+        FunctionInfo::new("root", Arity::Exact(2))
+            .numeric(|zs| match real_only(zs, |xs| nth_root(xs[0], xs[1])) {
+                x if x.is_nan() => zs[0].powc(zs[1].inv()),
+                x => x.into(),
+            })
+            .domain(|xs| !nth_root(xs[0], xs[1]).is_nan())
+            .derivative(|args, var| match args {
+                [u, Expr::Number(n)] if n.to_i64() == Some(2) => {
+                    Some(differentiate(&call("sqrt", u), var))
+                }
+                [u, Expr::Number(n)] => Some(differentiate(
+                    &Expr::BinaryOp(
+                        BinaryOp::Pow,
+                        Box::new(u.clone()),
+                        Box::new(Expr::Number(Real::from(1) / n.clone())),
+                    ),
+                    var,
+                )),
+                _ => None,
+            })
+            .exact(|args| match args {
+                // The same square root as `sqrt`, so that the two cancel
+                [u, Expr::Number(n)] if n.to_i64() == Some(2) => Some(simplify(&call("sqrt", u))),
+                [Expr::Number(x), Expr::Number(n)] => exact_root(x, n),
+                _ => None,
+            })
+            .latex(|args| match args {
+                [arg, Expr::Number(n)] if n.to_i64() == Some(2) => {
+                    Some(format!("\\sqrt{{{}}}", format_expr_latex(arg)))
+                }
+                [arg, n] => Some(format!(
+                    "\\sqrt[{}]{{{}}}",
+                    format_expr_latex(n),
+                    format_expr_latex(arg)
+                )),
+                _ => None,
+            }),
+        // d/dx hypot(a, b, ...) = (a a' + b b' + ...) / hypot(a, b, ...)
+        FunctionInfo::new("hypot", Arity::AtLeast(1))
+            .takes_lists()
+            .numeric(|zs| zs.iter().map(|z| z.norm_sqr()).sum::<f64>().sqrt().into())
+            .derivative(|args, var| {
+                let terms = args
+                    .iter()
+                    .map(|a| mul(a.clone(), differentiate(a, var)))
+                    .collect();
+                Some(div(
+                    fold_binary_ops(BinaryOp::Add, terms),
+                    Expr::Function("hypot".into(), args.to_vec()),
+                ))
+            })
+            .exact(|args| {
+                let squares = args.iter().try_fold(Real::from(0), |sum, a| match a {
+                    Expr::Number(n) => Some(sum + n.clone() * n.clone()),
+                    _ => None,
+                })?;
+                exact_root(&squares, &Real::from(2))
+            }),
+        FunctionInfo::new("max", Arity::AtLeast(1))
+            .takes_lists()
+            .real(|xs| xs.iter().copied().fold(f64::NEG_INFINITY, f64::max))
+            .exact(|args| extremum(args, |x, y| x > y))
+            .latex_command("\\max"),
+        FunctionInfo::new("min", Arity::AtLeast(1))
+            .takes_lists()
+            .real(|xs| xs.iter().copied().fold(f64::INFINITY, f64::min))
+            .exact(|args| extremum(args, |x, y| x < y))
+            .latex_command("\\min"),
+        FunctionInfo::new("sum", one)
+            .takes_lists()
+            .numeric(|zs| zs.iter().sum())
+            .derivative(|args, var| match args {
+                [list] => Some(Expr::Function("sum".into(), vec![differentiate(list, var)])),
+                _ => None,
+            })
+            .exact(|args| match args {
+                [Expr::List(items)] if items.is_empty() => Some(number(0)),
+                [Expr::List(items)] => {
+                    Some(simplify(&fold_binary_ops(BinaryOp::Add, items.clone())))
+                }
+                _ => None,
+            }),
+        FunctionInfo::new("len", one)
+            .takes_lists()
+            .numeric(|zs| (zs.len() as f64).into())
+            .derivative(|_, _| Some(number(0)))
+            .exact(|args| match args {
+                [Expr::List(items)] => Some(number(items.len() as i64)),
+                _ => None,
+            }),
+        // Evaluated pairwise on lists by `eval` itself; product rule,
+        // (a . b)' = a' . b + a . b'
+        FunctionInfo::new("dot", Arity::Exact(2))
+            .takes_lists()
+            .derivative(|args, var| match args {
+                [a, b] => Some(add(
+                    Expr::Function("dot".into(), vec![differentiate(a, var), b.clone()]),
+                    Expr::Function("dot".into(), vec![a.clone(), differentiate(b, var)]),
+                )),
+                _ => None,
+            })
+            .exact(|args| match args {
+                [Expr::List(a), Expr::List(b)] => {
+                    let products = a.iter().zip(b).map(|(x, y)| mul(x.clone(), y.clone()));
+                    match products.collect::<Vec<_>>() {
+                        products if products.is_empty() => Some(number(0)),
+                        products => Some(simplify(&fold_binary_ops(BinaryOp::Add, products))),
+                    }
+                }
+                _ => None,
+            }),
+        // `a..b`, the integers from `a` to `b`, built by `eval` itself
+        FunctionInfo::new("range", Arity::Exact(2))
+            .takes_lists()
+            .derivative(|_, _| Some(number(0)))
+            .exact(|args| match args {
+                [Expr::Number(a), Expr::Number(b)] => {
+                    let (a, b) = (a.to_i64()?, b.to_i64()?);
+                    if b.checked_sub(a)? >= MAX_RANGE_LEN as i64 {
+                        return None;
+                    }
+                    Some(Expr::List(
+                        (a..=b).map(|k| Expr::Number(Real::from(k))).collect(),
+                    ))
+                }
+                _ => None,
+            })
+            .format(|args| match args {
+                [a, b] => {
+                    let side = |e: &Expr| match e.precedence() {
+                        RANGE_PRECEDENCE => format!("({})", format_expr(e)),
+                        _ => format_expr(e),
+                    };
+                    Some(format!("{}..{}", side(a), side(b)))
+                }
+                _ => None,
+            })
+            .latex(|args| match args {
+                [a, b] => {
+                    let side = |e: &Expr| match e.precedence() {
+                        RANGE_PRECEDENCE => format!("\\left({}\\right)", format_expr_latex(e)),
+                        _ => format_expr_latex(e),
+                    };
+                    Some(format!("{} \\ldots {}", side(a), side(b)))
+                }
+                _ => None,
+            }),
+        // Parsed into `Expr::Derivative` and `Expr::Integral`, so only
+        // their arity is needed
+        FunctionInfo::new("diff", Arity::Range(2, 3)),
+        FunctionInfo::new("int", Arity::Range(2, 4)),
+    ]
+}
+
+/// Exact sines, cosines and tangents at multiples of pi/6 and pi/4, such as
+/// `sin(pi/6) = 1/2`, `cos(pi/4) = sqrt(2)/2` and `tan(pi/4) = 1`.
+fn exact_trig(name: &str, args: &[Expr]) -> Option<Expr> {
+    let [arg] = args else {
+        return None;
+    };
+    // In steps of pi/12, the angle is one of those when the step is even or
+    // a multiple of 3
+    let k = (pi_multiple(arg)? * Real::from(12)).to_i64()?;
+    if k % 2 != 0 && k % 3 != 0 {
+        return None;
+    }
+    // `numer sqrt(radicand) / denom` for angles 0, pi/6, pi/4, pi/3 and pi/2
+    let sine = |k: i64| match k {
+        0 => (0, 1, 1),
+        2 => (1, 2, 1),
+        3 => (1, 2, 2),
+        4 => (1, 2, 3),
+        _ => (1, 1, 1),
+    };
+    let tangent = |k: i64| match k {
+        0 => Some((0, 1, 1)),
+        2 => Some((1, 3, 3)),
+        3 => Some((1, 1, 1)),
+        4 => Some((1, 1, 3)),
+        _ => None,
+    };
+    // Reduced to the first quadrant, with the sign the reduction gives
+    let sin_at = |k: i64| {
+        let k = k.rem_euclid(24);
+        let (sign, k) = if k < 12 { (1, k) } else { (-1, k - 12) };
+        let (numer, denom, radicand) = sine(k.min(12 - k));
+        (sign * numer, denom, radicand)
+    };
+    let (numer, denom, radicand) = match name {
+        "sin" => sin_at(k),
+        "cos" => sin_at(k + 6),
+        _ => {
+            let k = k.rem_euclid(12);
+            let (numer, denom, radicand) = tangent(k.min(12 - k))?;
+            (if k > 6 { -numer } else { numer }, denom, radicand)
+        }
+    };
+    Some(match radicand {
+        1 => Expr::Number(Real::ratio(numer, denom)),
+        _ => simplify(&div(
+            mul(number(numer), call("sqrt", &number(radicand))),
+            number(denom),
+        )),
+    })
+}
+
+/// `value` for the single argument `at`, as `ln(1) = 0`.
+fn at_number(args: &[Expr], at: i64, value: i64) -> Option<Expr> {
+    match args {
+        [Expr::Number(n)] if *n == Real::from(at) => Some(number(value)),
+        _ => None,
+    }
+}
+
+/// Euler's formula, exp(b i) = cos(b) + sin(b) i, at multiples of pi.
+fn euler(arg: &Expr) -> Option<Expr> {
+    let b = imaginary_coefficient(arg)?;
+    pi_multiple(&b)?;
+    Some(simplify(&add(
+        call("cos", &b),
+        mul(call("sin", &b), Expr::Constant(Constant::I)),
+    )))
+}
+
+/// The argument of max or min that is `better` than the others, when all
+/// are numbers; a single argument that is not a list is its own extremum.
+fn extremum(args: &[Expr], better: fn(f64, f64) -> bool) -> Option<Expr> {
+    let value = |e: &Expr| match e {
+        Expr::Number(n) => Some(n.to_f64()),
+        _ => None,
+    };
+    match args {
+        [only] if !matches!(only, Expr::List(_)) => Some(only.clone()),
+        [first, rest @ ..] => {
+            let mut best = first;
+            for e in rest {
+                if better(value(e)?, value(best)?) {
+                    best = e;
+                }
+            }
+            value(best)?;
+            Some(best.clone())
+        }
+        [] => None,
+    }
+}
+
+/// The derivative rule `f(u)' = u' / denominator(u)`.
+fn over(
+    denominator: impl Fn(&Expr) -> Expr + Send + Sync + 'static,
+) -> impl Fn(&[Expr], &str) -> Option<Expr> + Send + Sync + 'static {
+    move |args, var| match args {
+        [u] => Some(div(differentiate(u, var), denominator(u))),
+        _ => None,
+    }
+}
+
+fn sqrt_one_minus_square(u: &Expr) -> Expr {
+    Expr::Function("sqrt".into(), vec![sub(number(1), square(u))])
+}
+
+fn call(name: &str, arg: &Expr) -> Expr {
+    Expr::Function(name.into(), vec![arg.clone()])
+}
+
+fn number(n: i64) -> Expr {
+    Expr::Number(Real::from(n))
+}
+
+fn neg(e: Expr) -> Expr {
+    Expr::UnaryOp(UnaryOp::Neg, Box::new(e))
+}
+
+fn add(a: Expr, b: Expr) -> Expr {
+    Expr::BinaryOp(BinaryOp::Add, Box::new(a), Box::new(b))
+}
+
+fn sub(a: Expr, b: Expr) -> Expr {
+    Expr::BinaryOp(BinaryOp::Sub, Box::new(a), Box::new(b))
+}
+
+fn mul(a: Expr, b: Expr) -> Expr {
+    Expr::BinaryOp(BinaryOp::Mul, Box::new(a), Box::new(b))
+}
+
+fn div(a: Expr, b: Expr) -> Expr {
+    Expr::BinaryOp(BinaryOp::Div, Box::new(a), Box::new(b))
+}
+
+fn square(e: &Expr) -> Expr {
+    Expr::BinaryOp(BinaryOp::Pow, Box::new(e.clone()), Box::new(number(2)))
+}
@@ -0,0 +1,180 @@
+use crate::math::algebra::simplify;
+use crate::math::ast::*;
+use crate::math::diff::differentiate;
+
+/// Recursion depth for adaptive Simpson integration.
+const MAX_SIMPSON_DEPTH: u32 = 20;
+
+/// An antiderivative of `expr` with respect to `var`, or `None` if none of
+/// the rules below apply. Covers linearity, powers, exponentials and the
+/// basic trig functions of a linear argument `k x + c`.
+pub fn integrate(expr: &Expr, var: &str) -> Option<Expr> {
+    antiderivative(&simplify(expr), var).map(|e| simplify(&e))
+}
+
+fn antiderivative(expr: &Expr, var: &str) -> Option<Expr> {
+    if !expr.contains_var(var) {
+        return Some(mul(expr.clone(), Expr::Variable(var.to_string())));
+    }
+    match expr {
+        Expr::Variable(_) => Some(div(pow(expr.clone(), number(2)), number(2))),
+
+        Expr::UnaryOp(UnaryOp::Neg, e) => Some(Expr::UnaryOp(
+            UnaryOp::Neg,
+            Box::new(antiderivative(e, var)?),
+        )),
+
+        Expr::BinaryOp(op @ (BinaryOp::Add | BinaryOp::Sub), a, b) => Some(Expr::BinaryOp(
+            op.clone(),
+            Box::new(antiderivative(a, var)?),
+            Box::new(antiderivative(b, var)?),
+        )),
+
+        // Constant factors move outside the integral
+        Expr::BinaryOp(BinaryOp::Mul, a, b) if !a.contains_var(var) => {
+            Some(mul((**a).clone(), antiderivative(b, var)?))
+        }
+        Expr::BinaryOp(BinaryOp::Mul, a, b) if !b.contains_var(var) => {
+            Some(mul(antiderivative(a, var)?, (**b).clone()))
+        }
+        Expr::BinaryOp(BinaryOp::Div, a, b) if !b.contains_var(var) => {
+            Some(div(antiderivative(a, var)?, (**b).clone()))
+        }
+
+        // ∫ 1/u = ln|u| / k
+        Expr::BinaryOp(BinaryOp::Div, a, u) if !a.contains_var(var) => {
+            let k = slope(u, var)?;
+            Some(mul(
+                (**a).clone(),
+                div(
+                    Expr::Function(
+                        "ln".into(),
+                        vec![Expr::Function("abs".into(), vec![(**u).clone()])],
+                    ),
+                    k,
+                ),
+            ))
+        }
+
+        // ∫ u^n = u^(n + 1) / (k (n + 1)), and ∫ u^-1 = ln|u| / k
+        Expr::BinaryOp(BinaryOp::Pow, u, n) if !n.contains_var(var) => {
+            let k = slope(u, var)?;
+            if let Expr::Number(n) = &**n {
+                if n.to_i64() == Some(-1) {
+                    return Some(div(
+                        Expr::Function(
+                            "ln".into(),
+                            vec![Expr::Function("abs".into(), vec![(**u).clone()])],
+                        ),
+                        k,
+                    ));
+                }
+            }
+            let m = add((**n).clone(), number(1));
+            Some(div(pow((**u).clone(), m.clone()), mul(k, m)))
+        }
+
+        // ∫ b^u = b^u / (k ln b)
+        Expr::BinaryOp(BinaryOp::Pow, b, u) if !b.contains_var(var) => {
+            let k = slope(u, var)?;
+            Some(div(
+                expr.clone(),
+                mul(k, Expr::Function("ln".into(), vec![(**b).clone()])),
+            ))
+        }
+
+        Expr::Function(name, args) => {
+            let [u] = args.as_slice() else {
+                return None;
+            };
+            let k = slope(u, var)?;
+            let f = |name: &str| Expr::Function(name.into(), vec![u.clone()]);
+            let integral = match name.as_str() {
+                "sin" => Expr::UnaryOp(UnaryOp::Neg, Box::new(f("cos"))),
+                "cos" => f("sin"),
+                "exp" => f("exp"),
+                // ∫ tan u = -ln|cos u|
+                "tan" => Expr::UnaryOp(
+                    UnaryOp::Neg,
+                    Box::new(Expr::Function(
+                        "ln".into(),
+                        vec![Expr::Function("abs".into(), vec![f("cos")])],
+                    )),
+                ),
+                "sqrt" => mul(
+                    Expr::Number(Real::ratio(2, 3)),
+                    pow(u.clone(), Expr::Number(Real::ratio(3, 2))),
+                ),
+                _ => return None,
+            };
+            Some(div(integral, k))
+        }
+
+        _ => None,
+    }
+}
+
+/// `k` when `u = k var + c` for constants `k` and `c`.
+fn slope(u: &Expr, var: &str) -> Option<Expr> {
+    let k = differentiate(u, var);
+    (!k.contains_var(var)).then_some(k)
+}
+
+/// The integral of `f` from `a` to `b` by adaptive Simpson's rule.
+pub fn integrate_numeric(mut f: impl FnMut(f64) -> f64, a: f64, b: f64) -> f64 {
+    if a == b {
+        return 0.0;
+    }
+    let (fa, fm, fb) = (f(a), f((a + b) / 2.0), f(b));
+    let whole = (b - a) / 6.0 * (fa + 4.0 * fm + fb);
+    simpson(
+        &mut f,
+        (a, fa),
+        (b, fb),
+        fm,
+        whole,
+        1e-10,
+        MAX_SIMPSON_DEPTH,
+    )
+}
+
+fn simpson(
+    f: &mut impl FnMut(f64) -> f64,
+    (a, fa): (f64, f64),
+    (b, fb): (f64, f64),
+    fm: f64,
+    whole: f64,
+    tolerance: f64,
+    depth: u32,
+) -> f64 {
+    let m = (a + b) / 2.0;
+    let (flm, frm) = (f((a + m) / 2.0), f((m + b) / 2.0));
+    let left = (m - a) / 6.0 * (fa + 4.0 * flm + fm);
+    let right = (b - m) / 6.0 * (fm + 4.0 * frm + fb);
+    let error = left + right - whole;
+    if depth == 0 || error.abs() <= 15.0 * tolerance {
+        return left + right + error / 15.0;
+    }
+    simpson(f, (a, fa), (m, fm), flm, left, tolerance / 2.0, depth - 1)
+        + simpson(f, (m, fm), (b, fb), frm, right, tolerance / 2.0, depth - 1)
+}
+
+fn number(n: i64) -> Expr {
+    Expr::Number(Real::from(n))
+}
+
+fn add(a: Expr, b: Expr) -> Expr {
+    Expr::BinaryOp(BinaryOp::Add, Box::new(a), Box::new(b))
+}
+
+fn mul(a: Expr, b: Expr) -> Expr {
+    Expr::BinaryOp(BinaryOp::Mul, Box::new(a), Box::new(b))
+}
+
+fn div(a: Expr, b: Expr) -> Expr {
+    Expr::BinaryOp(BinaryOp::Div, Box::new(a), Box::new(b))
+}
+
+fn pow(a: Expr, b: Expr) -> Expr {
+    Expr::BinaryOp(BinaryOp::Pow, Box::new(a), Box::new(b))
+}
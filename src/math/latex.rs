@@ -0,0 +1,870 @@
+use crate::math::ast::*;
+use crate::math::error::{Expected, NumberError, ParseError, Span};
+use crate::math::functions;
+
+/// Parse a LaTeX math fragment (with or without `$` delimiters) into an `Expr`.
+pub fn parse_latex(input: &str) -> Result<Expr, ParseError> {
+    let mut parser = LatexParser::new(input)?;
+    let expr = parser.parse_expr()?;
+    if parser.peek().is_some() {
+        return Err(parser.unexpected(vec![Expected::Operator, Expected::EndOfInput]));
+    }
+    Ok(expr)
+}
+
+/// Parse a LaTeX expression or relation, e.g. `x^2 + y^2 \leq 1`.
+pub fn parse_latex_statement(input: &str) -> Result<Statement, ParseError> {
+    let mut parser = LatexParser::new(input)?;
+    let lhs = parser.parse_expr()?;
+    let defining = parser.peek_sym('=');
+    let statement = match parser.parse_rel_op() {
+        Some(_) if defining => Statement::equation(lhs, parser.parse_expr()?),
+        Some(op) => {
+            let rhs = parser.parse_expr()?;
+            Statement::Relation(Relation { op, lhs, rhs })
+        }
+        None => Statement::Expr(lhs),
+    };
+    if parser.peek().is_some() {
+        return Err(parser.unexpected(vec![Expected::Operator, Expected::EndOfInput]));
+    }
+    Ok(statement)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Num(String),
+    Letter(char),
+    Cmd(String),
+    Sym(char),
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    tok: Tok,
+    span: Span,
+}
+
+/// Commands that only affect spacing and are dropped by the tokenizer.
+const SPACING: &[&str] = &[",", ";", ":", "!", " ", "quad", "qquad"];
+
+fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() || c == '$' => {}
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut num = c.to_string();
+                let mut end = start + 1;
+                while let Some(&(i, d)) = chars.peek() {
+                    if d.is_ascii_digit() || d == '.' {
+                        num.push(d);
+                        end = i + 1;
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token {
+                    tok: Tok::Num(num),
+                    span: Span::new(start, end),
+                });
+            }
+            c if c.is_alphabetic() => tokens.push(Token {
+                tok: Tok::Letter(c),
+                span: Span::new(start, start + c.len_utf8()),
+            }),
+            '\\' => {
+                let mut name = String::new();
+                let mut end = start + 1;
+                while let Some(&(i, d)) = chars.peek() {
+                    if d.is_ascii_alphabetic() {
+                        name.push(d);
+                        end = i + 1;
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                // Single-symbol commands such as `\,` or `\{`
+                if name.is_empty() {
+                    match chars.next() {
+                        Some((i, d)) => {
+                            name.push(d);
+                            end = i + d.len_utf8();
+                        }
+                        None => {
+                            return Err(ParseError::UnexpectedEnd {
+                                span: Span::point(end),
+                                expected: vec![Expected::Expression],
+                            })
+                        }
+                    }
+                }
+                if SPACING.contains(&name.as_str()) {
+                    continue;
+                }
+                tokens.push(Token {
+                    tok: Tok::Cmd(name),
+                    span: Span::new(start, end),
+                });
+            }
+            _ => tokens.push(Token {
+                tok: Tok::Sym(c),
+                span: Span::new(start, start + c.len_utf8()),
+            }),
+        }
+    }
+
+    Ok(fold_delimiter_sizing(tokens))
+}
+
+/// Fold `\left(` / `\right)` into the bare delimiter they size.
+fn fold_delimiter_sizing(tokens: Vec<Token>) -> Vec<Token> {
+    let mut out: Vec<Token> = Vec::with_capacity(tokens.len());
+    let mut iter = tokens.into_iter().peekable();
+    while let Some(token) = iter.next() {
+        let is_sizing = matches!(&token.tok, Tok::Cmd(name) if name == "left" || name == "right");
+        if is_sizing {
+            if let Some(Token {
+                tok: Tok::Sym(d), ..
+            }) = iter.peek()
+            {
+                let d = *d;
+                let delim = iter.next().unwrap();
+                out.push(Token {
+                    tok: Tok::Sym(d),
+                    span: Span::new(token.span.start, delim.span.end),
+                });
+                continue;
+            }
+        }
+        out.push(token);
+    }
+    out
+}
+
+struct LatexParser {
+    tokens: Vec<Token>,
+    idx: usize,
+    /// Byte length of the input, used for end-of-input spans.
+    end: usize,
+    /// Number of `|...|` groups currently open, so a `|` can be told apart
+    /// as an opening or closing bar.
+    abs_depth: usize,
+}
+
+impl LatexParser {
+    fn new(input: &str) -> Result<Self, ParseError> {
+        Ok(LatexParser {
+            tokens: tokenize(input)?,
+            idx: 0,
+            end: input.len(),
+            abs_depth: 0,
+        })
+    }
+
+    fn parse_rel_op(&mut self) -> Option<RelOp> {
+        let op = match self.peek()? {
+            Tok::Sym('=') => RelOp::Eq,
+            Tok::Sym('<') if self.peek_at(1) == Some(&Tok::Sym('=')) => {
+                self.bump();
+                RelOp::Le
+            }
+            Tok::Sym('>') if self.peek_at(1) == Some(&Tok::Sym('=')) => {
+                self.bump();
+                RelOp::Ge
+            }
+            Tok::Sym('<') => RelOp::Lt,
+            Tok::Sym('>') => RelOp::Gt,
+            Tok::Cmd(c) => match c.as_str() {
+                "ne" | "neq" => RelOp::Ne,
+                "lt" => RelOp::Lt,
+                "le" | "leq" => RelOp::Le,
+                "gt" => RelOp::Gt,
+                "ge" | "geq" => RelOp::Ge,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        self.bump();
+        Some(op)
+    }
+
+    fn peek(&self) -> Option<&Tok> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Tok> {
+        self.tokens.get(self.idx + offset).map(|t| &t.tok)
+    }
+
+    fn prev_end(&self) -> usize {
+        self.idx
+            .checked_sub(1)
+            .and_then(|i| self.tokens.get(i))
+            .map_or(0, |t| t.span.end)
+    }
+
+    fn bump(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.idx).cloned();
+        self.idx += 1;
+        token
+    }
+
+    fn peek_sym(&self, c: char) -> bool {
+        self.peek() == Some(&Tok::Sym(c))
+    }
+
+    fn eat_sym(&mut self, c: char) -> bool {
+        if self.peek_sym(c) {
+            self.idx += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn unexpected(&self, expected: Vec<Expected>) -> ParseError {
+        match self.tokens.get(self.idx) {
+            Some(token) => ParseError::UnexpectedChar {
+                found: match &token.tok {
+                    Tok::Num(n) => n.chars().next().unwrap_or('0'),
+                    Tok::Letter(c) | Tok::Sym(c) => *c,
+                    Tok::Cmd(_) => '\\',
+                },
+                span: token.span,
+                expected,
+            },
+            None => ParseError::UnexpectedEnd {
+                span: Span::point(self.end),
+                expected,
+            },
+        }
+    }
+
+    /// Consume the closing delimiter matching the one opened at `open_span`.
+    fn expect_close(&mut self, open: char, close: char, open_span: Span) -> Result<(), ParseError> {
+        if self.eat_sym(close) {
+            Ok(())
+        } else if self.peek().is_none() {
+            Err(ParseError::UnclosedDelimiter {
+                open,
+                open_span,
+                span: Span::point(self.end),
+            })
+        } else {
+            Err(self.unexpected(vec![Expected::Char(close), Expected::Operator]))
+        }
+    }
+
+    /// An expression, or a range `a \ldots b` of two.
+    fn parse_expr(&mut self) -> Result<Expr, ParseError> {
+        let start = self.parse_sum()?;
+        if !matches!(self.peek(), Some(Tok::Cmd(c)) if c == "ldots" || c == "dots") {
+            return Ok(start);
+        }
+        self.bump();
+        let end = self.parse_sum()?;
+        Ok(Expr::Function("range".into(), vec![start, end]))
+    }
+
+    /// Comma-separated expressions, as in a list or argument list.
+    fn parse_args(&mut self) -> Result<Vec<Expr>, ParseError> {
+        let mut args = vec![self.parse_expr()?];
+        while self.eat_sym(',') {
+            args.push(self.parse_expr()?);
+        }
+        Ok(args)
+    }
+
+    fn parse_sum(&mut self) -> Result<Expr, ParseError> {
+        let mut node = self.parse_term()?;
+        loop {
+            let op = match self.peek() {
+                Some(Tok::Sym('+')) => BinaryOp::Add,
+                Some(Tok::Sym('-')) => BinaryOp::Sub,
+                _ => break,
+            };
+            self.bump();
+            let rhs = self.parse_term()?;
+            node = Expr::BinaryOp(op, Box::new(node), Box::new(rhs));
+        }
+        Ok(node)
+    }
+
+    fn parse_term(&mut self) -> Result<Expr, ParseError> {
+        let mut node = self.parse_unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Tok::Sym('*')) => Some(BinaryOp::Mul),
+                Some(Tok::Sym('/')) => Some(BinaryOp::Div),
+                Some(Tok::Cmd(c)) if c == "cdot" || c == "times" => Some(BinaryOp::Mul),
+                Some(Tok::Cmd(c)) if c == "div" => Some(BinaryOp::Div),
+                _ if self.starts_factor() => None,
+                _ => break,
+            };
+            let rhs = match op {
+                Some(op) => {
+                    self.bump();
+                    (op, self.parse_unary()?)
+                }
+                // Implicit multiplication by juxtaposition
+                None => (BinaryOp::Mul, self.parse_power()?),
+            };
+            node = Expr::BinaryOp(rhs.0, Box::new(node), Box::new(rhs.1));
+        }
+        Ok(node)
+    }
+
+    /// Whether the next token can begin an implicitly multiplied factor.
+    fn starts_factor(&self) -> bool {
+        match self.peek() {
+            Some(Tok::Num(_)) | Some(Tok::Letter(_)) => true,
+            Some(Tok::Sym('(')) | Some(Tok::Sym('[')) | Some(Tok::Sym('{')) => true,
+            Some(Tok::Sym('|')) => self.abs_depth == 0,
+            Some(Tok::Cmd(c)) => !matches!(
+                c.as_str(),
+                "cdot"
+                    | "times"
+                    | "div"
+                    | "ne"
+                    | "neq"
+                    | "lt"
+                    | "le"
+                    | "leq"
+                    | "gt"
+                    | "ge"
+                    | "geq"
+                    | "%"
+                    | "\\"
+                    | "end"
+                    | "ldots"
+                    | "dots"
+            ),
+            _ => false,
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        if self.eat_sym('-') {
+            let expr = self.parse_unary()?;
+            Ok(Expr::UnaryOp(UnaryOp::Neg, Box::new(expr)))
+        } else if self.eat_sym('+') {
+            self.parse_unary()
+        } else {
+            self.parse_power()
+        }
+    }
+
+    fn parse_power(&mut self) -> Result<Expr, ParseError> {
+        let primary = self.parse_primary()?;
+        let base = self.parse_postfix(primary);
+        if self.eat_sym('^') {
+            let exp = self.parse_script()?;
+            let power = Expr::BinaryOp(BinaryOp::Pow, Box::new(base), Box::new(exp));
+            Ok(self.parse_postfix(power))
+        } else {
+            Ok(base)
+        }
+    }
+
+    /// Postfix `!` and `\%`.
+    fn parse_postfix(&mut self, mut expr: Expr) -> Expr {
+        loop {
+            let op = match self.peek() {
+                Some(Tok::Sym('!')) => UnaryOp::Factorial,
+                Some(Tok::Cmd(c)) if c == "%" => UnaryOp::Percent,
+                _ => return expr,
+            };
+            self.bump();
+            expr = Expr::UnaryOp(op, Box::new(expr));
+        }
+    }
+
+    /// The argument of `^`, `_`, `\frac` or `\sqrt`: a braced group or a
+    /// single token, where a bare number contributes only its first digit
+    /// (`x^23` is `x^{2} 3`).
+    fn parse_script(&mut self) -> Result<Expr, ParseError> {
+        match self.tokens.get(self.idx).cloned() {
+            Some(Token {
+                tok: Tok::Sym('{'),
+                span,
+            }) => {
+                self.bump();
+                let inner = self.parse_expr()?;
+                self.expect_close('{', '}', span)?;
+                Ok(inner)
+            }
+            Some(Token {
+                tok: Tok::Num(n),
+                span,
+            }) => {
+                let first = &n[..1];
+                if n.len() > 1 && first != "." {
+                    self.tokens[self.idx] = Token {
+                        tok: Tok::Num(n[1..].to_string()),
+                        span: Span::new(span.start + 1, span.end),
+                    };
+                } else {
+                    self.bump();
+                }
+                self.number(first, Span::new(span.start, span.start + 1))
+            }
+            Some(Token {
+                tok: Tok::Sym('-'), ..
+            }) => {
+                self.bump();
+                let inner = self.parse_script()?;
+                Ok(Expr::UnaryOp(UnaryOp::Neg, Box::new(inner)))
+            }
+            _ => self.parse_primary(),
+        }
+    }
+
+    fn number(&self, literal: &str, span: Span) -> Result<Expr, ParseError> {
+        literal
+            .parse::<Real>()
+            .map(Expr::Number)
+            .map_err(|_| ParseError::InvalidNumber {
+                literal: literal.to_string(),
+                reason: if literal.matches('.').count() > 1 {
+                    NumberError::MultipleDecimalPoints
+                } else {
+                    NumberError::MissingDigits
+                },
+                span,
+            })
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        let token = match self.tokens.get(self.idx).cloned() {
+            Some(token) => token,
+            None => return Err(self.unexpected(vec![Expected::Expression])),
+        };
+
+        match token.tok {
+            Tok::Num(n) => {
+                self.bump();
+                self.number(&n, token.span)
+            }
+            Tok::Letter(c) => {
+                self.bump();
+                if c == 'e' && !self.peek_sym('_') {
+                    Ok(Expr::Constant(Constant::E))
+                } else if c == 'i' && !self.peek_sym('_') {
+                    Ok(Expr::Constant(Constant::I))
+                } else {
+                    self.parse_subscripted(c.to_string())
+                }
+            }
+            Tok::Sym(open @ ('(' | '{')) => {
+                self.bump();
+                let close = if open == '(' { ')' } else { '}' };
+                let inner = self.parse_expr()?;
+                self.expect_close(open, close, token.span)?;
+                Ok(inner)
+            }
+            Tok::Sym('[') => {
+                self.bump();
+                let items = if self.peek_sym(']') {
+                    Vec::new()
+                } else {
+                    self.parse_args()?
+                };
+                self.expect_close('[', ']', token.span)?;
+                Ok(Expr::List(items))
+            }
+            Tok::Sym('|') => {
+                self.bump();
+                self.abs_depth += 1;
+                let inner = self.parse_expr()?;
+                self.abs_depth -= 1;
+                self.expect_close('|', '|', token.span)?;
+                Ok(Expr::Function("abs".into(), vec![inner]))
+            }
+            Tok::Cmd(name) => {
+                self.bump();
+                self.parse_command(&name, token.span)
+            }
+            Tok::Sym(_) => Err(self.unexpected(vec![Expected::Expression])),
+        }
+    }
+
+    /// The variable `base`, with a subscript if `_` follows: `x_1`, `x_{12}`
+    /// or `v_{max}`. Followed by `\left(`, as `format_expr_latex` writes
+    /// user functions, it is the call `f\left(x\right)` instead.
+    fn parse_subscripted(&mut self, base: String) -> Result<Expr, ParseError> {
+        if !self.eat_sym('_') {
+            return self.parse_call_or_variable(base);
+        }
+        let mut subscript = String::new();
+        match self.tokens.get(self.idx).cloned().map(|t| (t.tok, t.span)) {
+            Some((Tok::Sym('{'), open)) => {
+                self.bump();
+                loop {
+                    match self.peek() {
+                        Some(Tok::Letter(c)) => subscript.push(*c),
+                        Some(Tok::Num(n)) => subscript.push_str(n),
+                        _ => break,
+                    }
+                    self.bump();
+                }
+                if subscript.is_empty() {
+                    return Err(self.unexpected(vec![Expected::Expression]));
+                }
+                self.expect_close('{', '}', open)?;
+            }
+            Some((Tok::Letter(c), _)) => {
+                self.bump();
+                subscript.push(c);
+            }
+            // Like exponents, a bare number subscripts only its first digit
+            Some((Tok::Num(n), span)) => {
+                subscript.push_str(&n[..1]);
+                if n.len() > 1 {
+                    self.tokens[self.idx] = Token {
+                        tok: Tok::Num(n[1..].to_string()),
+                        span: Span::new(span.start + 1, span.end),
+                    };
+                } else {
+                    self.bump();
+                }
+            }
+            _ => return Err(self.unexpected(vec![Expected::Expression])),
+        }
+        self.parse_call_or_variable(Symbol::join(&base, Some(&subscript)))
+    }
+
+    /// A call of the user function `name` when a `\left(` follows, which
+    /// a bare `(` does not mark: `x(y + 1)` is a product.
+    fn parse_call_or_variable(&mut self, name: String) -> Result<Expr, ParseError> {
+        let sized_open = match self.tokens.get(self.idx) {
+            Some(Token {
+                tok: Tok::Sym('('),
+                span,
+            }) => span.end - span.start > 1,
+            _ => false,
+        };
+        if !sized_open {
+            return Ok(Expr::Variable(name));
+        }
+        let open = self.bump().unwrap().span;
+        let args = self.parse_args()?;
+        self.expect_close('(', ')', open)?;
+        Ok(Expr::Function(name, args))
+    }
+
+    fn parse_command(&mut self, name: &str, span: Span) -> Result<Expr, ParseError> {
+        match name {
+            "frac" | "dfrac" | "tfrac" => {
+                if let Some((var, order)) = self.parse_leibniz() {
+                    let operand = self.parse_term()?;
+                    return Ok(Expr::Derivative(Box::new(operand), var, order));
+                }
+                let num = self.parse_script()?;
+                let den = self.parse_script()?;
+                Ok(Expr::BinaryOp(BinaryOp::Div, Box::new(num), Box::new(den)))
+            }
+            "sqrt" => {
+                let index = if self.peek_sym('[') {
+                    let open = self.bump().unwrap().span;
+                    let index = self.parse_expr()?;
+                    self.expect_close('[', ']', open)?;
+                    Some(index)
+                } else {
+                    None
+                };
+                let radicand = self.parse_script()?;
+                Ok(match index {
+                    Some(index) if index != Expr::Number(Real::from(2)) => {
+                        Expr::Function("root".into(), vec![radicand, index])
+                    }
+                    _ => Expr::Function("sqrt".into(), vec![radicand]),
+                })
+            }
+            "begin" => {
+                let environment = self.parse_operator_name()?;
+                if environment != "cases" {
+                    return Err(ParseError::UnknownCommand {
+                        name: format!("begin{{{}}}", environment),
+                        span: Span::new(span.start, self.prev_end()),
+                    });
+                }
+                self.parse_cases()
+            }
+            "int" => self.parse_integral(),
+            "operatorname" => {
+                let name = self.parse_operator_name()?;
+                self.parse_function(name, span)
+            }
+            "pi" => Ok(Expr::Constant(Constant::Pi)),
+            "tau" => Ok(Expr::Constant(Constant::Tau)),
+            "phi" | "varphi" => Ok(Expr::Constant(Constant::Phi)),
+            "infty" => Ok(Expr::Constant(Constant::Infinity)),
+            _ if GREEK_LETTERS.contains(&name) => self.parse_subscripted(name.to_string()),
+            _ => match functions::from_latex_command(name) {
+                Some(f) => self.parse_function(f, span),
+                None => Err(ParseError::UnknownCommand {
+                    name: name.to_string(),
+                    span,
+                }),
+            },
+        }
+    }
+
+    /// Rows `value & condition` of a `cases` environment, separated by `\\`,
+    /// up to and including `\end{cases}`. The condition may be introduced
+    /// by `\text{if}`, and `\text{otherwise}` marks the fallback.
+    fn parse_cases(&mut self) -> Result<Expr, ParseError> {
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+        loop {
+            let value = self.parse_expr()?;
+            self.eat_sym(',');
+            if !self.eat_sym('&') {
+                return Err(self.unexpected(vec![Expected::Char('&'), Expected::Operator]));
+            }
+            let text = if self.peek() == Some(&Tok::Cmd("text".into())) {
+                self.bump();
+                Some(self.parse_operator_name()?)
+            } else {
+                None
+            };
+            match text.as_deref() {
+                Some("otherwise" | "else") => {
+                    otherwise = Some(Box::new(value));
+                }
+                _ => {
+                    let lhs = self.parse_expr()?;
+                    let op = match self.parse_rel_op() {
+                        Some(op) => op,
+                        None => {
+                            return Err(
+                                self.unexpected(vec![Expected::Relation, Expected::Operator])
+                            )
+                        }
+                    };
+                    let rhs = self.parse_expr()?;
+                    branches.push((value, Relation { op, lhs, rhs }));
+                }
+            }
+            // A trailing `\\` before `\end{cases}` is allowed
+            let row_break = self.peek() == Some(&Tok::Cmd("\\".into()));
+            if row_break {
+                self.bump();
+            }
+            if self.peek() == Some(&Tok::Cmd("end".into())) {
+                self.bump();
+                if self.parse_operator_name()? != "cases" {
+                    return Err(self.unexpected(vec![Expected::EndOfInput]));
+                }
+                return Ok(Expr::Piecewise(branches, otherwise));
+            }
+            if !row_break {
+                return Err(self.unexpected(vec![Expected::Operator]));
+            }
+        }
+    }
+
+    /// The variable and order of `\frac{d}{dx}` or `\frac{d^2}{dx^2}`,
+    /// after the `\frac`. Reads nothing and returns `None` for any other
+    /// fraction.
+    fn parse_leibniz(&mut self) -> Option<(String, u32)> {
+        let start = self.idx;
+        let parsed = self.parse_leibniz_parts();
+        if parsed.is_none() {
+            self.idx = start;
+        }
+        parsed
+    }
+
+    fn parse_leibniz_parts(&mut self) -> Option<(String, u32)> {
+        let eat = |p: &mut Self, tok: Tok| (p.peek() == Some(&tok)).then(|| p.bump());
+        eat(self, Tok::Sym('{'))?;
+        eat(self, Tok::Letter('d'))?;
+        let order = self.parse_leibniz_order()?;
+        eat(self, Tok::Sym('}'))?;
+        eat(self, Tok::Sym('{'))?;
+        eat(self, Tok::Letter('d'))?;
+        let var = self.parse_differential_var()?;
+        (self.parse_leibniz_order()? == order).then_some(())?;
+        eat(self, Tok::Sym('}'))?;
+        Some((var, order))
+    }
+
+    /// The `^2` or `^{2}` on the `d` of `\frac{d^2}{dx^2}`, 1 if absent.
+    fn parse_leibniz_order(&mut self) -> Option<u32> {
+        if !self.eat_sym('^') {
+            return Some(1);
+        }
+        let braced = self.eat_sym('{');
+        let order = match self.bump()?.tok {
+            Tok::Num(n) => n.parse().ok().filter(|&n| n > 0)?,
+            _ => return None,
+        };
+        if braced && !self.eat_sym('}') {
+            return None;
+        }
+        Some(order)
+    }
+
+    /// The `x` of `dx`: a letter or a Greek letter command.
+    fn parse_differential_var(&mut self) -> Option<String> {
+        let var = match self.peek()? {
+            Tok::Letter(c) => c.to_string(),
+            Tok::Cmd(name) if GREEK_LETTERS.contains(&name.as_str()) => name.clone(),
+            _ => return None,
+        };
+        self.bump();
+        Some(var)
+    }
+
+    /// `\int f \, dx` or `\int_a^b f \, dx`, after the `\int`. The
+    /// integrand runs up to the first `d` followed by a variable outside
+    /// any brackets.
+    fn parse_integral(&mut self) -> Result<Expr, ParseError> {
+        let lower = if self.eat_sym('_') {
+            Some(self.parse_script()?)
+        } else {
+            None
+        };
+        let upper = if self.eat_sym('^') {
+            Some(self.parse_script()?)
+        } else {
+            None
+        };
+        let bounds = match (lower, upper) {
+            (Some(a), Some(b)) => Some(Box::new((a, b))),
+            (None, None) => None,
+            (Some(_), None) => return Err(self.unexpected(vec![Expected::Char('^')])),
+            (None, Some(_)) => return Err(self.unexpected(vec![Expected::Char('_')])),
+        };
+
+        let mut depth = 0usize;
+        let mut differential = None;
+        for i in self.idx..self.tokens.len() {
+            match &self.tokens[i].tok {
+                Tok::Sym('(' | '[' | '{') => depth += 1,
+                Tok::Sym(')' | ']' | '}') if depth == 0 => break,
+                Tok::Sym(')' | ']' | '}') => depth -= 1,
+                Tok::Letter('d') if depth == 0 => {
+                    let next = self.tokens.get(i + 1).map(|t| &t.tok);
+                    let var = matches!(next, Some(Tok::Letter(_)))
+                        || matches!(next, Some(Tok::Cmd(c)) if GREEK_LETTERS.contains(&c.as_str()));
+                    if var {
+                        differential = Some(i);
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let Some(end) = differential else {
+            return Err(ParseError::UnexpectedEnd {
+                span: Span::point(self.end),
+                expected: vec![Expected::Char('d')],
+            });
+        };
+
+        // `\int dx` integrates 1
+        let integrand = if end == self.idx {
+            Expr::Number(Real::from(1))
+        } else {
+            let mut inner = LatexParser {
+                tokens: self.tokens[self.idx..end].to_vec(),
+                idx: 0,
+                end: self.tokens[end].span.start,
+                abs_depth: 0,
+            };
+            let integrand = inner.parse_expr()?;
+            if inner.peek().is_some() {
+                return Err(inner.unexpected(vec![Expected::Operator, Expected::Char('d')]));
+            }
+            integrand
+        };
+        self.idx = end + 1;
+        let var = self
+            .parse_differential_var()
+            .expect("checked when finding the differential");
+        Ok(Expr::Integral(Box::new(integrand), var, bounds))
+    }
+
+    /// The braced name after `\operatorname`, e.g. `{atan2}`, `{f'}` or
+    /// `{my\_fn}`.
+    fn parse_operator_name(&mut self) -> Result<String, ParseError> {
+        let open = match self.tokens.get(self.idx) {
+            Some(Token {
+                tok: Tok::Sym('{'),
+                span,
+            }) => *span,
+            _ => return Err(self.unexpected(vec![Expected::Char('{')])),
+        };
+        self.bump();
+        let mut name = String::new();
+        loop {
+            match self.peek() {
+                Some(Tok::Letter(c)) => name.push(*c),
+                Some(Tok::Num(n)) => name.push_str(n),
+                Some(Tok::Sym('\'')) => name.push('\''),
+                Some(Tok::Cmd(c)) if c == "_" => name.push('_'),
+                _ => break,
+            }
+            self.bump();
+        }
+        self.expect_close('{', '}', open)?;
+        Ok(name)
+    }
+
+    /// Arguments of a function command: `\log_{b}`, an optional power as in
+    /// `\sin^2 x`, then either a parenthesised list or a single operand.
+    fn parse_function(&mut self, name: String, span: Span) -> Result<Expr, ParseError> {
+        let base = if name == "log" && self.eat_sym('_') {
+            Some(self.parse_script()?)
+        } else {
+            None
+        };
+        let power = if self.eat_sym('^') {
+            Some(self.parse_script()?)
+        } else {
+            None
+        };
+
+        let mut args = if self.peek_sym('(') {
+            let open = self.bump().unwrap().span;
+            let args = self.parse_args()?;
+            self.expect_close('(', ')', open)?;
+            args
+        } else {
+            vec![self.parse_power()?]
+        };
+        if let Some(base) = base {
+            args.insert(0, base);
+        }
+
+        if let Some(arity) = function_arity(&name) {
+            if !arity.accepts(args.len()) {
+                return Err(ParseError::ArityMismatch {
+                    name,
+                    expected: arity,
+                    found: args.len(),
+                    span: Span::new(span.start, self.prev_end()),
+                });
+            }
+        }
+
+        // `\sin^{-1}` is the inverse function, not a reciprocal
+        let (name, power) = match (inverse_function(&name), &power) {
+            (Some(inverse), Some(p)) if p.is_minus_one() => (inverse, None),
+            _ => (name, power),
+        };
+
+        let call = Expr::Function(name, args);
+        Ok(match power {
+            Some(p) => Expr::BinaryOp(BinaryOp::Pow, Box::new(call), Box::new(p)),
+            None => call,
+        })
+    }
+}
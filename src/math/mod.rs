@@ -1,7 +1,16 @@
 pub mod algebra;
 pub mod ast;
 pub mod diff;
+pub mod env;
+pub mod error;
 pub mod eval;
+pub mod factor;
 pub mod format;
+pub mod functions;
+pub mod integrate;
+pub mod latex;
+pub mod number;
 pub mod parser;
+pub mod poly;
+pub mod unicode;
 pub mod utils;
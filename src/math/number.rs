@@ -0,0 +1,302 @@
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+use ordered_float::OrderedFloat;
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+use std::str::FromStr;
+
+/// Largest decimal exponent (as in `1e300`) and integer power (as in
+/// `3^1000`) computed exactly; beyond them numbers fall back to floats
+/// rather than growing without bound.
+const MAX_EXACT_EXPONENT: u32 = 1000;
+
+/// Largest estimated size, in bits of numerator plus denominator, of an
+/// exact power; `(10^1000)^1000` would otherwise take minutes to compute.
+const MAX_EXACT_BITS: u64 = 1 << 16;
+
+/// A real number: exact while only rational arithmetic is involved, and a
+/// float once something transcendental or irrational is.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Real {
+    Rational(BigRational),
+    /// Never a whole number; those are kept as rationals.
+    Float(OrderedFloat<f64>),
+}
+
+impl Real {
+    pub fn ratio(numer: i64, denom: i64) -> Real {
+        Real::Rational(BigRational::new(numer.into(), denom.into()))
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Real::Rational(r) => r.to_f64().unwrap_or(f64::NAN),
+            Real::Float(x) => x.0,
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        matches!(self, Real::Rational(_))
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(self, Real::Rational(r) if r.is_integer())
+    }
+
+    pub fn is_zero(&self) -> bool {
+        matches!(self, Real::Rational(r) if r.is_zero())
+    }
+
+    pub fn is_one(&self) -> bool {
+        matches!(self, Real::Rational(r) if r.is_one())
+    }
+
+    pub fn is_negative(&self) -> bool {
+        match self {
+            Real::Rational(r) => r.is_negative(),
+            Real::Float(x) => x.0 < 0.0,
+        }
+    }
+
+    /// The value as an `i64`, if it is a whole number in range.
+    pub fn to_i64(&self) -> Option<i64> {
+        match self {
+            Real::Rational(r) if r.is_integer() => r.to_integer().to_i64(),
+            _ => None,
+        }
+    }
+
+    pub fn abs(&self) -> Real {
+        match self {
+            Real::Rational(r) => Real::Rational(r.abs()),
+            Real::Float(x) => Real::Float(OrderedFloat(x.0.abs())),
+        }
+    }
+
+    /// Whether the value is written as a decimal rather than a fraction:
+    /// floats, and rationals whose denominator has no prime factors but 2
+    /// and 5, such as `0.25`.
+    pub fn is_terminating(&self) -> bool {
+        match self {
+            Real::Rational(r) => {
+                let mut d = r.denom().clone();
+                for p in [2u32, 5] {
+                    let p = BigInt::from(p);
+                    while (&d % &p).is_zero() {
+                        d /= &p;
+                    }
+                }
+                d.is_one()
+            }
+            Real::Float(_) => true,
+        }
+    }
+
+    /// `self^exp`, exactly for a rational raised to a whole power or to
+    /// `1/n` of a perfect `n`th power, as in `8^(2/3) = 4`.
+    pub fn pow(&self, exp: &Real) -> Real {
+        if let (Real::Rational(base), Real::Rational(e)) = (self, exp) {
+            let root = e.denom().to_u32().and_then(|n| exact_root(base, n));
+            let power = e
+                .numer()
+                .to_i32()
+                .filter(|p| p.unsigned_abs() <= MAX_EXACT_EXPONENT);
+            if let (Some(root), Some(power)) = (root, power) {
+                let bits = (root.numer().bits() + root.denom().bits())
+                    .saturating_mul(u64::from(power.unsigned_abs()));
+                if bits <= MAX_EXACT_BITS && !(root.is_zero() && power < 0) {
+                    return Real::Rational(num_traits::Pow::pow(root, power));
+                }
+            }
+        }
+        Real::from(self.to_f64().powf(exp.to_f64()))
+    }
+
+    /// A literal with digits in `radix`, such as the `ff` of `0xff`.
+    pub fn from_radix(digits: &str, radix: u32) -> Option<Real> {
+        let n = BigInt::parse_bytes(digits.as_bytes(), radix)?;
+        Some(Real::Rational(BigRational::from_integer(n)))
+    }
+}
+
+/// The rational `n`th root of `r`, if there is one.
+fn exact_root(r: &BigRational, n: u32) -> Option<BigRational> {
+    if n == 0 || (r.is_negative() && n.is_multiple_of(2)) {
+        return None;
+    }
+    let root = |x: &BigInt| {
+        let y = x.nth_root(n);
+        (num_traits::Pow::pow(&y, n) == *x).then_some(y)
+    };
+    Some(BigRational::new(root(r.numer())?, root(r.denom())?))
+}
+
+impl From<i64> for Real {
+    fn from(n: i64) -> Real {
+        Real::Rational(BigRational::from_integer(n.into()))
+    }
+}
+
+/// Whole numbers become exact; anything else stays a float.
+impl From<f64> for Real {
+    fn from(x: f64) -> Real {
+        if x.fract() == 0.0 {
+            match BigRational::from_float(x) {
+                Some(r) => Real::Rational(r),
+                None => Real::Float(OrderedFloat(x)),
+            }
+        } else {
+            Real::Float(OrderedFloat(x))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseRealError;
+
+/// Decimal literals, such as `-12`, `0.1` or `6.02e23`, read exactly.
+impl FromStr for Real {
+    type Err = ParseRealError;
+
+    fn from_str(s: &str) -> Result<Real, ParseRealError> {
+        let (negative, unsigned) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let (mantissa, exponent) = match unsigned.split_once(['e', 'E']) {
+            Some((m, e)) => (m, e.parse::<i64>().map_err(|_| ParseRealError)?),
+            None => (unsigned, 0),
+        };
+        let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let digits = format!("{}{}", whole, fraction);
+        let valid = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if digits.is_empty() || !valid(whole) || !valid(fraction) {
+            // Spellings such as `inf` and `NaN`
+            return s.parse::<f64>().map(Real::from).map_err(|_| ParseRealError);
+        }
+        let scale = exponent - fraction.len() as i64;
+        if scale.unsigned_abs() > MAX_EXACT_EXPONENT as u64 {
+            return s.parse::<f64>().map(Real::from).map_err(|_| ParseRealError);
+        }
+        let mut value =
+            BigRational::from_integer(digits.parse::<BigInt>().map_err(|_| ParseRealError)?);
+        let ten = BigRational::from_integer(10.into());
+        value *= num_traits::Pow::pow(ten, scale as i32);
+        Ok(Real::Rational(if negative { -value } else { value }))
+    }
+}
+
+/// Rationals as whole numbers, terminating decimals or `n/d`; floats as
+/// `f64` prints them.
+impl fmt::Display for Real {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Real::Rational(r) if r.is_integer() => write!(f, "{}", r.numer()),
+            Real::Rational(r) if self.is_terminating() => {
+                // Scale by a power of ten to a whole number, then place the point
+                let mut places = 0;
+                let mut scaled = r.abs();
+                while !scaled.is_integer() {
+                    scaled *= BigRational::from_integer(10.into());
+                    places += 1;
+                }
+                let digits = format!("{:0>width$}", scaled.numer(), width = places + 1);
+                let (whole, fraction) = digits.split_at(digits.len() - places);
+                let sign = if r.is_negative() { "-" } else { "" };
+                write!(f, "{}{}.{}", sign, whole, fraction)
+            }
+            Real::Rational(r) => write!(f, "{}/{}", r.numer(), r.denom()),
+            Real::Float(x) => write!(f, "{}", x.0),
+        }
+    }
+}
+
+/// By value, and a float before a rational it rounds to, so that the order
+/// agrees with `Eq`.
+impl Ord for Real {
+    fn cmp(&self, other: &Real) -> Ordering {
+        match (self, other) {
+            (Real::Rational(a), Real::Rational(b)) => a.cmp(b),
+            _ => OrderedFloat(self.to_f64())
+                .cmp(&OrderedFloat(other.to_f64()))
+                .then_with(|| self.is_exact().cmp(&other.is_exact())),
+        }
+    }
+}
+
+impl PartialOrd for Real {
+    fn partial_cmp(&self, other: &Real) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Exact arithmetic between rationals, float arithmetic otherwise.
+macro_rules! arithmetic {
+    ($trait:ident, $method:ident, $checked:expr) => {
+        impl $trait<&Real> for &Real {
+            type Output = Real;
+
+            fn $method(self, other: &Real) -> Real {
+                match (self, other) {
+                    (Real::Rational(a), Real::Rational(b)) => {
+                        let checked: fn(&BigRational, &BigRational) -> Option<BigRational> =
+                            $checked;
+                        match checked(a, b) {
+                            Some(r) => Real::Rational(r),
+                            None => Real::from(self.to_f64().$method(other.to_f64())),
+                        }
+                    }
+                    _ => Real::from(self.to_f64().$method(other.to_f64())),
+                }
+            }
+        }
+
+        impl $trait for Real {
+            type Output = Real;
+
+            fn $method(self, other: Real) -> Real {
+                (&self).$method(&other)
+            }
+        }
+    };
+}
+
+// Adding zero or multiplying by one skips the gcds, slow for big numbers
+arithmetic!(Add, add, |a, b| Some(match (a.is_zero(), b.is_zero()) {
+    (true, _) => b.clone(),
+    (_, true) => a.clone(),
+    _ => a + b,
+}));
+arithmetic!(Sub, sub, |a, b| Some(if b.is_zero() {
+    a.clone()
+} else {
+    a - b
+}));
+arithmetic!(Mul, mul, |a, b| Some(match (a.is_one(), b.is_one()) {
+    (true, _) => b.clone(),
+    (_, true) => a.clone(),
+    _ => a * b,
+}));
+// Division by zero gives the float infinity or NaN
+arithmetic!(Div, div, |a, b| (!b.is_zero()).then(|| a / b));
+
+impl Neg for &Real {
+    type Output = Real;
+
+    fn neg(self) -> Real {
+        match self {
+            Real::Rational(r) => Real::Rational(-r),
+            Real::Float(x) => Real::Float(-*x),
+        }
+    }
+}
+
+impl Neg for Real {
+    type Output = Real;
+
+    fn neg(self) -> Real {
+        -&self
+    }
+}
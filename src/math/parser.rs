@@ -1,57 +1,444 @@
 use crate::math::ast::*;
-use regex::Regex;
+pub use crate::math::error::{Expected, NumberError, ParseError, ParseWarning, Span};
+use crate::math::latex::parse_latex_statement;
+use crate::math::unicode::normalize;
+use std::borrow::Cow;
 use std::str::Chars;
 
-pub fn latex_to_math_expr(latex: &str) -> String {
-    let mut s = latex.to_string();
+/// Settings for the plain-text parser.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseOptions {
+    /// Read runs of letters such as `xy` or `2xsin(x)` as products of single
+    /// letters and known names instead of one identifier.
+    pub split_identifiers: bool,
+    /// Multi-letter variable names that are never split, such as `theta`.
+    pub variables: Vec<String>,
+}
 
-    // Replace common LaTeX trig/log functions with simpler names
-    s = s.replace(r"\sin", "sin");
-    s = s.replace(r"\cos", "cos");
-    s = s.replace(r"\tan", "tan");
-    s = s.replace(r"\log", "log");
-    s = s.replace(r"\exp", "exp");
-    s = s.replace(r"\abs", "abs");
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            split_identifiers: true,
+            variables: GREEK_LETTERS.iter().map(|g| g.to_string()).collect(),
+        }
+    }
+}
 
-    // Replace \frac{a}{b} with (a)/(b)
-    let re_frac = Regex::new(r"\\frac\s*\{([^}]*)\}\s*\{([^}]*)\}").unwrap();
-    s = re_frac.replace_all(&s, "($1)/($2)").into_owned();
+impl ParseOptions {
+    /// Keep `name` whole when splitting identifiers.
+    pub fn declare(&mut self, name: &str) {
+        if !self.variables.iter().any(|v| v == name) {
+            self.variables.push(name.to_string());
+        }
+    }
+}
+
+/// Parse a line of user input, choosing the LaTeX front end when the input
+/// contains LaTeX commands, `$` delimiters or braced groups.
+pub fn parse_input(input: &str) -> Result<Statement, ParseError> {
+    parse_input_with(input, &ParseOptions::default()).map(|(stmt, _)| stmt)
+}
 
-    // Remove $ signs commonly used in LaTeX math mode
-    s = s.replace("$", "");
+/// Whether `input` is LaTeX rather than plain notation, as `x^{2}` is
+/// even without a command.
+fn is_latex(input: &str) -> bool {
+    input.contains(['\\', '$', '{', '}'])
+}
 
-    s
+pub fn parse_input_with(
+    input: &str,
+    options: &ParseOptions,
+) -> Result<(Statement, Vec<ParseWarning>), ParseError> {
+    if is_latex(input) {
+        Ok((parse_latex_statement(input)?, Vec::new()))
+    } else {
+        parse_statement_with(input, options)
+    }
 }
 
-pub fn parse(expr: &str) -> Result<Expr, String> {
-    let mut parser = Parser::new(expr);
-    parser.skip_whitespace();
-    let parsed = parser.parse_expr()?;
+/// Parse `;`-separated statements, e.g. `f(x) = x^2; a = 3; f(a)`.
+///
+/// Error spans are relative to the whole input.
+pub fn parse_program(input: &str) -> Result<Vec<Statement>, ParseError> {
+    parse_program_with(input, &ParseOptions::default()).map(|(program, _)| program)
+}
 
-    parser.skip_whitespace();
-    if parser.curr.is_some() {
-        return Err("Unexpected characters after expression".to_string());
+/// [`parse_program`] with warnings. Names assigned or defined by one
+/// statement are declared for the statements after it, so `rate = 2; 3rate`
+/// does not split `rate`.
+pub fn parse_program_with(
+    input: &str,
+    options: &ParseOptions,
+) -> Result<(Vec<Statement>, Vec<ParseWarning>), ParseError> {
+    let mut options = options.clone();
+    let mut statements = Vec::new();
+    let mut warnings = Vec::new();
+    for (offset, chunk) in split_statements(input) {
+        if !chunk.trim().is_empty() {
+            let (stmt, chunk_warnings) =
+                parse_input_with(chunk, &options).map_err(|e| e.offset(offset))?;
+            if let Statement::Assign(name, _) | Statement::Define(name, _, _) = &stmt {
+                options.declare(name);
+            }
+            warnings.extend(chunk_warnings.into_iter().map(|w| w.offset(offset)));
+            statements.push(stmt);
+        }
     }
+    Ok((statements, warnings))
+}
 
-    Ok(parsed)
+/// The statements of a program with their offsets into it, split at each
+/// `;` outside brackets that is not the LaTeX space `\;`.
+fn split_statements(input: &str) -> Vec<(usize, &str)> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut depth = 0usize;
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth = depth.saturating_sub(1),
+            ';' if depth == 0 => {
+                statements.push((start, &input[start..i]));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    statements.push((start, &input[start..]));
+    statements
+}
+
+pub fn parse(expr: &str) -> Result<Expr, ParseError> {
+    let normalized = normalize(expr);
+    parse_normalized(&normalized.text).map_err(|e| e.map_spans(|pos| normalized.origin(pos)))
+}
+
+fn parse_normalized(expr: &str) -> Result<Expr, ParseError> {
+    let options = ParseOptions::default();
+    Parser::new(expr, &options).parse_whole_expr()
+}
+
+/// Parse as much of `expr` as possible, for input that is still being
+/// typed. Missing operands (`x^`) and unreadable input (`x + #`) become
+/// [`Expr::Hole`]s, unclosed brackets are closed at the end (`sin(x` is
+/// `sin(x)`), and text after a complete expression is dropped. Returns the
+/// errors passed over, which are empty exactly when [`parse`] succeeds.
+pub fn parse_recovering(expr: &str) -> (Expr, Vec<ParseError>) {
+    let normalized = normalize(expr);
+    let options = ParseOptions::default();
+    let mut parser = Parser::new(&normalized.text, &options);
+    parser.recovered = Some(Vec::new());
+    let parsed = parser.parse_whole_expr();
+    let mut errors = parser.recovered.unwrap_or_default();
+    let parsed = parsed.unwrap_or_else(|err| {
+        errors.push(err);
+        Expr::Hole(Hole::Error)
+    });
+    let errors = errors
+        .into_iter()
+        .map(|e| e.map_spans(|pos| normalized.origin(pos)))
+        .collect();
+    (parsed, errors)
+}
+
+/// Parse an expression or a relation such as `y = x^2` or `x >= 3`.
+pub fn parse_statement(input: &str) -> Result<Statement, ParseError> {
+    parse_statement_with(input, &ParseOptions::default()).map(|(stmt, _)| stmt)
+}
+
+/// [`parse_statement`] with options and warnings. Unicode symbols are
+/// normalized first (see [`normalize`]), with spans still pointing into
+/// `input`.
+pub fn parse_statement_with(
+    input: &str,
+    options: &ParseOptions,
+) -> Result<(Statement, Vec<ParseWarning>), ParseError> {
+    let normalized = normalize(input);
+    let origin = |pos| normalized.origin(pos);
+    let options = with_assigned_name(&normalized.text, options);
+    let mut parser = Parser::new(&normalized.text, &options);
+    let statement = parser
+        .parse_whole_statement()
+        .map_err(|e| e.map_spans(origin))?;
+    let warnings = parser
+        .warnings
+        .into_iter()
+        .map(|w| w.map_spans(origin))
+        .collect();
+    Ok((statement, warnings))
+}
+
+/// A program parsed by [`parse_program_recovering`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recovered {
+    /// The statements, with [`Expr::Hole`]s where input is missing or
+    /// could not be read.
+    pub program: Vec<Statement>,
+    pub warnings: Vec<ParseWarning>,
+    /// Every error passed over, in order; empty exactly when
+    /// [`parse_program_with`] succeeds.
+    pub errors: Vec<ParseError>,
+}
+
+/// [`parse_program_with`] that carries on past errors like
+/// [`parse_recovering`], so a line being typed still yields a partial
+/// program. A LaTeX statement that fails to parse becomes a single hole.
+pub fn parse_program_recovering(input: &str, options: &ParseOptions) -> Recovered {
+    let mut options = options.clone();
+    let mut recovered = Recovered {
+        program: Vec::new(),
+        warnings: Vec::new(),
+        errors: Vec::new(),
+    };
+    for (offset, chunk) in split_statements(input) {
+        if !chunk.trim().is_empty() {
+            let (stmt, warnings, errors) = recover_statement(chunk, &options);
+            if let Statement::Assign(name, _) | Statement::Define(name, _, _) = &stmt {
+                options.declare(name);
+            }
+            recovered
+                .warnings
+                .extend(warnings.into_iter().map(|w| w.offset(offset)));
+            recovered
+                .errors
+                .extend(errors.into_iter().map(|e| e.offset(offset)));
+            recovered.program.push(stmt);
+        }
+    }
+    recovered
+}
+
+fn recover_statement(
+    input: &str,
+    options: &ParseOptions,
+) -> (Statement, Vec<ParseWarning>, Vec<ParseError>) {
+    let hole = Statement::Expr(Expr::Hole(Hole::Error));
+    if is_latex(input) {
+        return match parse_latex_statement(input) {
+            Ok(stmt) => (stmt, Vec::new(), Vec::new()),
+            Err(err) => (hole, Vec::new(), vec![err]),
+        };
+    }
+
+    let normalized = normalize(input);
+    let origin = |pos| normalized.origin(pos);
+    let options = with_assigned_name(&normalized.text, options);
+    let mut parser = Parser::new(&normalized.text, &options);
+    parser.recovered = Some(Vec::new());
+    let statement = parser.parse_whole_statement();
+    let mut errors = parser.recovered.unwrap_or_default();
+    let statement = statement.unwrap_or_else(|err| {
+        errors.push(err);
+        hole
+    });
+    (
+        statement,
+        parser
+            .warnings
+            .into_iter()
+            .map(|w| w.map_spans(origin))
+            .collect(),
+        errors.into_iter().map(|e| e.map_spans(origin)).collect(),
+    )
+}
+
+/// `options` with the name on the left of an assignment such as `rate = 2`
+/// declared, so that it is never split.
+fn with_assigned_name<'o>(input: &str, options: &'o ParseOptions) -> Cow<'o, ParseOptions> {
+    match assigned_name(input) {
+        Some(name) if options.split_identifiers => {
+            let mut with_name = options.clone();
+            with_name.declare(name);
+            Cow::Owned(with_name)
+        }
+        _ => Cow::Borrowed(options),
+    }
+}
+
+/// `name` when `input` has the form `name = ...`.
+fn assigned_name(input: &str) -> Option<&str> {
+    let input = input.trim_start();
+    let end = input
+        .find(|c: char| !c.is_alphabetic())
+        .unwrap_or(input.len());
+    let rest = input[end..].trim_start();
+    (end > 0 && rest.starts_with('=') && !rest.starts_with("==")).then(|| &input[..end])
 }
 
 struct Parser<'a> {
+    input: &'a str,
     chars: Chars<'a>,
     curr: Option<char>,
+    /// Byte offset of `curr` in the input.
+    pos: usize,
+    options: &'a ParseOptions,
+    warnings: Vec<ParseWarning>,
+    /// Number of open `|` bars; inside one, `|` closes instead of opening.
+    abs_depth: usize,
+    /// Errors carried past when recovering, see [`parse_recovering`];
+    /// `None` when parsing strictly.
+    recovered: Option<Vec<ParseError>>,
 }
 
 impl<'a> Parser<'a> {
-    fn new(input: &'a str) -> Self {
+    fn new(input: &'a str, options: &'a ParseOptions) -> Self {
         let mut chars = input.chars();
         let curr = chars.next();
-        Parser { chars, curr }
+        Parser {
+            input,
+            chars,
+            curr,
+            pos: 0,
+            options,
+            warnings: Vec::new(),
+            abs_depth: 0,
+            recovered: None,
+        }
+    }
+
+    /// An expression filling the whole input.
+    fn parse_whole_expr(&mut self) -> Result<Expr, ParseError> {
+        self.skip_whitespace();
+        let parsed = self.parse_expr()?;
+        self.expect_end(vec![Expected::Operator, Expected::EndOfInput])?;
+        Ok(parsed)
+    }
+
+    /// An expression, relation or definition filling the whole input.
+    fn parse_whole_statement(&mut self) -> Result<Statement, ParseError> {
+        let lhs = self.parse_expr()?;
+
+        self.skip_whitespace();
+        // A single `=` may also be an assignment or definition; `==` is always
+        // a comparison.
+        let defining = self.curr == Some('=') && self.peek_next() != Some('=');
+        let statement = match self.parse_rel_op() {
+            Some(_) if defining => Statement::equation(lhs, self.parse_expr()?),
+            Some(op) => {
+                let rhs = self.parse_expr()?;
+                Statement::Relation(Relation { op, lhs, rhs })
+            }
+            None => Statement::Expr(lhs),
+        };
+
+        let mut expected = vec![Expected::Operator];
+        if matches!(statement, Statement::Expr(_)) {
+            expected.push(Expected::Relation);
+        }
+        expected.push(Expected::EndOfInput);
+        self.expect_end(expected)?;
+        Ok(statement)
+    }
+
+    /// Fail unless all input has been read. Recovering drops the rest.
+    fn expect_end(&mut self, expected: Vec<Expected>) -> Result<(), ParseError> {
+        self.skip_whitespace();
+        if self.curr.is_none() {
+            return Ok(());
+        }
+        let err = self.unexpected(expected);
+        self.recover(err, ())?;
+        self.seek(self.input.len());
+        Ok(())
+    }
+
+    /// In recovering mode, record `err` and carry on with `fallback` in
+    /// place of what was expected; otherwise fail with it.
+    fn recover<T>(&mut self, err: ParseError, fallback: T) -> Result<T, ParseError> {
+        match &mut self.recovered {
+            Some(errors) => {
+                errors.push(err);
+                Ok(fallback)
+            }
+            None => Err(err),
+        }
+    }
+
+    /// Move back to the byte offset `pos`, re-reading from there.
+    fn seek(&mut self, pos: usize) {
+        self.chars = self.input[pos..].chars();
+        self.curr = self.chars.next();
+        self.pos = pos;
+    }
+
+    /// The character after `curr`.
+    fn peek_next(&self) -> Option<char> {
+        self.peek_nth(1)
+    }
+
+    /// The character `n` places after `curr`.
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.chars.clone().nth(n - 1)
     }
 
     fn bump(&mut self) {
+        if let Some(c) = self.curr {
+            self.pos += c.len_utf8();
+        }
         self.curr = self.chars.next();
     }
 
+    /// Consume a relational operator (`=`, `==`, `!=`, `<`, `<=`, `>`, `>=`).
+    fn parse_rel_op(&mut self) -> Option<RelOp> {
+        let (op, len) = match (self.curr?, self.peek_next()) {
+            ('=', Some('=')) => (RelOp::Eq, 2),
+            ('=', _) => (RelOp::Eq, 1),
+            ('!', Some('=')) => (RelOp::Ne, 2),
+            ('<', Some('=')) => (RelOp::Le, 2),
+            ('<', _) => (RelOp::Lt, 1),
+            ('>', Some('=')) => (RelOp::Ge, 2),
+            ('>', _) => (RelOp::Gt, 1),
+            _ => return None,
+        };
+        for _ in 0..len {
+            self.bump();
+        }
+        Some(op)
+    }
+
+    /// Error for the current character (or end of input) not being one of `expected`.
+    fn unexpected(&self, expected: Vec<Expected>) -> ParseError {
+        match self.curr {
+            Some(found) => ParseError::UnexpectedChar {
+                found,
+                span: Span::new(self.pos, self.pos + found.len_utf8()),
+                expected,
+            },
+            None => ParseError::UnexpectedEnd {
+                span: Span::point(self.pos),
+                expected,
+            },
+        }
+    }
+
+    /// Consume the `)` matching the `(` at `open`.
+    fn expect_close_paren(&mut self, open: usize) -> Result<(), ParseError> {
+        self.skip_whitespace();
+        match self.curr {
+            Some(')') => {
+                self.bump();
+                Ok(())
+            }
+            // Recovering closes the parenthesis where it should have been
+            None => {
+                let err = ParseError::UnclosedDelimiter {
+                    open: '(',
+                    open_span: Span::new(open, open + 1),
+                    span: Span::point(self.pos),
+                };
+                self.recover(err, ())
+            }
+            Some(_) => {
+                let err = self.unexpected(vec![Expected::Char(')'), Expected::Operator]);
+                self.recover(err, ())
+            }
+        }
+    }
+
     fn skip_whitespace(&mut self) {
         while let Some(c) = self.curr {
             if c.is_whitespace() {
@@ -62,143 +449,749 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_expr(&mut self) -> Result<Expr, String> {
-        self.parse_add_sub()
+    /// An expression, a range `a..b` of two, or a conditional
+    /// `a if c else b`.
+    fn parse_expr(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_binary(0)?;
+        self.skip_whitespace();
+        if self.curr == Some('.') && self.peek_next() == Some('.') {
+            self.bump();
+            self.bump();
+            let end = self.parse_binary(0)?;
+            expr = Expr::Function("range".into(), vec![expr, end]);
+        }
+        self.skip_whitespace();
+        if self.eat_keyword("if") {
+            return self.parse_conditional(expr);
+        }
+        Ok(expr)
     }
 
-    fn parse_add_sub(&mut self) -> Result<Expr, String> {
-        let mut node = self.parse_mul_div()?;
-        loop {
-            self.skip_whitespace();
-            match self.curr {
-                Some('+') => {
-                    self.bump();
-                    let rhs = self.parse_mul_div()?;
-                    node = Expr::BinaryOp(BinaryOp::Add, Box::new(node), Box::new(rhs));
-                }
-                Some('-') => {
-                    self.bump();
-                    let rhs = self.parse_mul_div()?;
-                    node = Expr::BinaryOp(BinaryOp::Sub, Box::new(node), Box::new(rhs));
-                }
-                _ => break,
+    /// The rest of `value if condition [else ...]`, after the `if`. A chain
+    /// `a if c else b if d else e` is one piecewise expression.
+    fn parse_conditional(&mut self, value: Expr) -> Result<Expr, ParseError> {
+        let lhs = self.parse_binary(0)?;
+        self.skip_whitespace();
+        let (op, rhs) = match self.parse_rel_op() {
+            Some(op) => (op, self.parse_binary(0)?),
+            None => {
+                let err = self.unexpected(vec![Expected::Relation, Expected::Operator]);
+                self.recover(err, (RelOp::Eq, Expr::Hole(Hole::Missing)))?
             }
+        };
+        let mut branches = vec![(value, Relation { op, lhs, rhs })];
+
+        self.skip_whitespace();
+        if !self.eat_keyword("else") {
+            return Ok(Expr::Piecewise(branches, None));
         }
-        Ok(node)
+        let otherwise = match self.parse_expr()? {
+            Expr::Piecewise(rest, otherwise) => {
+                branches.extend(rest);
+                otherwise
+            }
+            fallback => Some(Box::new(fallback)),
+        };
+        Ok(Expr::Piecewise(branches, otherwise))
     }
 
-    fn parse_mul_div(&mut self) -> Result<Expr, String> {
-        let mut node = self.parse_pow()?;
+    /// Whether the word `keyword` (`if` or `else`) is at the cursor.
+    fn at_keyword(&self, keyword: &str) -> bool {
+        let rest = &self.input[self.pos..];
+        rest.starts_with(keyword)
+            && !rest[keyword.len()..]
+                .starts_with(|c: char| c.is_alphanumeric() || c == '_' || c == '\'')
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if !self.at_keyword(keyword) {
+            return false;
+        }
+        for _ in keyword.chars() {
+            self.bump();
+        }
+        true
+    }
+
+    /// Precedence climbing over the shared `OPERATORS` table: parse operands
+    /// joined by operators that bind at least as tightly as `min_prec`.
+    fn parse_binary(&mut self, min_prec: u8) -> Result<Expr, ParseError> {
+        let mut node = self.parse_unary()?;
 
         loop {
             self.skip_whitespace();
 
-            match self.curr {
-                Some('*') => {
-                    self.bump();
-                    let rhs = self.parse_pow()?;
-                    node = Expr::BinaryOp(BinaryOp::Mul, Box::new(node), Box::new(rhs));
-                }
-                Some('/') => {
-                    self.bump();
-                    let rhs = self.parse_pow()?;
-                    node = Expr::BinaryOp(BinaryOp::Div, Box::new(node), Box::new(rhs));
-                }
-                // Implicit multiplication detection:
-                // If next token looks like the start of an atom/pow (number, letter, or '(')
-                Some(c) if c.is_ascii_digit() || c.is_alphabetic() || c == '(' => {
-                    // Parse next primary expression (power)
-                    let rhs = self.parse_pow()?;
-                    node = Expr::BinaryOp(BinaryOp::Mul, Box::new(node), Box::new(rhs));
-                }
-                _ => break,
+            let (op, explicit) = match self.curr {
+                Some(c) => match BinaryOp::from_symbol(c) {
+                    Some(op) => (op, true),
+                    // Implicit multiplication detection:
+                    // If next token looks like the start of an operand (number, letter, '(', ...)
+                    None if self.starts_implicit_factor(c) => (BinaryOp::Mul, false),
+                    None => break,
+                },
+                None => break,
+            };
+
+            let prec = op.precedence();
+            if prec < min_prec {
+                break;
             }
+            if explicit {
+                self.bump();
+            }
+
+            let next_min = match op.assoc() {
+                Assoc::Left => prec + 1,
+                Assoc::Right => prec,
+            };
+            let rhs = self.parse_binary(next_min)?;
+            node = Expr::BinaryOp(op, Box::new(node), Box::new(rhs));
         }
 
         Ok(node)
     }
 
-    fn parse_pow(&mut self) -> Result<Expr, String> {
-        let base = self.parse_unary()?;
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
         self.skip_whitespace();
-        if self.curr == Some('^') {
+        if self.curr == Some('-') {
             self.bump();
-            let exp = self.parse_unary()?;
-            Ok(Expr::BinaryOp(BinaryOp::Pow, Box::new(base), Box::new(exp)))
+            let expr = self.parse_binary(NEG_PRECEDENCE + 1)?;
+            Ok(Expr::UnaryOp(UnaryOp::Neg, Box::new(expr)))
         } else {
-            Ok(base)
+            let atom = self.parse_atom()?;
+            self.parse_postfix(atom)
         }
     }
 
-    fn parse_unary(&mut self) -> Result<Expr, String> {
-        self.skip_whitespace();
-        if self.curr == Some('-') {
+    /// Postfix `!` and `%`, as in `5!` or `15%`. `!=` is left for the
+    /// relation.
+    fn parse_postfix(&mut self, mut expr: Expr) -> Result<Expr, ParseError> {
+        loop {
+            self.skip_whitespace();
+            let op = match (self.curr, self.peek_next()) {
+                (Some('!'), next) if next != Some('=') => UnaryOp::Factorial,
+                (Some('%'), _) => UnaryOp::Percent,
+                _ => return Ok(expr),
+            };
             self.bump();
-            let expr = self.parse_unary()?;
-            Ok(Expr::UnaryOp(UnaryOp::Neg, Box::new(expr)))
-        } else {
-            self.parse_atom()
+            expr = Expr::UnaryOp(op, Box::new(expr));
         }
     }
 
-    fn parse_atom(&mut self) -> Result<Expr, String> {
+    fn parse_atom(&mut self) -> Result<Expr, ParseError> {
         self.skip_whitespace();
         match self.curr {
-            Some(c) if c.is_ascii_digit() || c == '.' => self.parse_number(),
+            Some(c) if c.is_ascii_digit() || c == '.' => match self.parse_number() {
+                Err(err) => self.recover(err, Expr::Hole(Hole::Error)),
+                number => number,
+            },
             Some(c) if c.is_alphabetic() => self.parse_ident_or_func(),
             Some('(') => {
+                let open = self.pos;
                 self.bump();
                 let inner = self.parse_expr()?;
+                self.expect_close_paren(open)?;
+                Ok(inner)
+            }
+            Some(c @ ('√' | '∛' | '∜')) => {
+                self.bump();
+                // The radicand binds like an exponent: `√x^2` is `√(x^2)`
+                // while `√2x` is `(√2)x`.
+                let radicand = self.parse_binary(BinaryOp::Pow.precedence())?;
+                let index = match c {
+                    '√' => return Ok(Expr::Function("sqrt".into(), vec![radicand])),
+                    '∛' => 3,
+                    _ => 4,
+                };
+                Ok(Expr::Function(
+                    "root".into(),
+                    vec![radicand, Expr::Number(Real::from(index))],
+                ))
+            }
+            Some('[') => {
+                let open = self.pos;
+                self.bump();
+                self.skip_whitespace();
+                let items = if self.curr == Some(']') {
+                    Vec::new()
+                } else {
+                    self.parse_args()?
+                };
                 self.skip_whitespace();
-                if self.curr != Some(')') {
-                    return Err("Expected ')'".into());
+                match self.curr {
+                    Some(']') => self.bump(),
+                    None => {
+                        let err = ParseError::UnclosedDelimiter {
+                            open: '[',
+                            open_span: Span::new(open, open + 1),
+                            span: Span::point(self.pos),
+                        };
+                        self.recover(err, ())?
+                    }
+                    Some(_) => {
+                        let err = self.unexpected(vec![
+                            Expected::Char(','),
+                            Expected::Char(']'),
+                            Expected::Operator,
+                        ]);
+                        self.recover(err, ())?
+                    }
                 }
+                Ok(Expr::List(items))
+            }
+            Some('|') => {
+                let open = self.pos;
                 self.bump();
-                Ok(inner)
+                self.abs_depth += 1;
+                let inner = self.parse_expr();
+                self.abs_depth -= 1;
+                let inner = inner?;
+                self.skip_whitespace();
+                match self.curr {
+                    Some('|') => self.bump(),
+                    None => {
+                        let err = ParseError::UnclosedDelimiter {
+                            open: '|',
+                            open_span: Span::new(open, open + 1),
+                            span: Span::point(self.pos),
+                        };
+                        self.recover(err, ())?
+                    }
+                    Some(_) => {
+                        let err = self.unexpected(vec![Expected::Char('|'), Expected::Operator]);
+                        self.recover(err, ())?
+                    }
+                }
+                Ok(Expr::Function("abs".into(), vec![inner]))
+            }
+            _ => {
+                let err = self.unexpected(vec![Expected::Expression]);
+                if self.recovered.is_none() {
+                    return Err(err);
+                }
+                // A stray character is skipped; before an operator, a closing
+                // bracket or the end, the operand has just not been typed yet
+                let hole = match self.curr {
+                    Some(c) if !"+-*/^)]|,;=<>!%".contains(c) => {
+                        self.bump();
+                        Hole::Error
+                    }
+                    _ => Hole::Missing,
+                };
+                self.recover(err, Expr::Hole(hole))
             }
-            Some(c) => Err(format!("Unexpected character '{}'", c)),
-            None => Err("Unexpected end of input".into()),
         }
     }
 
-    fn parse_number(&mut self) -> Result<Expr, String> {
-        let mut num = String::new();
-        while let Some(c) = self.curr {
-            if c.is_ascii_digit() || c == '.' {
-                num.push(c);
+    /// Numeric literals: decimals with optional exponent (`6.02e23`, `1E-9`,
+    /// `.5`), `0x`/`0o`/`0b` integers, and `_` digit separators
+    /// (`1_000_000`).
+    fn parse_number(&mut self) -> Result<Expr, ParseError> {
+        let start = self.pos;
+
+        if self.curr == Some('0') {
+            let radix = match self.peek_next() {
+                Some('x' | 'X') => Some(16),
+                Some('o' | 'O') => Some(8),
+                Some('b' | 'B') => Some(2),
+                _ => None,
+            };
+            if let Some(radix) = radix {
+                self.bump();
                 self.bump();
+                let digits = self.eat_digits(radix, start)?;
+                if self.curr.is_some_and(|c| c.is_ascii_digit()) {
+                    while self.curr.is_some_and(|c| c.is_ascii_alphanumeric()) {
+                        self.bump();
+                    }
+                    return Err(self.invalid_number(start, NumberError::InvalidDigit));
+                }
+                if digits.is_empty() {
+                    return Err(self.invalid_number(start, NumberError::MissingDigits));
+                }
+                let value = Real::from_radix(&digits, radix)
+                    .ok_or_else(|| self.invalid_number(start, NumberError::InvalidDigit))?;
+                return Ok(Expr::Number(value));
+            }
+        }
+
+        // `..` after a number is a range, as in `1..10`
+        let decimal_point = |p: &Self| p.curr == Some('.') && p.peek_next() != Some('.');
+        let mut num = self.eat_digits(10, start)?;
+        if decimal_point(self) {
+            self.bump();
+            num.push('.');
+            num.push_str(&self.eat_digits(10, start)?);
+            if decimal_point(self) {
+                while matches!(self.curr, Some(c) if c.is_ascii_digit() || c == '.' || c == '_') {
+                    self.bump();
+                }
+                return Err(self.invalid_number(start, NumberError::MultipleDecimalPoints));
+            }
+        }
+        if num == "." {
+            return Err(self.invalid_number(start, NumberError::MissingDigits));
+        }
+
+        // Only treat `e` as an exponent when digits follow, so `2e` stays `2*e`
+        if let Some('e' | 'E') = self.curr {
+            let sign = matches!(self.peek_next(), Some('+' | '-'));
+            let first_digit = if sign {
+                self.peek_nth(2)
             } else {
-                break;
+                self.peek_next()
+            };
+            if first_digit.is_some_and(|c| c.is_ascii_digit()) {
+                num.push('e');
+                self.bump();
+                if sign {
+                    num.push(self.curr.unwrap());
+                    self.bump();
+                }
+                num.push_str(&self.eat_digits(10, start)?);
             }
         }
+
         num.parse()
             .map(Expr::Number)
-            .map_err(|_| "Invalid number".into())
+            .map_err(|_| self.invalid_number(start, NumberError::MissingDigits))
     }
 
-    fn parse_ident_or_func(&mut self) -> Result<Expr, String> {
+    /// Consume a run of digits in `radix`, allowing single `_` separators
+    /// between digits, and return the digits with the separators removed.
+    fn eat_digits(&mut self, radix: u32, start: usize) -> Result<String, ParseError> {
+        let mut digits = String::new();
+        while let Some(c) = self.curr {
+            if c.is_digit(radix) {
+                digits.push(c);
+                self.bump();
+            } else if c == '_' {
+                self.bump();
+                let between_digits =
+                    !digits.is_empty() && self.curr.is_some_and(|d| d.is_digit(radix));
+                if !between_digits {
+                    while matches!(self.curr, Some(d) if d.is_alphanumeric() || d == '_') {
+                        self.bump();
+                    }
+                    return Err(self.invalid_number(start, NumberError::MisplacedSeparator));
+                }
+            } else {
+                break;
+            }
+        }
+        Ok(digits)
+    }
+
+    /// Error for the literal from `start` up to the current position.
+    fn invalid_number(&self, start: usize, reason: NumberError) -> ParseError {
+        ParseError::InvalidNumber {
+            literal: self.input[start..self.pos].to_string(),
+            reason,
+            span: Span::new(start, self.pos),
+        }
+    }
+
+    fn parse_ident_or_func(&mut self) -> Result<Expr, ParseError> {
+        let start = self.pos;
+        if self.curr == Some('d') && matches!(self.peek_next(), Some('/' | '^')) {
+            if let Some(derivative) = self.parse_leibniz()? {
+                return Ok(derivative);
+            }
+        }
         let mut ident = String::new();
         while let Some(c) = self.curr {
-            if c.is_alphanumeric() || c == '_' {
+            if c.is_alphanumeric() {
                 ident.push(c);
                 self.bump();
             } else {
                 break;
             }
         }
+        // `log_2` is a base, not a subscript
+        let subscript = if self.curr == Some('_') && ident != "log" {
+            Some(self.parse_subscript()?)
+        } else {
+            None
+        };
+
+        if self.options.split_identifiers && ident.chars().all(char::is_alphabetic) {
+            let rest = self.input[self.pos..].trim_start_matches('\'');
+            let called = rest.trim_start().starts_with('(');
+            let parts = self.split_identifier(&ident, called);
+            if parts.len() > 1 {
+                // Only a run's first piece warns, not the rest re-read below
+                let continues = self.input[..start]
+                    .chars()
+                    .next_back()
+                    .is_some_and(char::is_alphabetic);
+                let unknown = |p: &&str| p.chars().count() == 1 && !self.is_known_name(p);
+                let adjacent_unknown = parts.windows(2).any(|w| unknown(&w[0]) && unknown(&w[1]));
+                if !continues && adjacent_unknown {
+                    self.warnings.push(ParseWarning::SplitIdentifier {
+                        ident: ident.clone(),
+                        parts: parts.iter().map(|p| p.to_string()).collect(),
+                        span: Span::new(start, start + ident.len()),
+                    });
+                }
+                // Take the first piece; implicit multiplication picks up the
+                // rest, along with any subscript
+                ident = parts[0].to_string();
+                self.seek(start + ident.len());
+                return self.parse_name(ident, None, start);
+            }
+        }
+        self.parse_name(ident, subscript, start)
+    }
+
+    /// Leibniz notation, `d/dx f` or `d^2/dx^2 f`, applying to a product
+    /// of factors as `d/dx x^2 sin x` does. Returns `None` (having read
+    /// nothing) when the `d` is just a variable, as in `d/2`.
+    fn parse_leibniz(&mut self) -> Result<Option<Expr>, ParseError> {
+        let start = self.pos;
+        let order = |p: &mut Self| -> Option<u32> {
+            if p.curr != Some('^') {
+                return Some(1);
+            }
+            p.bump();
+            let digits = p.eat_digits(10, p.pos).ok()?;
+            digits.parse().ok().filter(|&n| n > 0)
+        };
+        self.bump();
+        let numerator = order(self);
+        let var = match (self.curr, self.peek_next()) {
+            (Some('/'), Some('d')) => {
+                self.bump();
+                self.bump();
+                self.parse_leibniz_var()
+            }
+            _ => None,
+        };
+        match (numerator, var) {
+            (Some(n), Some(var)) if order(self) == Some(n) => {
+                let operand = self.parse_binary(BinaryOp::Mul.precedence())?;
+                Ok(Some(Expr::Derivative(Box::new(operand), var, n)))
+            }
+            _ => {
+                self.seek(start);
+                Ok(None)
+            }
+        }
+    }
+
+    /// The variable of `d/dx`: the longest declared name at the cursor, such
+    /// as `theta`, or else a single letter.
+    fn parse_leibniz_var(&mut self) -> Option<String> {
+        let rest = &self.input[self.pos..];
+        let run = &rest[..rest
+            .find(|c: char| !c.is_alphabetic())
+            .unwrap_or(rest.len())];
+        let first = run.chars().next()?;
+        let var = run
+            .char_indices()
+            .skip(1)
+            .map(|(j, _)| &run[..j])
+            .chain(std::iter::once(run))
+            .filter(|p| self.options.variables.iter().any(|v| v == p))
+            .last()
+            .unwrap_or(&run[..first.len_utf8()])
+            .to_string();
+        self.seek(self.pos + var.len());
+        Some(var)
+    }
+
+    /// The subscript after `_`: `x_1`, `v_max` or `v_{max}`.
+    fn parse_subscript(&mut self) -> Result<String, ParseError> {
+        self.bump();
+        let braced = self.curr == Some('{');
+        let open = self.pos;
+        if braced {
+            self.bump();
+        }
+        // Unbraced, a subscript is all digits or all letters, so `x_1y` is
+        // `x_1 y`
+        let digits = self.curr.is_some_and(|c| c.is_ascii_digit());
+        let mut subscript = String::new();
+        while let Some(c) = self.curr.filter(|&c| {
+            if braced {
+                c.is_alphanumeric()
+            } else {
+                c.is_alphabetic() != digits && c.is_alphanumeric()
+            }
+        }) {
+            subscript.push(c);
+            self.bump();
+        }
+        if subscript.is_empty() {
+            return Err(self.unexpected(vec![Expected::Expression]));
+        }
+        if braced {
+            match self.curr {
+                Some('}') => self.bump(),
+                None => {
+                    let err = ParseError::UnclosedDelimiter {
+                        open: '{',
+                        open_span: Span::new(open, open + 1),
+                        span: Span::point(self.pos),
+                    };
+                    self.recover(err, ())?
+                }
+                Some(_) => {
+                    let err = self.unexpected(vec![Expected::Char('}')]);
+                    self.recover(err, ())?
+                }
+            }
+        }
+        Ok(subscript)
+    }
+
+    /// A name read by `parse_ident_or_func`: a function call, constant or
+    /// (subscripted) variable.
+    fn parse_name(
+        &mut self,
+        mut ident: String,
+        subscript: Option<String>,
+        start: usize,
+    ) -> Result<Expr, ParseError> {
+        if subscript.is_none() && function_arity(&ident).is_some() {
+            return self.parse_builtin_call(ident, start);
+        }
+        ident = Symbol::join(&ident, subscript.as_deref());
+        // Primes name a derivative, `f'(x)`
+        while self.curr == Some('\'') {
+            ident.push('\'');
+            self.bump();
+        }
 
         self.skip_whitespace();
         if self.curr == Some('(') {
+            let open = self.pos;
             self.bump();
-            let arg = self.parse_expr()?;
-            self.skip_whitespace();
-            if self.curr != Some(')') {
-                return Err("Expected ')' after function argument".into());
+            let args = self.parse_args()?;
+            self.expect_close_paren(open)?;
+            Ok(Expr::Function(ident, args))
+        } else if let Some(c) = Constant::from_name(&ident) {
+            Ok(Expr::Constant(c))
+        } else {
+            Ok(Expr::Variable(ident))
+        }
+    }
+
+    /// A built-in function applied with or without parentheses: `sin(x)`,
+    /// `sin x`, `sin^2 x` (the square of `sin x`), `sin^-1 x` (`asin x`) and
+    /// `log_2 8` (the base-2 logarithm).
+    fn parse_builtin_call(&mut self, mut name: String, start: usize) -> Result<Expr, ParseError> {
+        let base = if name == "log" && self.curr == Some('_') {
+            self.bump();
+            match self.curr {
+                // A letter base is a single letter, so `log_b(x)` is not a call of `b`
+                Some(c) if c.is_alphabetic() => {
+                    self.bump();
+                    let letter = c.to_string();
+                    Some(match Constant::from_name(&letter) {
+                        Some(k) => Expr::Constant(k),
+                        None => Expr::Variable(letter),
+                    })
+                }
+                _ => Some(self.parse_atom()?),
             }
+        } else {
+            None
+        };
+
+        self.skip_whitespace();
+        let mut power = None;
+        if self.curr == Some('^') {
             self.bump();
-            Ok(Expr::Function(ident, Box::new(arg)))
+            let p = self.parse_unary()?;
+            match inverse_function(&name) {
+                Some(inverse) if p.is_minus_one() => name = inverse,
+                _ => power = Some(p),
+            }
+        }
+
+        self.skip_whitespace();
+        let mut args = if self.curr == Some('(') {
+            let open = self.pos;
+            self.bump();
+            let args = self.parse_args()?;
+            self.expect_close_paren(open)?;
+            args
         } else {
-            Ok(Expr::Variable(ident))
+            vec![self.parse_textbook_arg()?]
+        };
+        if let Some(base) = base {
+            args.insert(0, base);
+        }
+
+        if let Some(arity) = function_arity(&name) {
+            if !arity.accepts(args.len()) {
+                // A recovered call with the wrong arity evaluates to NaN
+                let err = ParseError::ArityMismatch {
+                    name: name.clone(),
+                    expected: arity,
+                    found: args.len(),
+                    span: Span::new(start, self.pos),
+                };
+                self.recover(err, ())?;
+            }
+        }
+        let call = match name.as_str() {
+            "diff" | "int" if function_arity(&name).is_some_and(|a| a.accepts(args.len())) => {
+                self.parse_calculus_call(name, args, start)?
+            }
+            _ => Expr::Function(name, args),
+        };
+        Ok(match power {
+            Some(p) => Expr::BinaryOp(BinaryOp::Pow, Box::new(call), Box::new(p)),
+            None => call,
+        })
+    }
+
+    /// `diff(f, x)`, `diff(f, x, n)`, `int(f, x)` and `int(f, x, a, b)` as
+    /// `Expr::Derivative` and `Expr::Integral`. A recovered call with
+    /// invalid arguments is kept as a call, evaluating to NaN.
+    fn parse_calculus_call(
+        &mut self,
+        name: String,
+        args: Vec<Expr>,
+        start: usize,
+    ) -> Result<Expr, ParseError> {
+        let invalid = |expected| ParseError::InvalidArgument {
+            name: name.clone(),
+            expected,
+            span: Span::new(start, self.pos),
+        };
+        let err = match (name.as_str(), args.as_slice()) {
+            (_, [_, var, ..]) if !matches!(var, Expr::Variable(_)) => invalid("a variable"),
+            ("diff", [e, Expr::Variable(var)]) => {
+                return Ok(Expr::Derivative(Box::new(e.clone()), var.clone(), 1))
+            }
+            ("diff", [e, Expr::Variable(var), Expr::Number(n)])
+                if n.to_i64()
+                    .is_some_and(|k| (1..=u32::MAX as i64).contains(&k)) =>
+            {
+                let order = n.to_i64().unwrap() as u32;
+                return Ok(Expr::Derivative(Box::new(e.clone()), var.clone(), order));
+            }
+            ("diff", _) => invalid("a positive whole number"),
+            ("int", [e, Expr::Variable(var)]) => {
+                return Ok(Expr::Integral(Box::new(e.clone()), var.clone(), None))
+            }
+            ("int", [e, Expr::Variable(var), a, b]) => {
+                return Ok(Expr::Integral(
+                    Box::new(e.clone()),
+                    var.clone(),
+                    Some(Box::new((a.clone(), b.clone()))),
+                ))
+            }
+            // Arity was checked already, so this is `int(f, x, a)`
+            _ => invalid("both bounds"),
+        };
+        self.recover(err, Expr::Function(name, args))
+    }
+
+    /// The argument of a function applied without parentheses: factors
+    /// joined by implicit multiplication, stopping at explicit operators and
+    /// at the next function, so `sin 2x cos x` is `sin(2x) cos(x)` and
+    /// `sin x + 1` is `sin(x) + 1`.
+    fn parse_textbook_arg(&mut self) -> Result<Expr, ParseError> {
+        let pow = BinaryOp::Pow.precedence();
+        let mut arg = self.parse_binary(pow)?;
+        loop {
+            self.skip_whitespace();
+            match self.curr {
+                Some(c) if self.starts_implicit_factor(c) && !self.at_function() => {
+                    let factor = self.parse_binary(pow)?;
+                    arg = Expr::BinaryOp(BinaryOp::Mul, Box::new(arg), Box::new(factor));
+                }
+                _ => return Ok(arg),
+            }
+        }
+    }
+
+    /// Whether `c` begins an operand that multiplies the one before it.
+    fn starts_implicit_factor(&self, c: char) -> bool {
+        c.is_ascii_digit()
+            || (c.is_alphabetic() && !self.at_keyword("if") && !self.at_keyword("else"))
+            || matches!(c, '(' | '[' | '√' | '∛' | '∜')
+            || (c == '|' && self.abs_depth == 0)
+    }
+
+    /// Whether the identifier at the cursor begins with a built-in function.
+    fn at_function(&self) -> bool {
+        let rest = &self.input[self.pos..];
+        let end = rest
+            .find(|c: char| !c.is_alphabetic())
+            .unwrap_or(rest.len());
+        if end == 0 {
+            return false;
+        }
+        let run = &rest[..end];
+        let called = rest[end..]
+            .trim_start_matches('\'')
+            .trim_start()
+            .starts_with('(');
+        let first = if self.options.split_identifiers {
+            self.split_identifier(run, called)[0]
+        } else {
+            run
+        };
+        function_arity(first).is_some()
+    }
+
+    /// Constants and declared variables, which are kept whole when
+    /// splitting identifiers.
+    fn is_known_name(&self, name: &str) -> bool {
+        Constant::from_name(name).is_some() || self.options.variables.iter().any(|v| v == name)
+    }
+
+    /// Split a run of letters into known names and single letters, taking
+    /// the longest constant, declared variable or built-in function name at
+    /// each point, so `xsinx` is `x sin x`. A run that is itself a known
+    /// name stays whole, and so does a called run not ending in a function
+    /// or declared name: it names a user function, as in `area(r)`.
+    fn split_identifier<'s>(&self, run: &'s str, called: bool) -> Vec<&'s str> {
+        let known = |name: &str| self.is_known_name(name) || function_arity(name).is_some();
+        if known(run) {
+            return vec![run];
+        }
+        let mut parts = Vec::new();
+        let mut i = 0;
+        while i < run.len() {
+            let rest = &run[i..];
+            let piece = rest
+                .char_indices()
+                .skip(1)
+                .map(|(j, _)| &rest[..j])
+                .chain(std::iter::once(rest))
+                .filter(|p| known(p))
+                .last()
+                .unwrap_or_else(|| {
+                    let c = rest.chars().next().unwrap();
+                    &rest[..c.len_utf8()]
+                });
+            parts.push(piece);
+            i += piece.len();
+        }
+        let ends_in_function = parts.last().is_some_and(|p| {
+            function_arity(p).is_some() || self.options.variables.iter().any(|v| v == p)
+        });
+        if called && !ends_in_function {
+            return vec![run];
+        }
+        parts
+    }
+
+    /// Comma-separated function arguments, up to but not including the `)`.
+    fn parse_args(&mut self) -> Result<Vec<Expr>, ParseError> {
+        let mut args = vec![self.parse_expr()?];
+        loop {
+            self.skip_whitespace();
+            if self.curr != Some(',') {
+                return Ok(args);
+            }
+            self.bump();
+            args.push(self.parse_expr()?);
         }
     }
 }
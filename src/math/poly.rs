@@ -0,0 +1,560 @@
+use crate::math::algebra::{fold_binary_ops, simplify};
+use crate::math::ast::*;
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::{Add, Mul, Neg, Sub};
+
+/// Largest estimated size in bits of the coefficients of a power that
+/// `Poly::from_expr` multiplies out; `(10^1000)^1000` is left alone.
+const MAX_POWER_BITS: u64 = 1 << 16;
+
+/// A polynomial in any number of variables with exact rational
+/// coefficients, stored sparsely as a map from exponents to coefficients.
+///
+/// Variables are kept in alphabetical order, and monomials compare
+/// lexicographically in that order, so the leading term of
+/// `x^2 + x y^3 + y^5` is `x^2`. Zero coefficients and unused variables
+/// are never stored, so equal polynomials compare equal.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Poly {
+    vars: Vec<String>,
+    terms: BTreeMap<Vec<u32>, BigRational>,
+}
+
+impl Poly {
+    pub fn zero() -> Poly {
+        Poly {
+            vars: Vec::new(),
+            terms: BTreeMap::new(),
+        }
+    }
+
+    pub fn one() -> Poly {
+        Poly::constant(BigRational::one())
+    }
+
+    pub fn constant(c: BigRational) -> Poly {
+        Poly::from_terms(Vec::new(), [(Vec::new(), c)])
+    }
+
+    pub fn var(name: &str) -> Poly {
+        Poly::from_terms(vec![name.to_string()], [(vec![1], BigRational::one())])
+    }
+
+    /// `var^n`.
+    pub fn monomial(var: &str, n: u32) -> Poly {
+        Poly::from_terms(vec![var.to_string()], [(vec![n], BigRational::one())])
+    }
+
+    fn from_terms(
+        vars: Vec<String>,
+        terms: impl IntoIterator<Item = (Vec<u32>, BigRational)>,
+    ) -> Poly {
+        let mut poly = Poly {
+            vars,
+            terms: BTreeMap::new(),
+        };
+        for (exps, c) in terms {
+            let sum = poly.terms.remove(&exps).unwrap_or_default() + c;
+            if !sum.is_zero() {
+                poly.terms.insert(exps, sum);
+            }
+        }
+        poly.trim()
+    }
+
+    /// Drop the variables that no term uses.
+    fn trim(mut self) -> Poly {
+        let used: Vec<usize> = (0..self.vars.len())
+            .filter(|&i| self.terms.keys().any(|e| e[i] > 0))
+            .collect();
+        if used.len() < self.vars.len() {
+            self.vars = used.iter().map(|&i| self.vars[i].clone()).collect();
+            self.terms = std::mem::take(&mut self.terms)
+                .into_iter()
+                .map(|(e, c)| (used.iter().map(|&i| e[i]).collect(), c))
+                .collect();
+        }
+        self
+    }
+
+    /// The terms with exponents over `vars`, a superset of `self.vars`.
+    fn terms_over(&self, vars: &[String]) -> Vec<(Vec<u32>, BigRational)> {
+        let at: Vec<usize> = self
+            .vars
+            .iter()
+            .map(|v| vars.iter().position(|w| w == v).unwrap())
+            .collect();
+        self.terms
+            .iter()
+            .map(|(e, c)| {
+                let mut exps = vec![0; vars.len()];
+                for (i, &n) in at.iter().zip(e) {
+                    exps[*i] = n;
+                }
+                (exps, c.clone())
+            })
+            .collect()
+    }
+
+    /// The variables of both, in order.
+    fn union(&self, other: &Poly) -> Vec<String> {
+        self.vars
+            .iter()
+            .chain(&other.vars)
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    pub fn vars(&self) -> &[String] {
+        &self.vars
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Whether there are no variables, as for zero.
+    pub fn is_constant(&self) -> bool {
+        self.vars.is_empty()
+    }
+
+    /// The value of a polynomial without variables.
+    pub fn to_constant(&self) -> Option<BigRational> {
+        self.is_constant()
+            .then(|| self.terms.values().next().cloned().unwrap_or_default())
+    }
+
+    /// The largest power of `var`, and 0 for the zero polynomial.
+    pub fn degree(&self, var: &str) -> u32 {
+        match self.vars.iter().position(|v| v == var) {
+            Some(i) => self.terms.keys().map(|e| e[i]).max().unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    /// The largest degree of a term, counting all of its variables.
+    pub fn total_degree(&self) -> u32 {
+        self.terms.keys().map(|e| e.iter().sum()).max().unwrap_or(0)
+    }
+
+    /// The coefficient of `var^k`, a polynomial in the other variables.
+    pub fn coefficient(&self, var: &str, k: u32) -> Poly {
+        let Some(i) = self.vars.iter().position(|v| v == var) else {
+            return if k == 0 { self.clone() } else { Poly::zero() };
+        };
+        Poly::from_terms(
+            self.vars.clone(),
+            self.terms.iter().filter(|(e, _)| e[i] == k).map(|(e, c)| {
+                let mut e = e.clone();
+                e[i] = 0;
+                (e, c.clone())
+            }),
+        )
+    }
+
+    /// The coefficients of `1, var, var^2, ...` up to the degree in `var`.
+    pub fn coefficients(&self, var: &str) -> Vec<Poly> {
+        (0..=self.degree(var))
+            .map(|k| self.coefficient(var, k))
+            .collect()
+    }
+
+    /// The coefficient of the largest power of `var`.
+    pub fn leading_coefficient(&self, var: &str) -> Poly {
+        self.coefficient(var, self.degree(var))
+    }
+
+    /// The leading term in the lexicographic order.
+    fn leading_term(&self) -> Option<(&Vec<u32>, &BigRational)> {
+        self.terms.iter().next_back()
+    }
+
+    /// The number in front of the leading term, and 0 for zero.
+    pub fn leading_number(&self) -> BigRational {
+        self.leading_term()
+            .map(|(_, c)| c.clone())
+            .unwrap_or_default()
+    }
+
+    /// `self` scaled so that its leading term has coefficient 1.
+    pub fn monic(&self) -> Poly {
+        match self.leading_term() {
+            Some((_, c)) => self.scale(&c.recip()),
+            None => Poly::zero(),
+        }
+    }
+
+    pub fn scale(&self, c: &BigRational) -> Poly {
+        Poly::from_terms(
+            self.vars.clone(),
+            self.terms.iter().map(|(e, d)| (e.clone(), d * c)),
+        )
+    }
+
+    /// `self^n`, by repeated squaring.
+    pub fn pow(&self, n: u32) -> Poly {
+        match n {
+            0 => Poly::one(),
+            _ => {
+                let half = self.pow(n / 2);
+                let square = &half * &half;
+                if n % 2 == 1 {
+                    &square * self
+                } else {
+                    square
+                }
+            }
+        }
+    }
+
+    /// A bound on the bits of each coefficient of `self^n`, from the largest
+    /// coefficient and the number of terms.
+    fn power_bits(&self, n: u32) -> u64 {
+        let largest = self
+            .terms
+            .values()
+            .map(|c| c.numer().bits() + c.denom().bits())
+            .max()
+            .unwrap_or(0);
+        let spread = u64::BITS - (self.terms.len() as u64).leading_zeros();
+        (largest + u64::from(spread)).saturating_mul(u64::from(n))
+    }
+
+    /// The derivative with respect to `var`.
+    pub fn derivative(&self, var: &str) -> Poly {
+        let Some(i) = self.vars.iter().position(|v| v == var) else {
+            return Poly::zero();
+        };
+        Poly::from_terms(
+            self.vars.clone(),
+            self.terms.iter().filter(|(e, _)| e[i] > 0).map(|(e, c)| {
+                let n = BigRational::from_integer(e[i].into());
+                let mut e = e.clone();
+                e[i] -= 1;
+                (e, c * n)
+            }),
+        )
+    }
+
+    /// The size in bits of the largest coefficient, numerator and
+    /// denominator together.
+    pub fn coefficient_bits(&self) -> u64 {
+        self.terms
+            .values()
+            .map(|c| c.numer().bits() + c.denom().bits())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The content over the integers: the positive `c` for which `self / c`
+    /// has whole coefficients without a common factor, as `1/2` for
+    /// `x/2 + 3/2`.
+    pub fn integer_content(&self) -> BigRational {
+        let numerators = self
+            .terms
+            .values()
+            .fold(BigInt::zero(), |g, c| g.gcd(c.numer()));
+        let denominators = self
+            .terms
+            .values()
+            .fold(BigInt::one(), |l, c| l.lcm(c.denom()));
+        if numerators.is_zero() {
+            BigRational::one()
+        } else {
+            BigRational::new(numerators, denominators)
+        }
+    }
+
+    /// `self` over its integer content, with the sign making the leading
+    /// number positive, as `x + 3` for `-x/2 - 3/2`.
+    pub fn integer_primitive(&self) -> Poly {
+        let c = self.integer_content();
+        self.scale(
+            &if self.leading_number().is_negative() {
+                -c
+            } else {
+                c
+            }
+            .recip(),
+        )
+    }
+
+    /// `self` with `value` put in for `var`.
+    pub fn substitute(&self, var: &str, value: &BigRational) -> Poly {
+        let Some(i) = self.vars.iter().position(|v| v == var) else {
+            return self.clone();
+        };
+        Poly::from_terms(
+            self.vars.clone(),
+            self.terms.iter().map(|(e, c)| {
+                let mut e = e.clone();
+                let power = num_traits::Pow::pow(value, e[i]);
+                e[i] = 0;
+                (e, c * power)
+            }),
+        )
+    }
+
+    /// The value at the given values of the variables, NaN if one is
+    /// missing.
+    pub fn evaluate(&self, values: &HashMap<&str, f64>) -> f64 {
+        let at: Vec<f64> = self
+            .vars
+            .iter()
+            .map(|v| values.get(v.as_str()).copied().unwrap_or(f64::NAN))
+            .collect();
+        self.terms
+            .iter()
+            .map(|(e, c)| {
+                let c = c.to_f64().unwrap_or(f64::NAN);
+                e.iter().zip(&at).fold(c, |t, (&n, x)| t * x.powi(n as i32))
+            })
+            .sum()
+    }
+
+    /// Division by the leading term of `divisor` in the lexicographic
+    /// order, as long division does for one variable: `self = q divisor + r`
+    /// with no term of `r` a multiple of that leading term. `None` for
+    /// division by zero.
+    pub fn div_rem(&self, divisor: &Poly) -> Option<(Poly, Poly)> {
+        let vars = self.union(divisor);
+        let divisor = divisor.terms_over(&vars);
+        let (lead, lead_c) = divisor.iter().max_by(|a, b| a.0.cmp(&b.0))?.clone();
+        let mut p: BTreeMap<_, _> = self.terms_over(&vars).into_iter().collect();
+        let (mut quotient, mut remainder) = (Vec::new(), Vec::new());
+        while let Some((e, c)) = p.pop_last() {
+            if e.iter().zip(&lead).all(|(a, b)| a >= b) {
+                let t: Vec<u32> = e.iter().zip(&lead).map(|(a, b)| a - b).collect();
+                let k = &c / &lead_c;
+                for (d, dc) in divisor.iter().filter(|(d, _)| *d != lead) {
+                    let exps: Vec<u32> = d.iter().zip(&t).map(|(a, b)| a + b).collect();
+                    let sum = p.remove(&exps).unwrap_or_default() - &k * dc;
+                    if !sum.is_zero() {
+                        p.insert(exps, sum);
+                    }
+                }
+                quotient.push((t, k));
+            } else {
+                remainder.push((e, c));
+            }
+        }
+        Some((
+            Poly::from_terms(vars.clone(), quotient),
+            Poly::from_terms(vars, remainder),
+        ))
+    }
+
+    /// `self / divisor`, when it leaves no remainder.
+    pub fn exact_div(&self, divisor: &Poly) -> Option<Poly> {
+        let (q, r) = self.div_rem(divisor)?;
+        r.is_zero().then_some(q)
+    }
+
+    /// The pseudo-remainder of `self` by `divisor` as polynomials in `var`,
+    /// `lc^(m - n + 1) self mod divisor` for degrees `m` and `n` and the
+    /// leading coefficient `lc` of `divisor`, which needs no fractions of
+    /// the other variables.
+    pub fn pseudo_rem(&self, divisor: &Poly, var: &str) -> Poly {
+        let n = divisor.degree(var);
+        let m = self.degree(var);
+        if divisor.is_zero() || m < n {
+            return self.clone();
+        }
+        let lc = divisor.leading_coefficient(var);
+        let mut r = self.clone();
+        let mut steps = m - n + 1;
+        while !r.is_zero() && r.degree(var) >= n && steps > 0 {
+            let shift = Poly::monomial(var, r.degree(var) - n);
+            r = &(&lc * &r) - &(&(&r.leading_coefficient(var) * &shift) * divisor);
+            steps -= 1;
+        }
+        &lc.pow(steps) * &r
+    }
+
+    /// The greatest common divisor, made monic, or zero when both are zero.
+    pub fn gcd(&self, other: &Poly) -> Poly {
+        if self.is_zero() {
+            return other.monic();
+        }
+        if other.is_zero() {
+            return self.monic();
+        }
+        // Over the rationals the constants other than zero are all units
+        let Some(var) = self.union(other).into_iter().next() else {
+            return Poly::one();
+        };
+
+        // gcd(a, b) = gcd(cont a, cont b) gcd(pp a, pp b), with the second
+        // by the primitive remainder sequence in `var`
+        let (ca, mut a) = self.content_and_primitive(&var);
+        let (cb, mut b) = other.content_and_primitive(&var);
+        if a.degree(&var) < b.degree(&var) {
+            std::mem::swap(&mut a, &mut b);
+        }
+        while b.degree(&var) > 0 {
+            let r = a.pseudo_rem(&b, &var);
+            a = b;
+            b = if r.degree(&var) == 0 && !r.is_zero() {
+                Poly::one()
+            } else {
+                // Scaling by the integer content keeps the coefficients small
+                r.content_and_primitive(&var).1.integer_primitive()
+            };
+        }
+        let g = if b.is_zero() {
+            a.content_and_primitive(&var).1
+        } else {
+            Poly::one()
+        };
+        (&ca.gcd(&cb) * &g).monic()
+    }
+
+    /// The content, the gcd of the coefficients as a polynomial in `var`,
+    /// and the primitive part, `self` divided by the content.
+    pub fn content_and_primitive(&self, var: &str) -> (Poly, Poly) {
+        if self.is_zero() {
+            return (Poly::zero(), Poly::zero());
+        }
+        let content = self
+            .coefficients(var)
+            .into_iter()
+            .filter(|c| !c.is_zero())
+            .fold(Poly::zero(), |g, c| g.gcd(&c));
+        let primitive = self
+            .exact_div(&content)
+            .expect("the content divides every coefficient");
+        (content, primitive)
+    }
+
+    /// The polynomial that `expr` is, if it is built from variables and
+    /// exact numbers by sums, products, whole powers and division by
+    /// numbers.
+    pub fn from_expr(expr: &Expr) -> Option<Poly> {
+        match expr {
+            Expr::Number(Real::Rational(r)) => Some(Poly::constant(r.clone())),
+            Expr::Variable(name) => Some(Poly::var(name)),
+            Expr::UnaryOp(UnaryOp::Neg, e) => Some(-&Poly::from_expr(e)?),
+            Expr::BinaryOp(op, a, b) => {
+                let a = Poly::from_expr(a)?;
+                match op {
+                    BinaryOp::Pow => {
+                        let n = match &**b {
+                            Expr::Number(n) => n.to_i64()?.to_u32()?,
+                            _ => return None,
+                        };
+                        (a.power_bits(n) <= MAX_POWER_BITS).then(|| a.pow(n))
+                    }
+                    BinaryOp::Div => {
+                        let d = Poly::from_expr(b)?.to_constant()?;
+                        (!d.is_zero()).then(|| a.scale(&d.recip()))
+                    }
+                    BinaryOp::Add => Some(&a + &Poly::from_expr(b)?),
+                    BinaryOp::Sub => Some(&a - &Poly::from_expr(b)?),
+                    BinaryOp::Mul => Some(&a * &Poly::from_expr(b)?),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// The polynomial as a simplified expression.
+    pub fn to_expr(&self) -> Expr {
+        let terms: Vec<Expr> = self
+            .terms
+            .iter()
+            .rev()
+            .map(|(e, c)| {
+                let mut factors = vec![Expr::Number(Real::Rational(c.clone()))];
+                for (v, &n) in self.vars.iter().zip(e) {
+                    if n > 0 {
+                        factors.push(Expr::BinaryOp(
+                            BinaryOp::Pow,
+                            Box::new(Expr::Variable(v.clone())),
+                            Box::new(Expr::Number(Real::from(n as i64))),
+                        ));
+                    }
+                }
+                fold_binary_ops(BinaryOp::Mul, factors)
+            })
+            .collect();
+        if terms.is_empty() {
+            Expr::Number(Real::from(0))
+        } else {
+            simplify(&fold_binary_ops(BinaryOp::Add, terms))
+        }
+    }
+}
+
+impl Add for &Poly {
+    type Output = Poly;
+
+    fn add(self, other: &Poly) -> Poly {
+        let vars = self.union(other);
+        let mut terms = self.terms_over(&vars);
+        terms.extend(other.terms_over(&vars));
+        Poly::from_terms(vars, terms)
+    }
+}
+
+impl Sub for &Poly {
+    type Output = Poly;
+
+    fn sub(self, other: &Poly) -> Poly {
+        self + &-other
+    }
+}
+
+impl Mul for &Poly {
+    type Output = Poly;
+
+    fn mul(self, other: &Poly) -> Poly {
+        let vars = self.union(other);
+        let (a, b) = (self.terms_over(&vars), other.terms_over(&vars));
+        let products = a.iter().flat_map(|(e, c)| {
+            b.iter().map(move |(f, d)| {
+                let exps = e.iter().zip(f).map(|(m, n)| m + n).collect();
+                (exps, c * d)
+            })
+        });
+        Poly::from_terms(vars.clone(), products)
+    }
+}
+
+impl Neg for &Poly {
+    type Output = Poly;
+
+    fn neg(self) -> Poly {
+        self.scale(&-BigRational::one())
+    }
+}
+
+/// The operators on owned polynomials, by reference.
+macro_rules! owned {
+    ($trait:ident, $method:ident) => {
+        impl $trait for Poly {
+            type Output = Poly;
+
+            fn $method(self, other: Poly) -> Poly {
+                (&self).$method(&other)
+            }
+        }
+    };
+}
+
+owned!(Add, add);
+owned!(Sub, sub);
+owned!(Mul, mul);
+
+impl Neg for Poly {
+    type Output = Poly;
+
+    fn neg(self) -> Poly {
+        -&self
+    }
+}
@@ -0,0 +1,94 @@
+/// Input rewritten into the ASCII syntax the parser reads, with a map back
+/// to byte offsets in the original for error spans.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Normalized {
+    pub text: String,
+    /// `origins[i]` is the offset in the original input of the character
+    /// that produced byte `i` of `text`, with one extra entry for the end.
+    origins: Vec<usize>,
+}
+
+impl Normalized {
+    /// Offset in the original input corresponding to `pos` in `text`.
+    pub fn origin(&self, pos: usize) -> usize {
+        self.origins[pos.min(self.origins.len() - 1)]
+    }
+}
+
+/// Rewrite math symbols copied from web pages and phones: `×` `÷` `−` `≤`
+/// as operators, superscripts as `^` exponents (`x²` is `x^2`), and Greek
+/// letters as the names of constants and variables (`π` is `pi`, `θ` is
+/// `theta`). Radical signs such as `√` are left for the parser.
+pub fn normalize(input: &str) -> Normalized {
+    let mut text = String::with_capacity(input.len());
+    let mut origins = Vec::with_capacity(input.len() + 1);
+    let mut in_superscript = false;
+    for (i, c) in input.char_indices() {
+        let mut push = |s: &str| {
+            text.push_str(s);
+            origins.extend(std::iter::repeat_n(i, s.len()));
+        };
+        match superscript(c) {
+            Some(s) => {
+                if !in_superscript {
+                    push("^");
+                }
+                in_superscript = true;
+                push(s);
+                continue;
+            }
+            None => in_superscript = false,
+        }
+        match symbol(c) {
+            Some(s) => push(s),
+            None => push(c.encode_utf8(&mut [0; 4])),
+        }
+    }
+    origins.push(input.len());
+    Normalized { text, origins }
+}
+
+fn superscript(c: char) -> Option<&'static str> {
+    Some(match c {
+        '⁰' => "0",
+        '¹' => "1",
+        '²' => "2",
+        '³' => "3",
+        '⁴' => "4",
+        '⁵' => "5",
+        '⁶' => "6",
+        '⁷' => "7",
+        '⁸' => "8",
+        '⁹' => "9",
+        '⁻' => "-",
+        '⁽' => "(",
+        '⁾' => ")",
+        _ => return None,
+    })
+}
+
+fn symbol(c: char) -> Option<&'static str> {
+    Some(match c {
+        '×' | '·' | '⋅' | '∙' | '∗' => "*",
+        '÷' | '∕' => "/",
+        '−' | '–' => "-",
+        '≤' | '⩽' => "<=",
+        '≥' | '⩾' => ">=",
+        '≠' => "!=",
+        'π' => "pi",
+        'τ' => "tau",
+        'φ' | 'ϕ' => "phi",
+        '∞' => "infinity",
+        'α' => "alpha",
+        'β' => "beta",
+        'γ' => "gamma",
+        'δ' => "delta",
+        'ε' | 'ϵ' => "epsilon",
+        'θ' | 'ϑ' => "theta",
+        'λ' => "lambda",
+        'μ' => "mu",
+        'σ' => "sigma",
+        'ω' => "omega",
+        _ => return None,
+    })
+}
@@ -1,16 +1,36 @@
+use crate::math::parser::{parse_program_recovering, ParseOptions};
 use crate::ui::components::{graph::Graph, input::ExpressionInput, output::Output};
 use yew::prelude::*;
 
 #[function_component(CliphApp)]
 pub fn cliph_app() -> Html {
     let expr = use_state(|| "x^2".to_string());
+    // The graph keeps showing the last input that parsed cleanly while the
+    // current one has errors, such as `x^` or `sin(` half-way through typing.
+    let plotted = use_state(|| (*expr).clone());
+    let valid = parse_program_recovering(&expr, &ParseOptions::default())
+        .errors
+        .is_empty();
+
+    {
+        let plotted = plotted.clone();
+        use_effect_with(((*expr).clone(), valid), move |(expr, valid)| {
+            if *valid {
+                plotted.set(expr.clone());
+            }
+            || ()
+        });
+    }
 
     html! {
         <div class="container">
             <h1>{ "Cliph – Graphing Calculator" }</h1>
-            <ExpressionInput expr={expr.clone()} />
+            <ExpressionInput expr={expr.clone()} invalid={!valid} />
             <Output expr={(*expr).clone()} />
-            <Graph expr={(*expr).clone()} />
+            if !valid {
+                <p class="stale">{ "Showing the last valid plot" }</p>
+            }
+            <Graph expr={(*plotted).clone()} />
         </div>
     }
 }
@@ -3,8 +3,17 @@ use plotters_canvas::CanvasBackend;
 use web_sys::HtmlCanvasElement;
 use yew::prelude::*;
 
+use crate::math::algebra::explicit_form;
+use crate::math::ast::{BinaryOp, Expr, RelOp, Relation, Statement};
+use crate::math::env::Environment;
 use crate::math::{eval, parser};
 
+/// Colours of successive curves when plotting a list of expressions.
+const CURVE_COLORS: [RGBColor; 5] = [RED, BLUE, GREEN, MAGENTA, CYAN];
+
+/// Grid cells per axis when sampling implicit curves and regions.
+const IMPLICIT_GRID: usize = 200;
+
 #[derive(Properties, PartialEq)]
 pub struct Props {
     pub expr: String,
@@ -35,15 +44,28 @@ pub fn graph(props: &Props) -> Html {
                 return cleanup;
             }
 
-            let parsed = match parser::parse(&expr_str) {
-                Ok(e) => e,
+            let program = match parser::parse_program(expr_str) {
+                Ok(p) => p,
+                Err(_) => return cleanup,
+            };
+            let (mut env, rest) = match Environment::from_program(&program) {
+                Ok(r) => r,
                 Err(_) => return cleanup,
             };
+            let parsed = match rest.last() {
+                Some(stmt) => env.inline_statement(stmt),
+                None => return cleanup,
+            };
 
-            let vars = |x: f64| {
-                let mut env = std::collections::HashMap::new();
-                env.insert("x".to_string(), x);
-                eval::evaluate_with_env(&parsed, &env)
+            // Explicit y = f(x) is plotted as a function; anything else
+            // (x^2 + y^2 = 1, y > x) as an implicit curve or region.
+            let (explicit, implicit) = match &parsed {
+                Statement::Expr(e) => (Some(e.clone()), None),
+                Statement::Relation(rel) => match explicit_form(rel, "y") {
+                    Some(f) => (Some(f.clone()), None),
+                    None => (None, Some(rel.clone())),
+                },
+                Statement::Assign(..) | Statement::Define(..) => return cleanup,
             };
 
             let x_range = -10.0..10.0;
@@ -67,49 +89,78 @@ pub fn graph(props: &Props) -> Html {
             // Draw axes explicitly
             if chart
                 .draw_series([
-                    PathElement::new(vec![(-10.0, 0.0), (10.0, 0.0)], &BLACK),
-                    PathElement::new(vec![(0.0, -10.0), (0.0, 10.0)], &BLACK),
+                    PathElement::new(vec![(-10.0, 0.0), (10.0, 0.0)], BLACK),
+                    PathElement::new(vec![(0.0, -10.0), (0.0, 10.0)], BLACK),
                 ])
                 .is_err()
             {
                 return cleanup;
             }
 
-            // Generate points on the curve
-            let points: Vec<(f64, f64)> = (-100..=100)
-                .map(|i| {
-                    let x = i as f64 / 10.0;
-                    (x, vars(x))
-                })
-                .collect();
-
-            // Filter points inside visible y range into segments
-            let is_in_range = |y: f64| y_range.start <= y && y <= y_range.end;
-            let mut segments = Vec::new();
-            let mut current_segment = Vec::new();
-
-            for p in points {
-                if is_in_range(p.1) {
-                    current_segment.push(p);
-                } else {
-                    if current_segment.len() > 1 {
-                        segments.push(current_segment);
+            if let Some(rel) = implicit {
+                if rel.op != RelOp::Eq {
+                    let step = (x_range.end - x_range.start) / IMPLICIT_GRID as f64;
+                    let cells = region_cells(&rel, &mut env, x_range.clone(), y_range.clone());
+                    if chart
+                        .draw_series(cells.into_iter().map(|(x, y)| {
+                            Rectangle::new([(x, y), (x + step, y + step)], RED.mix(0.2).filled())
+                        }))
+                        .is_err()
+                    {
+                        return cleanup;
                     }
-                    current_segment = Vec::new();
                 }
-            }
-            if current_segment.len() > 1 {
-                segments.push(current_segment);
-            }
 
-            // Draw each segment separately
-            for segment in segments {
+                let mut f = implicit_function(&rel, env);
+                let segments = zero_contour(&mut f, x_range.clone(), y_range.clone());
                 if chart
-                    .draw_series(LineSeries::new(segment.clone(), &RED))
+                    .draw_series(
+                        segments
+                            .into_iter()
+                            .map(|[a, b]| PathElement::new(vec![a, b], RED)),
+                    )
                     .is_err()
                 {
                     return cleanup;
                 }
+
+                return cleanup;
+            }
+
+            // A list such as [sin(x), cos(x)] is plotted as one curve per element
+            let mut samples: Vec<(f64, Vec<f64>)> = Vec::new();
+            let mut previous_branches = None;
+            for i in -100..=100 {
+                let x = i as f64 / 10.0;
+                env.set_var("x", x);
+                let (ys, branches) = match &explicit {
+                    Some(f) => (
+                        eval::evaluate_value(f, &env).to_vec(),
+                        eval::branches_taken(f, &env),
+                    ),
+                    None => (vec![f64::NAN], Vec::new()),
+                };
+                // Break the curve where a piecewise function switches branch
+                // rather than joining across the jump
+                if previous_branches.is_some_and(|p| p != branches) {
+                    samples.push((x, vec![f64::NAN; ys.len()]));
+                }
+                previous_branches = Some(branches);
+                samples.push((x, ys));
+            }
+            let curves = samples.iter().map(|(_, ys)| ys.len()).max().unwrap_or(0);
+
+            for k in 0..curves {
+                let points = samples
+                    .iter()
+                    .map(|(x, ys)| (*x, ys.get(k).copied().unwrap_or(f64::NAN)));
+                let color = CURVE_COLORS[k % CURVE_COLORS.len()];
+                // Draw each visible segment separately
+                for segment in visible_segments(points, y_range.clone()) {
+                    if chart.draw_series(LineSeries::new(segment, &color)).is_err() {
+                        return cleanup;
+                    }
+                }
             }
 
             cleanup
@@ -120,3 +171,117 @@ pub fn graph(props: &Props) -> Html {
         <canvas width=600 height=400 ref={canvas_ref}></canvas>
     }
 }
+
+/// Runs of consecutive points inside the visible y range, so curves break
+/// at asymptotes and undefined points instead of joining across them.
+fn visible_segments(
+    points: impl Iterator<Item = (f64, f64)>,
+    y_range: std::ops::Range<f64>,
+) -> Vec<Vec<(f64, f64)>> {
+    let mut segments = Vec::new();
+    let mut current_segment = Vec::new();
+    for p in points {
+        if y_range.start <= p.1 && p.1 <= y_range.end {
+            current_segment.push(p);
+        } else {
+            if current_segment.len() > 1 {
+                segments.push(current_segment);
+            }
+            current_segment = Vec::new();
+        }
+    }
+    if current_segment.len() > 1 {
+        segments.push(current_segment);
+    }
+    segments
+}
+
+/// `F(x, y) = lhs - rhs`, whose zero set is the boundary of `rel`.
+fn implicit_function(rel: &Relation, mut env: Environment) -> impl FnMut(f64, f64) -> f64 {
+    let f = Expr::BinaryOp(
+        BinaryOp::Sub,
+        Box::new(rel.lhs.clone()),
+        Box::new(rel.rhs.clone()),
+    );
+    move |x, y| {
+        env.set_var("x", x);
+        env.set_var("y", y);
+        eval::evaluate_with_env(&f, &env)
+    }
+}
+
+/// Lower-left corners of the grid cells whose centre satisfies `rel`.
+fn region_cells(
+    rel: &Relation,
+    env: &mut Environment,
+    x_range: std::ops::Range<f64>,
+    y_range: std::ops::Range<f64>,
+) -> Vec<(f64, f64)> {
+    let dx = (x_range.end - x_range.start) / IMPLICIT_GRID as f64;
+    let dy = (y_range.end - y_range.start) / IMPLICIT_GRID as f64;
+    let mut cells = Vec::new();
+    for i in 0..IMPLICIT_GRID {
+        for j in 0..IMPLICIT_GRID {
+            let x = x_range.start + i as f64 * dx;
+            let y = y_range.start + j as f64 * dy;
+            env.set_var("x", x + dx / 2.0);
+            env.set_var("y", y + dy / 2.0);
+            if eval::evaluate_relation(rel, env) {
+                cells.push((x, y));
+            }
+        }
+    }
+    cells
+}
+
+/// Line segments approximating `f(x, y) = 0` by marching squares.
+fn zero_contour(
+    f: &mut impl FnMut(f64, f64) -> f64,
+    x_range: std::ops::Range<f64>,
+    y_range: std::ops::Range<f64>,
+) -> Vec<[(f64, f64); 2]> {
+    let n = IMPLICIT_GRID;
+    let dx = (x_range.end - x_range.start) / n as f64;
+    let dy = (y_range.end - y_range.start) / n as f64;
+    let xs: Vec<f64> = (0..=n).map(|i| x_range.start + i as f64 * dx).collect();
+    let ys: Vec<f64> = (0..=n).map(|j| y_range.start + j as f64 * dy).collect();
+    let values: Vec<Vec<f64>> = xs
+        .iter()
+        .map(|&x| ys.iter().map(|&y| f(x, y)).collect())
+        .collect();
+
+    let mut segments = Vec::new();
+    for i in 0..n {
+        for j in 0..n {
+            // Corners in counter-clockwise order
+            let corners = [
+                (xs[i], ys[j], values[i][j]),
+                (xs[i + 1], ys[j], values[i + 1][j]),
+                (xs[i + 1], ys[j + 1], values[i + 1][j + 1]),
+                (xs[i], ys[j + 1], values[i][j + 1]),
+            ];
+            if corners.iter().any(|c| !c.2.is_finite()) {
+                continue;
+            }
+
+            let crossings: Vec<(f64, f64)> = (0..4)
+                .filter_map(|k| {
+                    let (x0, y0, v0) = corners[k];
+                    let (x1, y1, v1) = corners[(k + 1) % 4];
+                    if (v0 < 0.0) == (v1 < 0.0) {
+                        return None;
+                    }
+                    let t = v0 / (v0 - v1);
+                    Some((x0 + t * (x1 - x0), y0 + t * (y1 - y0)))
+                })
+                .collect();
+
+            for pair in crossings.chunks(2) {
+                if let [a, b] = pair {
+                    segments.push([*a, *b]);
+                }
+            }
+        }
+    }
+    segments
+}
@@ -3,6 +3,9 @@ use yew::prelude::*;
 #[derive(Properties, PartialEq)]
 pub struct Props {
     pub expr: UseStateHandle<String>,
+    /// Whether the current input has parse errors, to mark it as such.
+    #[prop_or_default]
+    pub invalid: bool,
 }
 
 #[function_component(ExpressionInput)]
@@ -21,6 +24,8 @@ pub fn expression_input(props: &Props) -> Html {
     html! {
         <input
             type="text"
+            class={classes!(props.invalid.then_some("invalid"))}
+            aria-invalid={props.invalid.to_string()}
             value={(*expr).clone()}
             {oninput}
             placeholder="Enter expression (e.g., x^2 + 3*x)"
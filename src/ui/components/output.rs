@@ -1,7 +1,10 @@
-use crate::math::algebra::simplify;
-use crate::math::diff::differentiate;
-use crate::math::format::format_expr_latex;
-use crate::math::parser::{latex_to_math_expr, parse};
+use crate::math::algebra::{expand_statement, explicit_form, simplify, simplify_statement};
+use crate::math::ast::{RelOp, Statement};
+use crate::math::diff::{differentiate, implicit_derivative};
+use crate::math::env::Environment;
+use crate::math::factor::factor_statement;
+use crate::math::format::{format_expr_latex, format_statement_latex};
+use crate::math::parser::{parse_program_recovering, ParseOptions};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{window, Element};
@@ -21,82 +24,149 @@ pub struct Props {
 #[function_component(Output)]
 pub fn output(props: &Props) -> Html {
     let node_ref_simplified = use_node_ref();
+    let node_ref_expanded = use_node_ref();
+    let node_ref_factored = use_node_ref();
     let node_ref_derivative = use_node_ref();
 
-    let (simplified_expr_latex, derivative_latex) = {
-        let expr_str = latex_to_math_expr(&props.expr);
+    // Input that is still being typed parses with holes, shown as boxes,
+    // and the errors are listed below.
+    let recovered = parse_program_recovering(&props.expr, &ParseOptions::default());
+    let diagnostics: Vec<String> = recovered
+        .errors
+        .iter()
+        .map(|err| err.render(&props.expr))
+        .chain(recovered.warnings.iter().map(|w| w.render(&props.expr)))
+        .collect();
+    let (simplified_expr_latex, expanded_latex, factored_latex, derivative_latex) = {
+        // Earlier statements define variables and functions; the last
+        // remaining one is what gets shown.
+        let program = Environment::from_program(&recovered.program)
+            .map_err(|err| format!("error: {}", err))
+            .map(|(env, rest)| {
+                let shown = rest.last().or(recovered.program.last()).cloned();
+                shown.map(|stmt| env.inline_statement(&stmt))
+            });
 
-        match parse(&expr_str) {
-            Ok(expr) => {
-                let simplified = simplify(&expr);
-                let simplified_latex = format!("${}$", format_expr_latex(&simplified));
+        match program {
+            Ok(Some(stmt)) => {
+                let simplified = simplify_statement(&stmt);
+                let simplified_latex = format!("${}$", format_statement_latex(&simplified));
+                let expanded_latex =
+                    format!("${}$", format_statement_latex(&expand_statement(&stmt)));
+                let factored_latex =
+                    format!("${}$", format_statement_latex(&factor_statement(&stmt)));
 
-                let deriv = differentiate(&simplified, "x");
-                let derivative_latex = format!("${}$", format_expr_latex(&deriv));
+                let derivative_latex = match &stmt {
+                    Statement::Expr(expr) => {
+                        let deriv = differentiate(&simplify(expr), "x");
+                        format!("${}$", format_expr_latex(&deriv))
+                    }
+                    Statement::Relation(rel) => {
+                        let deriv = match explicit_form(rel, "y") {
+                            Some(f) => Some(differentiate(&simplify(f), "x")),
+                            None if rel.op == RelOp::Eq => Some(implicit_derivative(rel, "x", "y")),
+                            None => None,
+                        };
+                        match deriv {
+                            Some(d) => format!("$\\frac{{dy}}{{dx}} = {}$", format_expr_latex(&d)),
+                            None => "Not defined for inequalities".to_string(),
+                        }
+                    }
+                    Statement::Define(name, params, body) if params.len() == 1 => {
+                        let deriv = differentiate(&simplify(body), &params[0]);
+                        format!(
+                            "${}'\\left({}\\right) = {}$",
+                            name,
+                            params[0],
+                            format_expr_latex(&deriv)
+                        )
+                    }
+                    Statement::Assign(..) | Statement::Define(..) => {
+                        "Not defined for definitions".to_string()
+                    }
+                };
 
-                (simplified_latex, derivative_latex)
+                (
+                    simplified_latex,
+                    expanded_latex,
+                    factored_latex,
+                    derivative_latex,
+                )
             }
-            Err(_) => (
-                "Error parsing expression".to_string(),
-                "Error parsing expression".to_string(),
+            Ok(None) => (String::new(), String::new(), String::new(), String::new()),
+            Err(diagnostic) => (
+                diagnostic.clone(),
+                diagnostic.clone(),
+                diagnostic.clone(),
+                diagnostic,
             ),
         }
     };
 
-    // Use effect with setTimeout to defer renderMathInElement call safely
-    {
-        let node_ref_simplified = node_ref_simplified.clone();
-        let simplified_expr_latex = simplified_expr_latex.clone();
-        let expr = props.expr.clone();
-
-        use_effect_with((expr, simplified_expr_latex), move |_| {
-            if let Some(elem) = node_ref_simplified.cast::<Element>() {
-                let elem_clone = elem.clone();
-                let closure = Closure::once(move || {
-                    render_math_in_element(elem_clone);
-                });
-                window()
-                    .unwrap()
-                    .set_timeout_with_callback(closure.as_ref().unchecked_ref())
-                    .expect("failed to set timeout");
-                closure.forget();
-            }
-            || ()
-        });
-    }
-
-    {
-        let node_ref_derivative = node_ref_derivative.clone();
-        let derivative_latex = derivative_latex.clone();
-        let expr = props.expr.clone();
-
-        use_effect_with((expr, derivative_latex), move |_| {
-            if let Some(elem) = node_ref_derivative.cast::<Element>() {
-                let elem_clone = elem.clone();
-                let closure = Closure::once(move || {
-                    render_math_in_element(elem_clone);
-                });
-                window()
-                    .unwrap()
-                    .set_timeout_with_callback(closure.as_ref().unchecked_ref())
-                    .expect("failed to set timeout");
-                closure.forget();
-            }
-            || ()
-        });
-    }
+    use_render_math(
+        node_ref_simplified.clone(),
+        props.expr.clone(),
+        simplified_expr_latex.clone(),
+    );
+    use_render_math(
+        node_ref_expanded.clone(),
+        props.expr.clone(),
+        expanded_latex.clone(),
+    );
+    use_render_math(
+        node_ref_factored.clone(),
+        props.expr.clone(),
+        factored_latex.clone(),
+    );
+    use_render_math(
+        node_ref_derivative.clone(),
+        props.expr.clone(),
+        derivative_latex.clone(),
+    );
 
     html! {
         <>
             <p>{ "Simplified expression:" }</p>
             <div ref={node_ref_simplified}>
-                <code>{ simplified_expr_latex }</code>
+                <pre><code>{ simplified_expr_latex }</code></pre>
+            </div>
+
+            <p>{ "Expanded form:" }</p>
+            <div ref={node_ref_expanded}>
+                <code>{ expanded_latex }</code>
+            </div>
+
+            <p>{ "Factored form:" }</p>
+            <div ref={node_ref_factored}>
+                <code>{ factored_latex }</code>
             </div>
 
             <p>{ "Derivative w.r.t x:" }</p>
             <div ref={node_ref_derivative}>
                 <code>{ derivative_latex }</code>
             </div>
+
+            { for diagnostics.into_iter().map(|d| html! { <pre><code>{ d }</code></pre> }) }
         </>
     }
 }
+
+/// Typeset the math in the element of `node_ref` whenever the input or its
+/// LaTeX changes. Use effect with setTimeout to defer renderMathInElement
+/// call safely.
+#[hook]
+fn use_render_math(node_ref: NodeRef, expr: String, latex: String) {
+    use_effect_with((expr, latex), move |_| {
+        if let Some(elem) = node_ref.cast::<Element>() {
+            let closure = Closure::once(move || {
+                render_math_in_element(elem);
+            });
+            window()
+                .unwrap()
+                .set_timeout_with_callback(closure.as_ref().unchecked_ref())
+                .expect("failed to set timeout");
+            closure.forget();
+        }
+        || ()
+    });
+}
@@ -1,13 +1,23 @@
-use cliph::math::algebra::simplify;
+use cliph::math::algebra::{expand, simplify};
 use cliph::math::ast::BinaryOp::*;
 use cliph::math::ast::Expr::*;
 use cliph::math::ast::UnaryOp::*;
+use cliph::math::ast::{Arity, Constant, RelOp, Relation, Statement};
+use cliph::math::diff::differentiate;
+use cliph::math::env::{EnvError, Environment};
+use cliph::math::eval::{evaluate, evaluate_complex, evaluate_relation, Complex64};
+use cliph::math::factor::{factor, factor_poly};
+use cliph::math::format::{format_expr, format_expr_latex, format_statement_latex};
+use cliph::math::latex::{parse_latex, parse_latex_statement};
+use cliph::math::parser::{
+    parse, parse_input, parse_statement, Expected, NumberError, ParseError, Span,
+};
+use cliph::math::poly::Poly;
 use cliph::BinaryOp;
 use cliph::Expr;
-use ordered_float::OrderedFloat;
-
+/// `n` as the parser reads its decimal spelling, so `num(0.1)` is exact.
 fn num(n: f64) -> Expr {
-    Number(OrderedFloat(n))
+    Number(n.to_string().parse().unwrap())
 }
 
 fn var(name: &str) -> Expr {
@@ -15,7 +25,131 @@ fn var(name: &str) -> Expr {
 }
 
 fn func(name: &str, arg: Expr) -> Expr {
-    Function(name.to_string(), Box::new(arg))
+    Function(name.to_string(), vec![arg])
+}
+
+fn funcn(name: &str, args: Vec<Expr>) -> Expr {
+    Function(name.to_string(), args)
+}
+
+fn add(a: Expr, b: Expr) -> Expr {
+    BinaryOp(Add, Box::new(a), Box::new(b))
+}
+
+fn sub(a: Expr, b: Expr) -> Expr {
+    BinaryOp(Sub, Box::new(a), Box::new(b))
+}
+
+fn mul(a: Expr, b: Expr) -> Expr {
+    BinaryOp(Mul, Box::new(a), Box::new(b))
+}
+
+fn div(a: Expr, b: Expr) -> Expr {
+    BinaryOp(Div, Box::new(a), Box::new(b))
+}
+
+fn pow(a: Expr, b: Expr) -> Expr {
+    BinaryOp(Pow, Box::new(a), Box::new(b))
+}
+
+fn neg(a: Expr) -> Expr {
+    UnaryOp(Neg, Box::new(a))
+}
+
+fn fact(a: Expr) -> Expr {
+    UnaryOp(Factorial, Box::new(a))
+}
+
+fn percent(a: Expr) -> Expr {
+    UnaryOp(Percent, Box::new(a))
+}
+
+fn konst(c: Constant) -> Expr {
+    Expr::Constant(c)
+}
+
+fn derivative(e: Expr, var: &str, order: u32) -> Expr {
+    Derivative(Box::new(e), var.into(), order)
+}
+
+/// The integral of `e` in `var`, definite when `bounds` is given.
+fn integral(e: Expr, var: &str, bounds: Option<(Expr, Expr)>) -> Expr {
+    Integral(Box::new(e), var.into(), bounds.map(Box::new))
+}
+
+fn rel(op: RelOp, lhs: Expr, rhs: Expr) -> Statement {
+    Statement::Relation(Relation { op, lhs, rhs })
+}
+
+fn piecewise() -> Expr {
+    let cond = |op, rhs| Relation {
+        op,
+        lhs: var("x"),
+        rhs,
+    };
+    Piecewise(
+        vec![
+            (pow(var("x"), num(2.0)), cond(RelOp::Lt, num(0.0))),
+            (num(1.0), cond(RelOp::Eq, num(0.0))),
+        ],
+        Some(Box::new(func("sin", var("x")))),
+    )
+}
+
+/// sech(u) = 1 / cosh(u), with d/du sech(u) = -sech(u) tanh(u). Registering
+/// twice replaces it with the same function, so every test may call this.
+fn register_sech() {
+    use cliph::math::functions::{register, FunctionInfo};
+
+    register(
+        FunctionInfo::new("sech", Arity::Exact(1))
+            .real(|xs| 1.0 / xs[0].cosh())
+            .derivative_of_arg(|u| neg(mul(func("sech", u.clone()), func("tanh", u.clone()))))
+            .exact(|args| match args {
+                [Number(n)] if n.is_zero() => Some(num(1.0)),
+                _ => None,
+            })
+            .latex_command("\\operatorname{sech}"),
+    );
+}
+
+fn simplified(input: &str) -> Expr {
+    simplify(&parse(input).unwrap())
+}
+
+/// The LaTeX of `input` once simplified.
+fn latex(input: &str) -> String {
+    format_expr_latex(&simplified(input))
+}
+
+/// Asserts that `a` and `b` simplify to the same expression.
+fn same(a: &str, b: &str) {
+    assert_eq!(simplified(a), simplified(b), "{} = {}", a, b);
+}
+
+/// The LaTeX of `input` once expanded.
+fn expanded(input: &str) -> String {
+    format_expr_latex(&expand(&parse(input).unwrap()))
+}
+
+fn poly(input: &str) -> Poly {
+    Poly::from_expr(&parse(input).unwrap()).unwrap()
+}
+
+/// Asserts that `p` is the polynomial `input`.
+fn same_poly(p: &Poly, input: &str) {
+    assert_eq!(*p, poly(input), "{}", input);
+}
+
+/// The LaTeX of `input` once factored.
+fn factored(input: &str) -> String {
+    format_expr_latex(&factor(&parse(input).unwrap()))
+}
+
+/// Asserts that `input` evaluates to `expected` in complex numbers.
+fn close(input: &str, expected: Complex64) {
+    let z = evaluate_complex(&parse(input).unwrap());
+    assert!((z - expected).norm() < 1e-12, "{} = {}", input, z);
 }
 
 fn fold_binary_ops(op: BinaryOp, mut exprs: Vec<Expr>) -> Expr {
@@ -30,7 +164,7 @@ fn fold_binary_ops(op: BinaryOp, mut exprs: Vec<Expr>) -> Expr {
 
 #[test]
 fn test_number_identity() {
-    assert_eq!(simplify(&num(3.14)), num(3.14));
+    assert_eq!(simplify(&num(2.5)), num(2.5));
 }
 
 #[test]
@@ -40,91 +174,63 @@ fn test_variable_identity() {
 
 #[test]
 fn test_add_zero() {
-    assert_eq!(
-        simplify(&BinaryOp(Add, Box::new(var("x")), Box::new(num(0.0)))),
-        var("x")
-    );
+    assert_eq!(simplify(&add(var("x"), num(0.0))), var("x"));
 }
 
 #[test]
 fn test_mul_one() {
-    assert_eq!(
-        simplify(&BinaryOp(Mul, Box::new(var("x")), Box::new(num(1.0)))),
-        var("x")
-    );
+    assert_eq!(simplify(&mul(var("x"), num(1.0))), var("x"));
 }
 
 #[test]
 fn test_mul_zero() {
-    assert_eq!(
-        simplify(&BinaryOp(Mul, Box::new(var("x")), Box::new(num(0.0)))),
-        num(0.0)
-    );
+    assert_eq!(simplify(&mul(var("x"), num(0.0))), num(0.0));
 }
 
 #[test]
 fn test_pow_zero() {
-    assert_eq!(
-        simplify(&BinaryOp(Pow, Box::new(var("x")), Box::new(num(0.0)))),
-        num(1.0)
-    );
+    assert_eq!(simplify(&pow(var("x"), num(0.0))), num(1.0));
 }
 
 #[test]
 fn test_pow_one() {
-    assert_eq!(
-        simplify(&BinaryOp(Pow, Box::new(var("x")), Box::new(num(1.0)))),
-        var("x")
-    );
+    assert_eq!(simplify(&pow(var("x"), num(1.0))), var("x"));
 }
 
 #[test]
 fn test_addition_constants() {
-    assert_eq!(
-        simplify(&BinaryOp(Add, Box::new(num(3.0)), Box::new(num(2.0)))),
-        num(5.0)
-    );
+    assert_eq!(simplify(&add(num(3.0), num(2.0))), num(5.0));
 }
 
 #[test]
 fn test_combine_like_terms() {
-    let expr = BinaryOp(
-        Add,
-        Box::new(BinaryOp(Mul, Box::new(num(2.0)), Box::new(var("x")))),
-        Box::new(var("x")),
-    );
-    assert_eq!(
-        simplify(&expr),
-        BinaryOp(Mul, Box::new(num(3.0)), Box::new(var("x")))
-    );
+    let expr = add(mul(num(2.0), var("x")), var("x"));
+    assert_eq!(simplify(&expr), mul(num(3.0), var("x")));
+}
+
+#[test]
+fn test_combine_like_terms_with_fractional_coefficients() {
+    assert_eq!(simplified("x/2 + x/2"), var("x"));
+    assert_eq!(simplified("1.5x - x/2"), var("x"));
+    assert_eq!(simplified("2/x + 3/x"), simplified("5/x"));
+    assert_eq!(simplified("y/x + 2y/x"), simplified("3y/x"));
 }
 
 #[test]
 fn test_combine_constants_and_vars() {
-    let expr = BinaryOp(
-        Add,
-        Box::new(num(5.0)),
-        Box::new(BinaryOp(Add, Box::new(num(2.0)), Box::new(var("x")))),
-    );
-    assert_eq!(
-        simplify(&expr),
-        BinaryOp(Add, Box::new(num(7.0)), Box::new(var("x")))
-    );
+    let expr = add(num(5.0), add(num(2.0), var("x")));
+    assert_eq!(simplify(&expr), add(var("x"), num(7.0)));
 }
 
 #[test]
 fn test_add_double_neg() {
-    let expr = BinaryOp(
-        Add,
-        Box::new(UnaryOp(Neg, Box::new(UnaryOp(Neg, Box::new(var("x")))))),
-        Box::new(num(2.0)),
-    );
+    let expr = add(neg(neg(var("x"))), num(2.0));
 
     let simplified = simplify(&expr);
 
-    let expected1 = BinaryOp(Add, Box::new(var("x")), Box::new(num(2.0)));
+    let expected1 = add(var("x"), num(2.0));
 
-    let expected2 = BinaryOp(Add, Box::new(num(2.0)), Box::new(var("x")));
+    let expected2 = add(num(2.0), var("x"));
 
     assert!(
         simplified == expected1 || simplified == expected2,
@@ -137,37 +243,25 @@ fn test_add_double_neg() {
 
 #[test]
 fn test_subtract_same_var() {
-    let expr = BinaryOp(Sub, Box::new(var("x")), Box::new(var("x")));
+    let expr = sub(var("x"), var("x"));
     assert_eq!(simplify(&expr), num(0.0));
 }
 
 #[test]
 fn test_subtract_constants() {
-    let expr = BinaryOp(Sub, Box::new(num(5.0)), Box::new(num(3.0)));
+    let expr = sub(num(5.0), num(3.0));
     assert_eq!(simplify(&expr), num(2.0));
 }
 
 #[test]
 fn test_subtract_distributes_negation() {
-    let expr = BinaryOp(
-        Sub,
-        Box::new(num(2.0)),
-        Box::new(BinaryOp(Add, Box::new(var("x")), Box::new(num(3.0)))),
-    );
+    let expr = sub(num(2.0), add(var("x"), num(3.0)));
     let simplified = simplify(&expr);
 
     // Expected simplified form: (-1) + (-x)
-    let expected1 = BinaryOp(
-        Add,
-        Box::new(num(-1.0)),
-        Box::new(UnaryOp(Neg, Box::new(var("x")))),
-    );
+    let expected1 = add(num(-1.0), neg(var("x")));
 
-    let expected2 = BinaryOp(
-        Add,
-        Box::new(UnaryOp(Neg, Box::new(var("x")))),
-        Box::new(num(-1.0)),
-    );
+    let expected2 = add(neg(var("x")), num(-1.0));
 
     assert!(
         simplified == expected1 || simplified == expected2,
@@ -180,82 +274,52 @@ fn test_subtract_distributes_negation() {
 
 #[test]
 fn test_multiply_constants() {
-    assert_eq!(
-        simplify(&BinaryOp(Mul, Box::new(num(3.0)), Box::new(num(4.0)))),
-        num(12.0)
-    );
+    assert_eq!(simplify(&mul(num(3.0), num(4.0))), num(12.0));
 }
 
 #[test]
 fn test_multiply_and_combine_like_terms() {
-    let expr = BinaryOp(
-        Add,
-        Box::new(BinaryOp(Mul, Box::new(num(2.0)), Box::new(var("x")))),
-        Box::new(BinaryOp(Mul, Box::new(num(3.0)), Box::new(var("x")))),
-    );
-    assert_eq!(
-        simplify(&expr),
-        BinaryOp(Mul, Box::new(num(5.0)), Box::new(var("x")))
-    );
+    let expr = add(mul(num(2.0), var("x")), mul(num(3.0), var("x")));
+    assert_eq!(simplify(&expr), mul(num(5.0), var("x")));
 }
 
 #[test]
 fn test_div_by_one() {
-    assert_eq!(
-        simplify(&BinaryOp(Div, Box::new(var("x")), Box::new(num(1.0)))),
-        var("x")
-    );
+    assert_eq!(simplify(&div(var("x"), num(1.0))), var("x"));
 }
 
 #[test]
 fn test_zero_div() {
-    assert_eq!(
-        simplify(&BinaryOp(Div, Box::new(num(0.0)), Box::new(var("x")))),
-        num(0.0)
-    );
+    assert_eq!(simplify(&div(num(0.0), var("x"))), num(0.0));
 }
 
 #[test]
 fn test_div_constants() {
-    assert_eq!(
-        simplify(&BinaryOp(Div, Box::new(num(6.0)), Box::new(num(2.0)))),
-        num(3.0)
-    );
+    assert_eq!(simplify(&div(num(6.0), num(2.0))), num(3.0));
 }
 
 #[test]
 fn test_nested_add_flattened() {
-    let expr = BinaryOp(
-        Add,
-        Box::new(BinaryOp(Add, Box::new(var("x")), Box::new(var("y")))),
-        Box::new(var("z")),
-    );
+    let expr = add(add(var("x"), var("y")), var("z"));
     let expected = fold_binary_ops(Add, vec![var("x"), var("y"), var("z")]);
     assert_eq!(simplify(&expr), expected);
 }
 
 #[test]
 fn test_nested_mul_flattened() {
-    let expr = BinaryOp(
-        Mul,
-        Box::new(BinaryOp(Mul, Box::new(num(2.0)), Box::new(var("x")))),
-        Box::new(num(3.0)),
-    );
-    assert_eq!(
-        simplify(&expr),
-        BinaryOp(Mul, Box::new(num(6.0)), Box::new(var("x")))
-    );
+    let expr = mul(mul(num(2.0), var("x")), num(3.0));
+    assert_eq!(simplify(&expr), mul(num(6.0), var("x")));
 }
 
 #[test]
 fn test_double_negation() {
-    let expr = UnaryOp(Neg, Box::new(UnaryOp(Neg, Box::new(var("x")))));
+    let expr = neg(neg(var("x")));
     assert_eq!(simplify(&expr), var("x"));
 }
 
 #[test]
 fn test_negate_number() {
-    assert_eq!(simplify(&UnaryOp(Neg, Box::new(num(3.0)))), num(-3.0));
+    assert_eq!(simplify(&neg(num(3.0))), num(-3.0));
 }
 
 #[test]
@@ -297,109 +361,2025 @@ fn test_log_negative_should_not_simplify() {
 
 #[test]
 fn test_add_like_functions() {
-    let expr = BinaryOp(
-        Add,
-        Box::new(func("cos", var("x"))),
-        Box::new(func("cos", var("x"))),
-    );
+    let expr = add(func("cos", var("x")), func("cos", var("x")));
     let simplified = simplify(&expr);
 
-    let expected = BinaryOp(Mul, Box::new(num(2.0)), Box::new(func("cos", var("x"))));
+    let expected = mul(num(2.0), func("cos", var("x")));
 
     assert_eq!(simplified, expected);
 }
 
 #[test]
 fn test_pythagorean_identity() {
-    use Expr::*;
-
     let x = var("x");
-    let sin2 = BinaryOp(Pow, Box::new(func("sin", x.clone())), Box::new(num(2.0)));
-    let cos2 = BinaryOp(Pow, Box::new(func("cos", x.clone())), Box::new(num(2.0)));
+    let sin2 = pow(func("sin", x.clone()), num(2.0));
+    let cos2 = pow(func("cos", x.clone()), num(2.0));
 
-    let expr = BinaryOp(Add, Box::new(sin2), Box::new(cos2));
+    let expr = add(sin2, cos2);
     let simplified = simplify(&expr);
     assert_eq!(simplified, num(1.0));
 }
 
 #[test]
 fn test_nested_add_constants_flattened() {
-    let expr = BinaryOp(
-        Add,
-        Box::new(BinaryOp(Add, Box::new(num(1.0)), Box::new(num(2.0)))),
-        Box::new(num(3.0)),
-    );
+    let expr = add(add(num(1.0), num(2.0)), num(3.0));
     assert_eq!(simplify(&expr), num(6.0));
 }
 
 #[test]
 fn test_nested_mul_constants_flattened() {
-    let expr = BinaryOp(
-        Mul,
-        Box::new(BinaryOp(Mul, Box::new(num(2.0)), Box::new(num(3.0)))),
-        Box::new(num(4.0)),
-    );
+    let expr = mul(mul(num(2.0), num(3.0)), num(4.0));
     assert_eq!(simplify(&expr), num(24.0));
 }
 
 #[test]
 fn test_pythagorean_identity_cos2_plus_sin2_reversed() {
     let x = var("x");
-    let cos2 = BinaryOp(Pow, Box::new(func("cos", x.clone())), Box::new(num(2.0)));
-    let sin2 = BinaryOp(Pow, Box::new(func("sin", x.clone())), Box::new(num(2.0)));
+    let cos2 = pow(func("cos", x.clone()), num(2.0));
+    let sin2 = pow(func("sin", x.clone()), num(2.0));
 
-    let expr = BinaryOp(Add, Box::new(cos2), Box::new(sin2));
+    let expr = add(cos2, sin2);
     assert_eq!(simplify(&expr), num(1.0));
 }
 
 #[test]
 fn test_subtract_identical_functions() {
-    let expr = BinaryOp(
-        Sub,
-        Box::new(func("cos", var("x"))),
-        Box::new(func("cos", var("x"))),
-    );
+    let expr = sub(func("cos", var("x")), func("cos", var("x")));
     assert_eq!(simplify(&expr), num(0.0));
 }
 
 #[test]
 fn test_negation_of_sum() {
-    let expr = UnaryOp(
-        Neg,
-        Box::new(BinaryOp(Add, Box::new(var("x")), Box::new(var("y")))),
-    );
+    let expr = neg(add(var("x"), var("y")));
 
     let simplified = simplify(&expr);
 
-    let expected = BinaryOp(
-        Add,
-        Box::new(UnaryOp(Neg, Box::new(var("x")))),
-        Box::new(UnaryOp(Neg, Box::new(var("y")))),
-    );
+    let expected = add(neg(var("x")), neg(var("y")));
 
     assert_eq!(simplified, simplify(&expected));
 }
 
 #[test]
 fn test_combine_trig_like_terms() {
-    let expr = BinaryOp(
-        Add,
-        Box::new(func("sin", var("x"))),
-        Box::new(func("sin", var("x"))),
-    );
+    let expr = add(func("sin", var("x")), func("sin", var("x")));
 
     let simplified = simplify(&expr);
-    let expected = BinaryOp(Mul, Box::new(num(2.0)), Box::new(func("sin", var("x"))));
+    let expected = mul(num(2.0), func("sin", var("x")));
 
     assert_eq!(simplified, expected);
 }
 
 #[test]
 fn test_power_zero_nested() {
-    let expr = BinaryOp(
-        Pow,
-        Box::new(BinaryOp(Add, Box::new(var("x")), Box::new(num(1.0)))),
-        Box::new(num(0.0)),
-    );
+    let expr = pow(add(var("x"), num(1.0)), num(0.0));
     assert_eq!(simplify(&expr), num(1.0));
 }
+
+#[test]
+fn test_parse_error_unexpected_char_span() {
+    let err = parse("x + # 2").unwrap_err();
+    assert_eq!(
+        err,
+        ParseError::UnexpectedChar {
+            found: '#',
+            span: Span::new(4, 5),
+            expected: vec![Expected::Expression],
+        }
+    );
+}
+
+#[test]
+fn test_parse_error_unclosed_paren() {
+    let err = parse("sin(x + 1").unwrap_err();
+    assert_eq!(
+        err,
+        ParseError::UnclosedDelimiter {
+            open: '(',
+            open_span: Span::new(3, 4),
+            span: Span::point(9),
+        }
+    );
+}
+
+#[test]
+fn test_parse_error_trailing_input() {
+    let err = parse("(x))").unwrap_err();
+    assert_eq!(err.span(), Span::new(3, 4));
+    assert_eq!(err.expected(), &[Expected::Operator, Expected::EndOfInput]);
+}
+
+#[test]
+fn test_parse_error_span_counts_bytes() {
+    let err = parse("é + $").unwrap_err();
+    assert_eq!(err.span(), Span::new(5, 6));
+    assert_eq!(err.span().char_range("é + $"), (4, 5));
+}
+
+#[test]
+fn test_parse_error_render_caret() {
+    let source = "x + # 2";
+    let rendered = parse(source).unwrap_err().render(source);
+    assert_eq!(
+        rendered,
+        "error: unexpected character '#'\n --> 1:5\n  |\n1 | x + # 2\n  |     ^ expected expression"
+    );
+}
+
+#[test]
+fn test_parse_multi_argument_function() {
+    assert_eq!(
+        parse("max(a, b, c)").unwrap(),
+        funcn("max", vec![var("a"), var("b"), var("c")])
+    );
+    assert_eq!(
+        parse("log(2, x)").unwrap(),
+        funcn("log", vec![num(2.0), var("x")])
+    );
+}
+
+#[test]
+fn test_parse_arity_mismatch() {
+    let err = parse("1 + atan2(y)").unwrap_err();
+    assert_eq!(
+        err,
+        ParseError::ArityMismatch {
+            name: "atan2".to_string(),
+            expected: Arity::Exact(2),
+            found: 1,
+            span: Span::new(4, 12),
+        }
+    );
+}
+
+#[test]
+fn test_evaluate_multi_argument_functions() {
+    assert_eq!(evaluate(&parse("log(2, 8)").unwrap()), 3.0);
+    assert_eq!(evaluate(&parse("max(1, 5, 3)").unwrap()), 5.0);
+    assert_eq!(evaluate(&parse("min(4, -2)").unwrap()), -2.0);
+    assert_eq!(evaluate(&parse("hypot(3, 4)").unwrap()), 5.0);
+    assert_eq!(evaluate(&parse("root(-8, 3)").unwrap()), -2.0);
+}
+
+#[test]
+fn test_simplify_multi_argument_functions() {
+    assert_eq!(simplify(&parse("atan2(0, 1)").unwrap()), num(0.0));
+    assert_eq!(simplify(&parse("max(x)").unwrap()), var("x"));
+    let expr = funcn("max", vec![var("x"), num(3.0)]);
+    assert_eq!(simplify(&expr), expr);
+}
+
+#[test]
+fn test_differentiate_log_base() {
+    let d = differentiate(&parse("log(2, x)").unwrap(), "x");
+    let at = |x: f64| {
+        let mut env = Environment::new();
+        env.set_var("x", x);
+        cliph::math::eval::evaluate_with_env(&d, &env)
+    };
+    assert!((at(3.0) - 1.0 / (3.0 * 2f64.ln())).abs() < 1e-12);
+}
+
+#[test]
+fn test_differentiate_exponentials() {
+    let derivative = |input: &str| differentiate(&parse(input).unwrap(), "x");
+    assert_eq!(derivative("e^x"), simplified("e^x"));
+    assert_eq!(derivative("2^x"), simplified("2^x ln(2)"));
+    assert_eq!(derivative("2^(3x)"), simplified("3 * 2^(3x) ln(2)"));
+    assert_eq!(format_expr(&derivative("x^x")), "x ^ x * (ln(x) + 1)");
+}
+
+#[test]
+fn test_format_multi_argument_functions() {
+    assert_eq!(
+        format_expr_latex(&parse("log(2, x)").unwrap()),
+        "\\log_{2}\\left(x\\right)"
+    );
+    assert_eq!(
+        format_expr_latex(&parse("root(x, 3)").unwrap()),
+        "\\sqrt[3]{x}"
+    );
+    assert_eq!(
+        format_expr_latex(&parse("max(a, b)").unwrap()),
+        "\\max\\left(a, b\\right)"
+    );
+}
+
+#[test]
+fn test_latex_nested_frac() {
+    assert_eq!(
+        parse_latex(r"\frac{\frac{1}{x}}{2}").unwrap(),
+        div(div(num(1.0), var("x")), num(2.0))
+    );
+}
+
+#[test]
+fn test_latex_sqrt_and_operators() {
+    assert_eq!(
+        parse_latex(r"\sqrt{x} \cdot 3 \times y").unwrap(),
+        mul(mul(func("sqrt", var("x")), num(3.0)), var("y"))
+    );
+    assert_eq!(
+        parse_latex(r"\sqrt[3]{x}").unwrap(),
+        funcn("root", vec![var("x"), num(3.0)])
+    );
+}
+
+#[test]
+fn test_latex_left_right_and_braced_exponent() {
+    assert_eq!(
+        parse_latex(r"$\left(x + 1\right)^{2 y}$").unwrap(),
+        pow(add(var("x"), num(1.0)), mul(num(2.0), var("y")))
+    );
+    // A bare exponent takes a single digit
+    assert_eq!(
+        parse_latex("x^23").unwrap(),
+        mul(pow(var("x"), num(2.0)), num(3.0))
+    );
+}
+
+#[test]
+fn test_latex_functions() {
+    assert_eq!(parse_latex(r"\ln x").unwrap(), func("ln", var("x")));
+    assert_eq!(
+        parse_latex(r"\log_2 x").unwrap(),
+        funcn("log", vec![num(2.0), var("x")])
+    );
+    assert_eq!(
+        parse_latex(r"\operatorname{atan2}(y, x)").unwrap(),
+        funcn("atan2", vec![var("y"), var("x")])
+    );
+    assert_eq!(
+        parse_latex(r"2\pi r").unwrap(),
+        mul(mul(num(2.0), konst(Constant::Pi)), var("r"))
+    );
+    assert_eq!(
+        parse_latex(r"\sin^2 x").unwrap(),
+        pow(func("sin", var("x")), num(2.0))
+    );
+}
+
+#[test]
+fn test_latex_abs_bars() {
+    assert_eq!(
+        parse_latex(r"\left|x\right| |y|").unwrap(),
+        mul(func("abs", var("x")), func("abs", var("y")))
+    );
+}
+
+#[test]
+fn test_latex_errors() {
+    assert_eq!(
+        parse_latex(r"\foo{x}").unwrap_err(),
+        ParseError::UnknownCommand {
+            name: "foo".to_string(),
+            span: Span::new(0, 4),
+        }
+    );
+    assert!(matches!(
+        parse_latex(r"\frac{1}{x").unwrap_err(),
+        ParseError::UnclosedDelimiter { open: '{', .. }
+    ));
+}
+
+#[test]
+fn test_latex_round_trip() {
+    let exprs = vec![
+        add(mul(num(3.0), var("x")), num(1.0)),
+        div(func("sin", var("x")), pow(var("x"), num(2.0))),
+        funcn("log", vec![num(2.0), var("x")]),
+        funcn("root", vec![var("x"), num(3.0)]),
+        func("sqrt", var("y")),
+        funcn("max", vec![var("a"), var("b")]),
+        funcn("atan2", vec![var("y"), var("x")]),
+        func("abs", var("x")),
+        mul(func("exp", var("x")), func("cos", var("x"))),
+        func("f", var("x")),
+        funcn("g", vec![var("x"), var("y")]),
+        func("theta_1", var("t")),
+        func("f'", var("x")),
+        func("my_fn", var("x")),
+        derivative(func("f", var("x")), "x", 1),
+        integral(func("f", var("x")), "x", None),
+        List(vec![num(1.0), var("x"), pow(var("x"), num(2.0))]),
+        List(vec![]),
+        funcn("range", vec![num(1.0), var("n")]),
+        func("sum", List(vec![funcn("range", vec![num(1.0), num(5.0)])])),
+    ];
+    for expr in exprs {
+        let latex = format_expr_latex(&expr);
+        assert_eq!(
+            parse_latex(&latex).unwrap(),
+            expr,
+            "round trip of {}",
+            latex
+        );
+    }
+
+    let define = parse_statement("f(x) = x^2").unwrap();
+    let latex = format_statement_latex(&define);
+    assert_eq!(parse_latex_statement(&latex).unwrap(), define);
+    assert!(matches!(define, Statement::Define(..)));
+    assert_eq!(
+        parse_latex("[1, 2] + 1 \\ldots 3").unwrap(),
+        funcn(
+            "range",
+            vec![add(List(vec![num(1.0), num(2.0)]), num(1.0)), num(3.0)]
+        )
+    );
+    // Only a sized parenthesis marks a call
+    assert_eq!(
+        parse_latex("x(y + 1)").unwrap(),
+        mul(var("x"), add(var("y"), num(1.0)))
+    );
+}
+
+#[test]
+fn test_parse_pow_right_associative() {
+    assert_eq!(
+        parse("2^3^2").unwrap(),
+        pow(num(2.0), pow(num(3.0), num(2.0)))
+    );
+    assert_eq!(evaluate(&parse("2^3^2").unwrap()), 512.0);
+}
+
+#[test]
+fn test_parse_unary_minus_below_pow() {
+    assert_eq!(parse("-x^2").unwrap(), neg(pow(var("x"), num(2.0))));
+    assert_eq!(evaluate(&parse("-2^2").unwrap()), -4.0);
+    assert_eq!(parse("2^-1").unwrap(), pow(num(2.0), neg(num(1.0))));
+    assert_eq!(
+        parse("2x^2 - 3").unwrap(),
+        sub(mul(num(2.0), pow(var("x"), num(2.0))), num(3.0))
+    );
+}
+
+#[test]
+fn test_parse_left_associative_sub_div() {
+    assert_eq!(
+        parse("8 - 4 - 2").unwrap(),
+        sub(sub(num(8.0), num(4.0)), num(2.0))
+    );
+    assert_eq!(evaluate(&parse("8 / 4 / 2").unwrap()), 1.0);
+}
+
+#[test]
+fn test_operator_table() {
+    use cliph::math::ast::{Assoc, NEG_PRECEDENCE};
+    assert!(Add.precedence() < Mul.precedence());
+    assert!(Mul.precedence() < NEG_PRECEDENCE);
+    assert!(NEG_PRECEDENCE < Pow.precedence());
+    assert_eq!(Pow.assoc(), Assoc::Right);
+    assert_eq!(BinaryOp::from_symbol('/'), Some(Div));
+}
+
+#[test]
+fn test_format_expr_minimal_parentheses() {
+    assert_eq!(
+        format_expr(&mul(add(var("x"), num(1.0)), var("y"))),
+        "(x + 1) * y"
+    );
+    assert_eq!(format_expr(&neg(pow(var("x"), num(2.0)))), "-x ^ 2");
+    assert_eq!(format_expr(&pow(neg(var("x")), num(2.0))), "(-x) ^ 2");
+    assert_eq!(
+        format_expr(&sub(var("a"), sub(var("b"), var("c")))),
+        "a - (b - c)"
+    );
+}
+
+#[test]
+fn test_format_expr_round_trip() {
+    for src in [
+        "(x + 1) * y",
+        "2 ^ 3 ^ 2",
+        "(2 ^ 3) ^ 2",
+        "-x ^ 2",
+        "a - (b - c)",
+        "x / (y * z)",
+    ] {
+        let expr = parse(src).unwrap();
+        assert_eq!(format_expr(&expr), src);
+        assert_eq!(parse(&format_expr(&expr)).unwrap(), expr);
+    }
+}
+
+#[test]
+fn test_format_latex_parentheses() {
+    assert_eq!(
+        format_expr_latex(&mul(add(var("x"), num(1.0)), var("y"))),
+        r"\left(x + 1\right) y"
+    );
+    assert_eq!(
+        format_expr_latex(&pow(add(var("x"), num(1.0)), num(2.0))),
+        r"\left(x + 1\right)^{2}"
+    );
+    assert_eq!(format_expr_latex(&add(var("x"), neg(var("y")))), "x - y");
+    assert_eq!(format_expr_latex(&mul(var("x"), num(2.0))), r"x \cdot 2");
+    let expr = mul(add(var("x"), num(1.0)), pow(var("x"), num(2.0)));
+    assert_eq!(parse_latex(&format_expr_latex(&expr)).unwrap(), expr);
+}
+
+#[test]
+fn test_parse_scientific_notation() {
+    assert_eq!(parse("6.02e23").unwrap(), num(6.02e23));
+    assert_eq!(parse("1E-9").unwrap(), num(1e-9));
+    assert_eq!(parse("2.5e+3").unwrap(), num(2500.0));
+    assert_eq!(parse(".5").unwrap(), num(0.5));
+    assert_eq!(parse("1e-3x").unwrap(), mul(num(1e-3), var("x")));
+}
+
+#[test]
+fn test_parse_e_without_exponent_digits_is_implicit_mul() {
+    assert_eq!(parse("2e").unwrap(), mul(num(2.0), konst(Constant::E)));
+    assert_eq!(
+        parse("2e-x").unwrap(),
+        sub(mul(num(2.0), konst(Constant::E)), var("x"))
+    );
+}
+
+#[test]
+fn test_parse_radix_literals() {
+    assert_eq!(parse("0xff").unwrap(), num(255.0));
+    assert_eq!(parse("0b1010").unwrap(), num(10.0));
+    assert_eq!(parse("0o17").unwrap(), num(15.0));
+    assert_eq!(parse("0xFF_FF").unwrap(), num(65535.0));
+}
+
+#[test]
+fn test_parse_digit_separators() {
+    assert_eq!(parse("1_000_000").unwrap(), num(1_000_000.0));
+    assert_eq!(parse("1_000.000_5").unwrap(), num(1000.0005));
+}
+
+#[test]
+fn test_parse_malformed_numbers() {
+    let cases = [
+        ("1.2.3", NumberError::MultipleDecimalPoints, 0, 5),
+        ("1 + 0x", NumberError::MissingDigits, 4, 6),
+        ("1__000", NumberError::MisplacedSeparator, 0, 6),
+        ("10_", NumberError::MisplacedSeparator, 0, 3),
+        ("0b102", NumberError::InvalidDigit, 0, 5),
+    ];
+    for (src, reason, start, end) in cases {
+        assert_eq!(
+            parse(src).unwrap_err(),
+            ParseError::InvalidNumber {
+                literal: src[start..end].to_string(),
+                reason,
+                span: Span::new(start, end),
+            },
+            "{}",
+            src
+        );
+    }
+}
+
+#[test]
+fn test_parse_constants() {
+    assert_eq!(parse("pi").unwrap(), konst(Constant::Pi));
+    assert_eq!(parse("2e").unwrap(), mul(num(2.0), konst(Constant::E)));
+    assert_eq!(parse("inf").unwrap(), konst(Constant::Infinity));
+    assert_eq!(parse_latex(r"\infty").unwrap(), konst(Constant::Infinity));
+    assert_eq!(
+        parse_latex(r"e^{x}").unwrap(),
+        pow(konst(Constant::E), var("x"))
+    );
+}
+
+#[test]
+fn test_evaluate_constants() {
+    assert_eq!(evaluate(&parse("pi").unwrap()), std::f64::consts::PI);
+    assert_eq!(evaluate(&parse("tau / 2").unwrap()), std::f64::consts::PI);
+    assert!((evaluate(&parse("phi^2 - phi").unwrap()) - 1.0).abs() < 1e-12);
+    assert_eq!(evaluate(&parse("e").unwrap()), std::f64::consts::E);
+}
+
+#[test]
+fn test_simplify_exact_constant_values() {
+    assert_eq!(simplify(&parse("sin(pi)").unwrap()), num(0.0));
+    assert_eq!(simplify(&parse("cos(pi)").unwrap()), num(-1.0));
+    assert_eq!(simplify(&parse("sin(pi/2)").unwrap()), num(1.0));
+    assert_eq!(simplify(&parse("cos(2pi)").unwrap()), num(1.0));
+    assert_eq!(simplify(&parse("sin(pi/6)").unwrap()), num(0.5));
+    assert_eq!(simplify(&parse("tan(pi/4)").unwrap()), num(1.0));
+    assert_eq!(simplify(&parse("tan(3pi/4)").unwrap()), num(-1.0));
+    assert_eq!(
+        simplify(&parse("cos(pi/4)").unwrap()),
+        simplify(&parse("sqrt(2)/2").unwrap())
+    );
+    assert_eq!(
+        simplify(&parse("sin(4pi/3)").unwrap()),
+        simplify(&parse("-sqrt(3)/2").unwrap())
+    );
+    assert_eq!(simplify(&parse("log(e)").unwrap()), num(1.0));
+    assert_eq!(simplify(&parse("exp(0)").unwrap()), num(1.0));
+    assert_eq!(simplify(&parse("exp(1)").unwrap()), konst(Constant::E));
+}
+
+#[test]
+fn test_simplify_keeps_constants_symbolic() {
+    assert_eq!(
+        simplify(&parse("pi + pi").unwrap()),
+        mul(num(2.0), konst(Constant::Pi))
+    );
+    let expr = parse("sin(pi/5)").unwrap();
+    assert_eq!(simplify(&expr), expr);
+}
+
+#[test]
+fn test_differentiate_constant() {
+    assert_eq!(differentiate(&parse("pi").unwrap(), "x"), num(0.0));
+    assert_eq!(
+        differentiate(&parse("pi x").unwrap(), "x"),
+        konst(Constant::Pi)
+    );
+    assert_eq!(differentiate(&parse("5!").unwrap(), "x"), num(0.0));
+    assert_eq!(
+        simplify(&differentiate(&parse("n!/(k!(n-k)!)").unwrap(), "x")),
+        num(0.0)
+    );
+    assert_eq!(
+        simplify(&differentiate(&parse("x n!").unwrap(), "x")),
+        simplified("n!")
+    );
+}
+
+#[test]
+fn test_format_constants() {
+    assert_eq!(format_expr_latex(&parse("2 pi + e").unwrap()), r"2 \pi + e");
+    assert_eq!(format_expr_latex(&parse("inf").unwrap()), r"\infty");
+    assert_eq!(format_expr(&parse("tau * phi").unwrap()), "tau * phi");
+}
+
+#[test]
+fn test_parse_relations() {
+    assert_eq!(
+        parse_statement("y = x^2").unwrap(),
+        rel(RelOp::Eq, var("y"), pow(var("x"), num(2.0)))
+    );
+    assert_eq!(
+        parse_statement("x >= 3").unwrap(),
+        rel(RelOp::Ge, var("x"), num(3.0))
+    );
+    assert_eq!(
+        parse_statement("x != 1").unwrap(),
+        rel(RelOp::Ne, var("x"), num(1.0))
+    );
+    assert_eq!(
+        parse_statement("x^2").unwrap(),
+        Statement::Expr(pow(var("x"), num(2.0)))
+    );
+    assert_eq!(
+        parse_input(r"x^2 + y^2 \leq 1").unwrap(),
+        rel(
+            RelOp::Le,
+            add(pow(var("x"), num(2.0)), pow(var("y"), num(2.0))),
+            num(1.0)
+        )
+    );
+}
+
+#[test]
+fn test_parse_input_braced_latex() {
+    // Pasted LaTeX without any command is still LaTeX
+    assert_eq!(
+        parse_input("x^{2}").unwrap(),
+        Statement::Expr(pow(var("x"), num(2.0)))
+    );
+    assert_eq!(
+        parse_input("e^{-x^2}").unwrap(),
+        Statement::Expr(pow(konst(Constant::E), neg(pow(var("x"), num(2.0)))))
+    );
+    assert_eq!(
+        parse_input("10^{-3}").unwrap(),
+        Statement::Expr(pow(num(10.0), neg(num(3.0))))
+    );
+}
+
+#[test]
+fn test_parse_relation_errors() {
+    assert_eq!(
+        parse_statement("x = 1 = 2").unwrap_err().span(),
+        Span::new(6, 7)
+    );
+    assert_eq!(
+        parse_statement("x = ").unwrap_err(),
+        ParseError::UnexpectedEnd {
+            span: Span::point(4),
+            expected: vec![Expected::Expression],
+        }
+    );
+}
+
+#[test]
+fn test_simplify_relation_moves_terms_left() {
+    use cliph::math::algebra::simplify_relation;
+    let Statement::Relation(r) = parse_statement("2x = x + 3").unwrap() else {
+        panic!("expected relation");
+    };
+    let normalized = simplify_relation(&r);
+    assert_eq!(normalized.op, RelOp::Eq);
+    assert_eq!(normalized.rhs, num(0.0));
+    assert_eq!(normalized.lhs, add(var("x"), num(-3.0)));
+}
+
+#[test]
+fn test_explicit_form() {
+    use cliph::math::algebra::explicit_form;
+    let Statement::Relation(r) = parse_statement("sin(x) = y").unwrap() else {
+        panic!("expected relation");
+    };
+    assert_eq!(explicit_form(&r, "y"), Some(&func("sin", var("x"))));
+    let Statement::Relation(r) = parse_statement("y = x y").unwrap() else {
+        panic!("expected relation");
+    };
+    assert_eq!(explicit_form(&r, "y"), None);
+}
+
+#[test]
+fn test_evaluate_relation() {
+    use cliph::math::eval::evaluate_relation;
+    let Statement::Relation(r) = parse_statement("x^2 < 4").unwrap() else {
+        panic!("expected relation");
+    };
+    let mut env = Environment::new();
+    env.set_var("x", 1.5);
+    assert!(evaluate_relation(&r, &env));
+    env.set_var("x", -3.0);
+    assert!(!evaluate_relation(&r, &env));
+}
+
+#[test]
+fn test_implicit_derivative_of_circle() {
+    use cliph::math::diff::implicit_derivative;
+    let Statement::Relation(r) = parse_statement("x^2 + y^2 = 1").unwrap() else {
+        panic!("expected relation");
+    };
+    let d = implicit_derivative(&r, "x", "y");
+    let mut env = Environment::new();
+    env.set_var("x", 0.6);
+    env.set_var("y", 0.8);
+    assert!((cliph::math::eval::evaluate_with_env(&d, &env) + 0.75).abs() < 1e-12);
+}
+
+#[test]
+fn test_format_relations() {
+    use cliph::math::format::{format_statement, format_statement_latex};
+    let stmt = parse_statement("x^2 + y^2 <= 1").unwrap();
+    assert_eq!(format_statement_latex(&stmt), r"x^{2} + y^{2} \leq 1");
+    assert_eq!(format_statement(&stmt), "x ^ 2 + y ^ 2 <= 1");
+    assert_eq!(parse_statement(&format_statement(&stmt)).unwrap(), stmt);
+}
+
+#[test]
+fn test_parse_definitions() {
+    assert_eq!(
+        parse_statement("f(x) = x^2 + 1").unwrap(),
+        Statement::Define(
+            "f".into(),
+            vec!["x".into()],
+            add(pow(var("x"), num(2.0)), num(1.0))
+        )
+    );
+    assert_eq!(
+        parse_statement("a = 3").unwrap(),
+        Statement::Assign("a".into(), num(3.0))
+    );
+    // Plotting variables, self-references and `==` stay equations
+    assert!(matches!(
+        parse_statement("a = a + 1").unwrap(),
+        Statement::Relation(_)
+    ));
+    assert!(matches!(
+        parse_statement("a == 3").unwrap(),
+        Statement::Relation(_)
+    ));
+    assert!(matches!(
+        parse_statement("sin(x) = 1").unwrap(),
+        Statement::Relation(_)
+    ));
+}
+
+#[test]
+fn test_parse_program_offsets_errors() {
+    use cliph::math::parser::parse_program;
+    assert_eq!(parse_program("a = 3; f(a)").unwrap().len(), 2);
+    assert_eq!(
+        parse_program("a = 3; 1 + ").unwrap_err().span(),
+        Span::point(11)
+    );
+    // Only top-level semicolons separate statements, and `\;` is a space
+    let program = parse_program(r"a = 2 \; x; \frac{a}{2}").unwrap();
+    assert_eq!(program.len(), 2);
+    assert_eq!(
+        parse_program(r"y = x \; 2; (1; 2)").unwrap_err().span(),
+        Span::new(14, 15)
+    );
+}
+
+#[test]
+fn test_environment_inlines_definitions() {
+    use cliph::math::parser::parse_program;
+    let program = parse_program("f(x) = x^2 + 1; a = 3; f(a) + f'(x)").unwrap();
+    let (env, rest) = Environment::from_program(&program).unwrap();
+    let [Statement::Expr(e)] = rest.as_slice() else {
+        panic!("expected one expression");
+    };
+    let mut at = env.clone();
+    at.set_var("x", 2.0);
+    assert_eq!(cliph::math::eval::evaluate_with_env(e, &at), 14.0);
+    assert_eq!(
+        env.inline(e),
+        add(
+            add(pow(num(3.0), num(2.0)), num(1.0)),
+            mul(num(2.0), var("x"))
+        )
+    );
+}
+
+#[test]
+fn test_environment_rejects_cycles_and_builtins() {
+    let mut env = Environment::new();
+    env.assign("a", var("b")).unwrap();
+    assert_eq!(
+        env.assign("b", add(var("a"), num(1.0))),
+        Err(EnvError::Cyclic("b".into()))
+    );
+    assert_eq!(
+        env.define("sin", vec!["x".into()], var("x")),
+        Err(EnvError::Builtin("sin".into()))
+    );
+    assert_eq!(
+        env.define("g", vec!["x".into()], func("g", var("x"))),
+        Err(EnvError::Cyclic("g".into()))
+    );
+    assert!(env.var("b").is_none());
+}
+
+#[test]
+fn test_differentiate_through_user_functions() {
+    use cliph::math::diff::differentiate_with_env;
+    // Undefined functions get the chain rule symbolically
+    assert_eq!(
+        differentiate(&func("f", pow(var("x"), num(2.0))), "x"),
+        simplify(&mul(
+            func("f'", pow(var("x"), num(2.0))),
+            mul(num(2.0), var("x"))
+        ))
+    );
+    let mut env = Environment::new();
+    env.define("g", vec!["u".into()], func("sin", var("u")))
+        .unwrap();
+    let d = differentiate_with_env(&func("g", pow(var("x"), num(2.0))), "x", &env);
+    env.set_var("x", 1.5);
+    let expected = 2.0 * 1.5 * (1.5f64 * 1.5).cos();
+    assert!((cliph::math::eval::evaluate_with_env(&d, &env) - expected).abs() < 1e-12);
+}
+
+#[test]
+fn test_parse_splits_juxtaposed_identifiers() {
+    assert_eq!(parse("xy").unwrap(), mul(var("x"), var("y")));
+    assert_eq!(
+        parse("2xsin(x)").unwrap(),
+        mul(mul(num(2.0), var("x")), func("sin", var("x")))
+    );
+    assert_eq!(
+        parse("xy^2").unwrap(),
+        mul(var("x"), pow(var("y"), num(2.0)))
+    );
+    assert_eq!(
+        parse("2pir").unwrap(),
+        mul(mul(num(2.0), konst(Constant::Pi)), var("r"))
+    );
+    // Declared names, including Greek letters, stay whole
+    assert_eq!(parse("theta").unwrap(), var("theta"));
+    assert_eq!(parse("sin(x)").unwrap(), func("sin", var("x")));
+    // A called run with no built-in suffix names a user function
+    assert_eq!(parse("area(r)").unwrap(), func("area", var("r")));
+}
+
+#[test]
+fn test_parse_split_options_and_warnings() {
+    use cliph::math::parser::{
+        parse_program_with, parse_statement_with, ParseOptions, ParseWarning,
+    };
+    let options = ParseOptions::default();
+    let (_, warnings) = parse_statement_with("3xy + 1", &options).unwrap();
+    assert_eq!(
+        warnings,
+        vec![ParseWarning::SplitIdentifier {
+            ident: "xy".into(),
+            parts: vec!["x".into(), "y".into()],
+            span: Span::new(1, 3),
+        }]
+    );
+    let (_, warnings) = parse_statement_with("2xsin(x)", &options).unwrap();
+    assert!(warnings.is_empty());
+
+    let mut options = ParseOptions::default();
+    options.declare("rate");
+    let (stmt, warnings) = parse_statement_with("2rate", &options).unwrap();
+    assert_eq!(stmt, Statement::Expr(mul(num(2.0), var("rate"))));
+    assert!(warnings.is_empty());
+
+    let options = ParseOptions {
+        split_identifiers: false,
+        ..ParseOptions::default()
+    };
+    let (stmt, _) = parse_statement_with("xy", &options).unwrap();
+    assert_eq!(stmt, Statement::Expr(var("xy")));
+
+    // Assigned names are declared for later statements
+    let (program, warnings) =
+        parse_program_with("rate = 2; 3rate", &ParseOptions::default()).unwrap();
+    assert_eq!(program[1], Statement::Expr(mul(num(3.0), var("rate"))));
+    assert!(warnings.is_empty());
+}
+
+#[test]
+fn test_parse_abs_bars() {
+    assert_eq!(
+        parse("|x - 1|").unwrap(),
+        func("abs", sub(var("x"), num(1.0)))
+    );
+    assert_eq!(
+        parse("2|x||y|").unwrap(),
+        mul(mul(num(2.0), func("abs", var("x"))), func("abs", var("y")))
+    );
+    assert_eq!(
+        parse("|x").unwrap_err(),
+        ParseError::UnclosedDelimiter {
+            open: '|',
+            open_span: Span::new(0, 1),
+            span: Span::point(2),
+        }
+    );
+}
+
+#[test]
+fn test_parse_factorial_and_percent() {
+    assert_eq!(parse("5!").unwrap(), fact(num(5.0)));
+    assert_eq!(parse("2^3!").unwrap(), pow(num(2.0), fact(num(3.0))));
+    assert_eq!(parse("-3!").unwrap(), neg(fact(num(3.0))));
+    assert_eq!(parse("15%").unwrap(), percent(num(15.0)));
+    // `!=` is still a relation
+    assert!(matches!(
+        parse_statement("x != 1").unwrap(),
+        Statement::Relation(Relation { op: RelOp::Ne, .. })
+    ));
+}
+
+#[test]
+fn test_factorial_evaluation_and_simplification() {
+    assert_eq!(
+        evaluate(
+            &parse("n!/(k!(n-k)!)").unwrap().substitute(
+                &[("n".to_string(), num(5.0)), ("k".to_string(), num(2.0))]
+                    .into_iter()
+                    .collect()
+            )
+        ),
+        10.0
+    );
+    assert_eq!(simplify(&parse("5!").unwrap()), num(120.0));
+    assert_eq!(simplify(&parse("15% * 200").unwrap()), num(30.0));
+    // gamma(1.5) = sqrt(pi) / 2
+    let half = evaluate(&parse("0.5!").unwrap());
+    assert!((half - std::f64::consts::PI.sqrt() / 2.0).abs() < 1e-12);
+    assert!(evaluate(&parse("(-2)!").unwrap()).is_nan());
+}
+
+#[test]
+fn test_format_postfix_operators() {
+    assert_eq!(
+        format_expr_latex(&parse("(x + 1)!").unwrap()),
+        r"\left(x + 1\right)!"
+    );
+    assert_eq!(format_expr_latex(&parse("15%").unwrap()), r"15\%");
+    assert_eq!(format_expr(&parse("(n - k)!").unwrap()), "(n - k)!");
+    for input in ["(x + 1)!", "2^3!", "15%", "|x - 1|"] {
+        let expr = parse(input).unwrap();
+        assert_eq!(parse_latex(&format_expr_latex(&expr)).unwrap(), expr);
+    }
+}
+
+#[test]
+fn test_parse_unicode_operators() {
+    assert_eq!(parse("3×4").unwrap(), mul(num(3.0), num(4.0)));
+    assert_eq!(parse("6÷2").unwrap(), div(num(6.0), num(2.0)));
+    assert_eq!(parse("x − 1").unwrap(), sub(var("x"), num(1.0)));
+    assert_eq!(
+        parse_statement("x ≤ 1").unwrap(),
+        parse_statement("x <= 1").unwrap()
+    );
+}
+
+#[test]
+fn test_parse_unicode_superscripts_and_radicals() {
+    assert_eq!(parse("x²").unwrap(), pow(var("x"), num(2.0)));
+    assert_eq!(parse("x⁻¹").unwrap(), pow(var("x"), neg(num(1.0))));
+    assert_eq!(
+        parse("x²y").unwrap(),
+        mul(pow(var("x"), num(2.0)), var("y"))
+    );
+    assert_eq!(
+        parse("√(x+1)").unwrap(),
+        func("sqrt", add(var("x"), num(1.0)))
+    );
+    assert_eq!(
+        parse("2√x²").unwrap(),
+        mul(num(2.0), func("sqrt", pow(var("x"), num(2.0))))
+    );
+    assert_eq!(
+        parse("∛8").unwrap(),
+        funcn("root", vec![num(8.0), num(3.0)])
+    );
+    // All spellings of the square root are the same function
+    assert_eq!(parse_latex(r"\sqrt[2]{x}").unwrap(), func("sqrt", var("x")));
+    assert_eq!(simplify(&parse("sqrt(x) - √x").unwrap()), num(0.0));
+    assert_eq!(simplify(&parse("sqrt(x) - root(x, 2)").unwrap()), num(0.0));
+    assert_eq!(
+        differentiate(&parse("√x").unwrap(), "x"),
+        differentiate(&parse("sqrt(x)").unwrap(), "x")
+    );
+    assert_eq!(
+        differentiate(&parse("root(x, 2)").unwrap(), "x"),
+        differentiate(&parse("sqrt(x)").unwrap(), "x")
+    );
+}
+
+#[test]
+fn test_parse_unicode_greek() {
+    assert_eq!(
+        parse("2πr").unwrap(),
+        mul(mul(num(2.0), konst(Constant::Pi)), var("r"))
+    );
+    assert_eq!(parse("θ").unwrap(), var("theta"));
+    assert_eq!(parse("2θ").unwrap(), mul(num(2.0), var("theta")));
+    assert_eq!(parse("∞").unwrap(), konst(Constant::Infinity));
+}
+
+#[test]
+fn test_unicode_error_spans_point_into_original() {
+    // `π` expands to two bytes and `×` to one, yet the span is of the `#`
+    let err = parse("π × #").unwrap_err();
+    assert_eq!(err.span(), Span::new(6, 7));
+    assert!(err.render("π × #").ends_with("    ^ expected expression"));
+}
+
+#[test]
+fn test_parse_function_application_without_parentheses() {
+    assert_eq!(parse("sin x").unwrap(), func("sin", var("x")));
+    assert_eq!(
+        parse("cos 2x").unwrap(),
+        func("cos", mul(num(2.0), var("x")))
+    );
+    assert_eq!(
+        parse("sin x + 1").unwrap(),
+        add(func("sin", var("x")), num(1.0))
+    );
+    assert_eq!(
+        parse("2sin x cos x").unwrap(),
+        mul(mul(num(2.0), func("sin", var("x"))), func("cos", var("x")))
+    );
+    assert_eq!(
+        parse("sin x^2").unwrap(),
+        func("sin", pow(var("x"), num(2.0)))
+    );
+    assert_eq!(parse("sinx").unwrap(), func("sin", var("x")));
+    assert_eq!(parse("ln x").unwrap(), func("ln", var("x")));
+    assert_eq!(parse("sqrt x").unwrap(), func("sqrt", var("x")));
+    assert!(matches!(
+        parse("atan2 x").unwrap_err(),
+        ParseError::ArityMismatch { found: 1, .. }
+    ));
+}
+
+#[test]
+fn test_parse_function_powers_and_bases() {
+    let sin_sq = pow(func("sin", var("x")), num(2.0));
+    assert_eq!(parse("sin^2 x").unwrap(), sin_sq);
+    assert_eq!(parse("sin^2(x)").unwrap(), sin_sq);
+    assert_eq!(parse("sin^-1 x").unwrap(), func("asin", var("x")));
+    assert_eq!(parse("tan^-1(x)").unwrap(), func("atan", var("x")));
+    assert_eq!(
+        parse("log_2 8").unwrap(),
+        funcn("log", vec![num(2.0), num(8.0)])
+    );
+    assert_eq!(
+        parse("log_b(x)").unwrap(),
+        funcn("log", vec![var("b"), var("x")])
+    );
+    assert_eq!(parse_latex(r"\sin^{-1} x").unwrap(), func("asin", var("x")));
+}
+
+#[test]
+fn test_new_real_functions() {
+    assert_eq!(evaluate(&parse("log_2 8").unwrap()), 3.0);
+    assert_eq!(evaluate(&parse("sqrt 16").unwrap()), 4.0);
+    assert_eq!(simplify(&parse("ln e").unwrap()), num(1.0));
+    let asin_half = evaluate(&parse("sin^-1 0.5").unwrap());
+    assert!((asin_half - std::f64::consts::FRAC_PI_6).abs() < 1e-12);
+    // Out of the real domain stays symbolic
+    let expr = parse("asin(2)").unwrap();
+    assert_eq!(simplify(&expr), func("asin", num(2.0)));
+
+    let at = |expr: &Expr, x: f64| {
+        let mut env = Environment::new();
+        env.set_var("x", x);
+        cliph::math::eval::evaluate_with_env(expr, &env)
+    };
+    let d = differentiate(&parse("sqrt x").unwrap(), "x");
+    assert!((at(&d, 4.0) - 0.25).abs() < 1e-12);
+    let d = differentiate(&parse("atan x").unwrap(), "x");
+    assert!((at(&d, 1.0) - 0.5).abs() < 1e-12);
+    let d = differentiate(&parse("acos x").unwrap(), "x");
+    assert!((at(&d, 0.0) + 1.0).abs() < 1e-12);
+
+    assert_eq!(format_expr_latex(&parse("sqrt x").unwrap()), r"\sqrt{x}");
+    assert_eq!(
+        format_expr_latex(&parse("sin^-1 x").unwrap()),
+        r"\arcsin\left(x\right)"
+    );
+    assert_eq!(
+        format_expr_latex(&parse("ln x").unwrap()),
+        r"\ln\left(x\right)"
+    );
+}
+
+#[test]
+fn test_parse_subscripted_variables() {
+    assert_eq!(parse("x_1").unwrap(), var("x_1"));
+    assert_eq!(parse("v_{max}").unwrap(), var("v_max"));
+    assert_eq!(
+        parse("2x_1y_2").unwrap(),
+        mul(mul(num(2.0), var("x_1")), var("y_2"))
+    );
+    assert_eq!(parse_latex("x_{1}").unwrap(), var("x_1"));
+    assert_eq!(parse_latex("v_{max}").unwrap(), var("v_max"));
+    assert_eq!(parse_latex("x_12").unwrap(), mul(var("x_1"), num(2.0)));
+    assert_eq!(parse_latex(r"\theta_0").unwrap(), var("theta_0"));
+    assert!(matches!(
+        parse("v_{max").unwrap_err(),
+        ParseError::UnclosedDelimiter { open: '{', .. }
+    ));
+}
+
+#[test]
+fn test_subscript_symbols_and_formatting() {
+    use cliph::math::ast::Symbol;
+    assert_eq!(
+        Symbol::parse("v_max"),
+        Symbol {
+            base: "v",
+            subscript: Some("max")
+        }
+    );
+    assert_eq!(Symbol::parse("x").subscript, None);
+    assert_eq!(Symbol::join("x", Some("1")), "x_1");
+
+    let expr = parse("v_max t + x_0").unwrap();
+    assert_eq!(format_expr_latex(&expr), "v_{max} t + x_{0}");
+    assert_eq!(parse_latex(&format_expr_latex(&expr)).unwrap(), expr);
+    assert_eq!(format_expr(&expr), "v_max * t + x_0");
+    assert_eq!(format_expr_latex(&var("theta_1")), r"\theta_{1}");
+}
+
+#[test]
+fn test_parse_lists_and_ranges() {
+    assert_eq!(
+        parse("[1, 2, 3]").unwrap(),
+        List(vec![num(1.0), num(2.0), num(3.0)])
+    );
+    assert_eq!(parse("[]").unwrap(), List(vec![]));
+    assert_eq!(
+        parse("1..10").unwrap(),
+        funcn("range", vec![num(1.0), num(10.0)])
+    );
+    assert_eq!(
+        parse("1..n+1").unwrap(),
+        funcn("range", vec![num(1.0), add(var("n"), num(1.0))])
+    );
+    assert_eq!(parse("1.5").unwrap(), num(1.5));
+    assert_eq!(
+        parse("2[x, 1]").unwrap(),
+        mul(num(2.0), List(vec![var("x"), num(1.0)]))
+    );
+    assert!(matches!(
+        parse("[1, 2").unwrap_err(),
+        ParseError::UnclosedDelimiter { open: '[', .. }
+    ));
+
+    assert_eq!(format_expr(&parse("[x, x^2]").unwrap()), "[x, x ^ 2]");
+    assert_eq!(format_expr(&parse("1..n+1").unwrap()), "1..n + 1");
+    assert_eq!(
+        format_expr_latex(&parse("[x, 1]").unwrap()),
+        r"\left[x, 1\right]"
+    );
+}
+
+#[test]
+fn test_evaluate_lists() {
+    use cliph::math::eval::{evaluate_value, Value};
+    let value = |s: &str| evaluate_value(&parse(s).unwrap(), &Environment::new());
+    assert_eq!(value("[1, 2, 3] * 2"), Value::List(vec![2.0, 4.0, 6.0]));
+    assert_eq!(
+        value("[1, 2] + [10, 20, 30]"),
+        Value::List(vec![11.0, 22.0])
+    );
+    assert_eq!(value("-[1, 2]"), Value::List(vec![-1.0, -2.0]));
+    assert_eq!(value("abs([-1, 2])"), Value::List(vec![1.0, 2.0]));
+    assert_eq!(value("1..4"), Value::List(vec![1.0, 2.0, 3.0, 4.0]));
+    assert_eq!(value("[0, 2..3]"), Value::List(vec![0.0, 2.0, 3.0]));
+    assert_eq!(value("sum(1..10)"), Value::Number(55.0));
+    assert_eq!(value("len([1, 2, 3])"), Value::Number(3.0));
+    assert_eq!(value("dot([1, 2], [3, 4])"), Value::Number(11.0));
+    assert_eq!(value("max([1, 5, 2])"), Value::Number(5.0));
+    assert!(evaluate(&parse("[1, 2]").unwrap()).is_nan());
+
+    let mut env = Environment::new();
+    env.define("f", vec!["t".into()], parse("t^2").unwrap())
+        .unwrap();
+    env.assign("v", parse("[1, 2, 3]").unwrap()).unwrap();
+    assert_eq!(
+        evaluate_value(&parse("f(v)").unwrap(), &env),
+        Value::List(vec![1.0, 4.0, 9.0])
+    );
+    assert_eq!(
+        evaluate_value(&parse("sum(v)").unwrap(), &env),
+        Value::Number(6.0)
+    );
+}
+
+#[test]
+fn test_simplify_and_differentiate_lists() {
+    assert_eq!(
+        simplify(&parse("[1, 2] * 3").unwrap()),
+        List(vec![num(3.0), num(6.0)])
+    );
+    assert_eq!(
+        simplify(&parse("1..3").unwrap()),
+        List(vec![num(1.0), num(2.0), num(3.0)])
+    );
+    assert_eq!(simplify(&parse("sum(1..4)").unwrap()), num(10.0));
+    assert_eq!(simplify(&parse("len([x, y])").unwrap()), num(2.0));
+    assert_eq!(
+        simplify(&parse("dot([x, 1], [2, 3])").unwrap()),
+        simplify(&parse("2x + 3").unwrap())
+    );
+    assert_eq!(
+        simplify(&parse("sin([0, x])").unwrap()),
+        List(vec![num(0.0), func("sin", var("x"))])
+    );
+
+    assert_eq!(
+        differentiate(&parse("[x, x^2, 3]").unwrap(), "x"),
+        List(vec![num(1.0), simplify(&parse("2x").unwrap()), num(0.0)])
+    );
+    assert_eq!(
+        differentiate(&parse("sum([x, x^2])").unwrap(), "x"),
+        simplify(&parse("1 + 2x").unwrap())
+    );
+}
+
+#[test]
+fn test_parse_piecewise() {
+    assert_eq!(
+        parse("x^2 if x < 0 else 1 if x == 0 else sin(x)").unwrap(),
+        piecewise()
+    );
+    assert_eq!(
+        parse_latex(
+            r"\begin{cases} x^2 & x < 0 \\ 1, & \text{if } x = 0 \\ \sin x & \text{otherwise} \end{cases}"
+        )
+        .unwrap(),
+        piecewise()
+    );
+    assert_eq!(
+        parse("1 if x > 0").unwrap(),
+        Piecewise(
+            vec![(
+                num(1.0),
+                Relation {
+                    op: RelOp::Gt,
+                    lhs: var("x"),
+                    rhs: num(0.0)
+                }
+            )],
+            None
+        )
+    );
+    assert!(matches!(
+        parse_statement("f(x) = x if x > 0 else -x").unwrap(),
+        Statement::Define(_, _, Piecewise(..))
+    ));
+    // Words merely starting with `if` are still identifiers
+    assert_eq!(parse("2 iff").unwrap(), parse("2 i f f").unwrap());
+    assert!(matches!(
+        parse("1 if x").unwrap_err(),
+        ParseError::UnexpectedEnd { .. }
+    ));
+}
+
+#[test]
+fn test_evaluate_and_simplify_piecewise() {
+    let at = |expr: &Expr, x: f64| {
+        let mut env = Environment::new();
+        env.set_var("x", x);
+        cliph::math::eval::evaluate_with_env(expr, &env)
+    };
+    let f = piecewise();
+    assert_eq!(at(&f, -3.0), 9.0);
+    assert_eq!(at(&f, 0.0), 1.0);
+    assert_eq!(at(&f, 1.0), 1f64.sin());
+    assert!(at(&parse("1 if x > 0").unwrap(), -1.0).is_nan());
+
+    let mut env = Environment::new();
+    env.define("g", vec!["t".into()], parse("t if t > 0 else -t").unwrap())
+        .unwrap();
+    assert_eq!(
+        cliph::math::eval::evaluate_with_env(&parse("g(-2) + g(3)").unwrap(), &env),
+        5.0
+    );
+
+    assert_eq!(simplify(&parse("x if 1 < 2 else y").unwrap()), var("x"));
+    assert_eq!(simplify(&parse("x if pi < 3 else y").unwrap()), var("y"));
+    assert_eq!(
+        simplify(&parse("x if x > 0 else y if 2 > 1 else z").unwrap()),
+        parse("x if x > 0 else y").unwrap()
+    );
+
+    assert_eq!(
+        differentiate(&parse("x^2 if x < 0 else 3x").unwrap(), "x"),
+        parse("2x if x < 0 else 3").unwrap()
+    );
+}
+
+#[test]
+fn test_format_piecewise() {
+    assert_eq!(
+        format_expr(&piecewise()),
+        "x ^ 2 if x < 0 else 1 if x = 0 else sin(x)"
+    );
+    assert_eq!(parse(&format_expr(&piecewise())).unwrap(), piecewise());
+    assert_eq!(
+        format_expr_latex(&piecewise()),
+        r"\begin{cases} x^{2} & x < 0 \\ 1 & x = 0 \\ \sin\left(x\right) & \text{otherwise} \end{cases}"
+    );
+    assert_eq!(
+        parse_latex(&format_expr_latex(&piecewise())).unwrap(),
+        piecewise()
+    );
+    assert_eq!(
+        format_expr(&parse("2 (1 if x > 0 else 0)").unwrap()),
+        "2 * (1 if x > 0 else 0)"
+    );
+}
+
+#[test]
+fn test_parse_recovering_partial_input() {
+    use cliph::math::ast::Hole;
+    use cliph::math::parser::parse_recovering;
+
+    let (expr, errors) = parse_recovering("x^");
+    assert_eq!(expr, pow(var("x"), Hole(Hole::Missing)));
+    assert!(matches!(errors[..], [ParseError::UnexpectedEnd { .. }]));
+
+    // Unclosed parentheses are closed at the end
+    let (expr, errors) = parse_recovering("sin(x + 1");
+    assert_eq!(expr, func("sin", add(var("x"), num(1.0))));
+    assert!(matches!(
+        errors[..],
+        [ParseError::UnclosedDelimiter { open: '(', .. }]
+    ));
+    let (expr, _) = parse_recovering("sin(");
+    assert_eq!(expr, func("sin", Hole(Hole::Missing)));
+
+    // A stray character becomes an error hole and parsing carries on
+    let (expr, errors) = parse_recovering("x + # y");
+    assert_eq!(add(var("x"), mul(Hole(Hole::Error), var("y"))), expr);
+    assert_eq!(errors[0].span(), Span::new(4, 5));
+
+    let (expr, errors) = parse_recovering("2 * 1.2.3");
+    assert_eq!(expr, mul(num(2.0), Hole(Hole::Error)));
+    assert_eq!(errors.len(), 1);
+
+    let (expr, errors) = parse_recovering("x^2)");
+    assert_eq!(expr, pow(var("x"), num(2.0)));
+    assert_eq!(errors.len(), 1);
+
+    let (expr, errors) = parse_recovering("x^2 + 1");
+    assert_eq!(expr, parse("x^2 + 1").unwrap());
+    assert!(errors.is_empty());
+
+    assert!(cliph::math::eval::evaluate(&parse_recovering("1 +").0).is_nan());
+}
+
+#[test]
+fn test_parse_program_recovering() {
+    use cliph::math::ast::Hole;
+    use cliph::math::parser::{parse_program_recovering, ParseOptions};
+
+    let recovered = parse_program_recovering("a = 2; a x^", &ParseOptions::default());
+    assert_eq!(recovered.program.len(), 2);
+    assert_eq!(
+        recovered.program[0],
+        Statement::Assign("a".into(), num(2.0))
+    );
+    assert_eq!(
+        recovered.program[1],
+        Statement::Expr(mul(var("a"), pow(var("x"), Hole(Hole::Missing))))
+    );
+    // Spans point into the whole program
+    assert_eq!(recovered.errors[0].span(), Span::point(11));
+
+    let recovered = parse_program_recovering(r"\frac{1}{", &ParseOptions::default());
+    assert_eq!(recovered.program, vec![Statement::Expr(Hole(Hole::Error))]);
+    assert_eq!(recovered.errors.len(), 1);
+
+    let recovered = parse_program_recovering("y = x^2; x + 1", &ParseOptions::default());
+    assert!(recovered.errors.is_empty());
+
+    assert_eq!(
+        format_expr_latex(&add(var("x"), Hole(Hole::Missing))),
+        r"x + \square"
+    );
+    assert_eq!(format_expr(&add(var("x"), Hole(Hole::Error))), "x + ?");
+}
+
+#[test]
+fn test_parse_derivatives_and_integrals() {
+    assert_eq!(
+        parse("d/dx (x^2 sin x)").unwrap(),
+        derivative(parse("x^2 sin x").unwrap(), "x", 1)
+    );
+    assert_eq!(
+        parse("d/dx x^2 + 1").unwrap(),
+        add(derivative(parse("x^2").unwrap(), "x", 1), num(1.0))
+    );
+    assert_eq!(
+        parse("d^2/dt^2 t^3").unwrap(),
+        derivative(parse("t^3").unwrap(), "t", 2)
+    );
+    assert_eq!(
+        parse("diff(x^3, x, 2)").unwrap(),
+        derivative(parse("x^3").unwrap(), "x", 2)
+    );
+    assert_eq!(
+        parse_latex("\\frac{d}{dx} x^2 \\sin x").unwrap(),
+        derivative(parse("x^2 sin x").unwrap(), "x", 1)
+    );
+    assert_eq!(
+        parse_latex("\\frac{d^2}{d\\theta^2} \\cos \\theta").unwrap(),
+        derivative(func("cos", var("theta")), "theta", 2)
+    );
+    // Not Leibniz notation
+    assert_eq!(parse("d/2").unwrap(), div(var("d"), num(2.0)));
+
+    let indefinite = integral(parse("x^2").unwrap(), "x", None);
+    assert_eq!(parse("int(x^2, x)").unwrap(), indefinite);
+    assert_eq!(parse_latex("\\int x^2 \\, dx").unwrap(), indefinite);
+    let definite = integral(parse("x^2").unwrap(), "x", Some((num(0.0), num(1.0))));
+    assert_eq!(parse("int(x^2, x, 0, 1)").unwrap(), definite);
+    assert_eq!(parse_latex("\\int_0^1 x^2 dx").unwrap(), definite);
+    assert_eq!(parse_latex("\\int_{0}^{1} x^{2} \\, dx").unwrap(), definite);
+
+    assert!(matches!(
+        parse("diff(x^2, 2)"),
+        Err(ParseError::InvalidArgument { .. })
+    ));
+    assert!(matches!(
+        parse("diff(x^2, x, 1.5)"),
+        Err(ParseError::InvalidArgument { .. })
+    ));
+    assert!(matches!(
+        parse("int(x, x, 0)"),
+        Err(ParseError::InvalidArgument { .. })
+    ));
+}
+
+#[test]
+fn test_evaluate_derivatives_and_integrals() {
+    let at = |input: &str, x: f64| {
+        let mut env = Environment::new();
+        env.set_var("x", x);
+        cliph::math::eval::evaluate_with_env(&parse(input).unwrap(), &env)
+    };
+    assert_eq!(at("d/dx x^3", 2.0), 12.0);
+    assert_eq!(at("diff(x^3, x, 2)", 2.0), 12.0);
+    assert!((at("int(x^2, x, 0, 3)", 0.0) - 9.0).abs() < 1e-9);
+    assert!((at("int(x^2, x)", 3.0) - 9.0).abs() < 1e-9);
+    // No antiderivative by rule, so numerically
+    assert!((at("int(exp(-x^2), x, 0, 1)", 0.0) - 0.746_824_132_812_427).abs() < 1e-9);
+    assert!((at("int(t x, t, 0, 2)", 3.0) - 6.0).abs() < 1e-9);
+
+    let mut env = Environment::new();
+    env.assign("x", num(3.0)).unwrap();
+    let inlined = env.inline(&parse("d/dx x^2").unwrap());
+    assert_eq!(simplify(&inlined), num(6.0));
+
+    assert_eq!(
+        simplify(&parse("d/dx (x^2 sin x)").unwrap()),
+        differentiate(&parse("x^2 sin x").unwrap(), "x")
+    );
+    assert_eq!(simplify(&parse("int(3, x, 0, 2)").unwrap()), num(6.0));
+    assert_eq!(simplify(&parse("int(2x, x, 0, 3)").unwrap()), num(9.0));
+    // Definite integrals by antiderivative stay exact
+    assert_eq!(
+        simplify(&parse("int(x^2, x, 0, 1)").unwrap()),
+        Number(cliph::math::ast::Real::ratio(1, 3))
+    );
+    assert_eq!(
+        simplify(&parse_latex("\\int_0^1 x^2 dx").unwrap()),
+        Number(cliph::math::ast::Real::ratio(1, 3))
+    );
+    assert_eq!(
+        simplify(&parse("int(cos(2x), x)").unwrap()),
+        simplify(&parse("sin(2x) / 2").unwrap())
+    );
+    // Fixing the variable of an indefinite integral fixes the others too
+    let bindings =
+        std::collections::HashMap::from([("x".to_string(), num(3.0)), ("a".to_string(), num(2.0))]);
+    let fixed = parse("int(a x, x)").unwrap().substitute(&bindings);
+    assert!(!fixed.contains_var("a"));
+    assert_eq!(simplify(&fixed), num(9.0));
+    // Left as is when there is neither a rule nor a number to compute
+    let unknown = parse("int(exp(-a x^2), x)").unwrap();
+    assert!(matches!(simplify(&unknown), Integral(..)));
+
+    // Fundamental theorem of calculus and the Leibniz rule
+    assert_eq!(
+        differentiate(&unknown, "x"),
+        simplify(&parse("exp(-a x^2)").unwrap())
+    );
+    let leibniz = differentiate(&parse("int(exp(t^2), t, 0, x^2)").unwrap(), "x");
+    assert!(!leibniz.contains_var("t"));
+    let expected = 2.0 * 0.7 * 0.7f64.powi(4).exp();
+    assert!((at(&format_expr(&leibniz), 0.7) - expected).abs() < 1e-9);
+}
+
+#[test]
+fn test_format_derivatives_and_integrals() {
+    let derivative = parse("diff(x^3, x, 2)").unwrap();
+    assert_eq!(format_expr(&derivative), "diff(x ^ 3, x, 2)");
+    assert_eq!(
+        format_expr_latex(&derivative),
+        "\\frac{d^{2}}{dx^{2}}\\left(x^{3}\\right)"
+    );
+    assert_eq!(
+        format_expr_latex(&parse("d/dx sin x").unwrap()),
+        "\\frac{d}{dx}\\left(\\sin\\left(x\\right)\\right)"
+    );
+    let integral = parse("int(x^2, x, 0, 1)").unwrap();
+    assert_eq!(format_expr(&integral), "int(x ^ 2, x, 0, 1)");
+    assert_eq!(format_expr_latex(&integral), "\\int_{0}^{1} x^{2} \\, dx");
+    assert_eq!(
+        parse_latex(&format_expr_latex(&integral)).unwrap(),
+        integral
+    );
+    assert_eq!(parse(&format_expr(&derivative)).unwrap(), derivative);
+}
+
+#[test]
+fn test_exact_rational_arithmetic() {
+    use cliph::math::ast::Real;
+
+    assert_eq!(simplified("1/3 + 1/3 + 1/3"), num(1.0));
+    assert_eq!(simplified("0.1 + 0.2"), num(0.3));
+    assert_eq!(simplified("1/3"), Number(Real::ratio(1, 3)));
+    assert_eq!(simplified("2/6 - 1/2"), Number(Real::ratio(-1, 6)));
+    assert_eq!(simplified("(2/3)^2"), Number(Real::ratio(4, 9)));
+    assert_eq!(simplified("8^(2/3)"), num(4.0));
+    assert_eq!(simplified("sqrt(9/4)"), num(1.5));
+    assert_eq!(simplified("abs(-1/3)"), Number(Real::ratio(1, 3)));
+    assert_eq!(simplified("hypot(3, 4)"), num(5.0));
+    assert_eq!(evaluate(&simplified("1/3")), 1.0 / 3.0);
+}
+
+#[test]
+fn test_irrational_values_stay_symbolic() {
+    assert_eq!(simplified("2^(1/2)"), pow(num(2.0), num(0.5)));
+    // For evaluate to compute, rather than rounded to floats
+    assert_eq!(simplified("exp(2)"), func("exp", num(2.0)));
+    assert_eq!(simplified("sqrt(2)"), func("sqrt", num(2.0)));
+    assert_eq!(simplified("sqrt(8)"), func("sqrt", num(8.0)));
+    assert_eq!(simplified("ln(2)"), func("ln", num(2.0)));
+    let derivative = differentiate(&parse("log(2, x)").unwrap(), "x");
+    assert!(!format_expr(&derivative).contains('.'));
+}
+
+#[test]
+fn test_big_integers() {
+    assert_eq!(
+        format_expr(&simplified("2^100 + 1")),
+        "1267650600228229401496703205377"
+    );
+    assert_eq!(
+        parse("0x1_0000_0000_0000_0000").unwrap(),
+        simplified("2^64")
+    );
+    assert_eq!(simplified("2^1000"), simplified("2^500 * 2^500"));
+    // Powers too large to hold exactly fall back to floats
+    assert_eq!(
+        evaluate(&simplified("((10^1000)^1000)^1000")),
+        f64::INFINITY
+    );
+}
+
+#[test]
+fn test_format_rationals() {
+    assert_eq!(format_expr(&simplified("1/3")), "1/3");
+    assert_eq!(format_expr(&simplified("1/4")), "0.25");
+    assert_eq!(latex("-2/3"), "-\\frac{2}{3}");
+    assert_eq!(latex("x^(1/3)"), "x^{\\frac{1}{3}}");
+    assert_eq!(format_expr(&simplified("(1/3)^x")), "(1/3) ^ x");
+}
+
+#[test]
+fn test_differentiate_rational_powers() {
+    assert_eq!(
+        differentiate(&parse("x^(1/3)").unwrap(), "x"),
+        simplified("1/3 x^(-2/3)")
+    );
+}
+
+#[test]
+fn test_parse_imaginary_unit() {
+    assert_eq!(parse("i").unwrap(), konst(Constant::I));
+    assert_eq!(parse_latex("2i").unwrap(), parse("2 i").unwrap());
+}
+
+#[test]
+fn test_evaluate_complex() {
+    close("sqrt(-1)", Complex64::i());
+    close("sqrt(-4)", Complex64::new(0.0, 2.0));
+    close("i^2", Complex64::new(-1.0, 0.0));
+    close("(1 + i)^2", Complex64::new(0.0, 2.0));
+    close("(3 + 4i) / (1 - 2i)", Complex64::new(-1.0, 2.0));
+    close("ln(-1)", Complex64::new(0.0, std::f64::consts::PI));
+    close("exp(i pi)", Complex64::new(-1.0, 0.0));
+    close("sin(i)", Complex64::new(0.0, 1f64.sinh()));
+    close("abs(3 + 4i)", Complex64::new(5.0, 0.0));
+    close("int(x i, x, 0, 2)", Complex64::new(0.0, 2.0));
+}
+
+#[test]
+fn test_evaluate_real_part_only() {
+    assert_eq!(evaluate(&parse("exp(i pi)").unwrap()), -1.0);
+    assert_eq!(evaluate(&parse("i * i").unwrap()), -1.0);
+    assert!(evaluate(&parse("sqrt(-1)").unwrap()).is_nan());
+    assert_eq!(evaluate(&parse("root(-8, 3)").unwrap()), -2.0);
+}
+
+#[test]
+fn test_simplify_powers_of_i() {
+    assert_eq!(simplified("i^2"), num(-1.0));
+    assert_eq!(simplified("i^7"), neg(konst(Constant::I)));
+    assert_eq!(simplified("2 i * 3 i"), num(-6.0));
+    assert_eq!(simplified("1 / i"), simplified("-i"));
+    assert_eq!(simplified("exp(i pi)"), num(-1.0));
+    assert_eq!(simplified("exp(-i pi / 2)"), simplified("-i"));
+}
+
+#[test]
+fn test_simplify_square_roots_of_negatives() {
+    assert_eq!(simplified("sqrt(-9)"), simplified("3i"));
+    assert_eq!(simplified("sqrt(-1)"), konst(Constant::I));
+    assert_eq!(simplified("√(-1) * √(-1)"), num(-1.0));
+    assert_eq!(format_expr(&simplified("sqrt(-2)")), "i * sqrt(2)");
+    assert_eq!(simplified("(-1)^(1/2)"), konst(Constant::I));
+    assert_eq!(simplified("(-4)^(1/2)"), simplified("2i"));
+    assert_eq!(simplified("(-4)^(3/2)"), simplified("-8i"));
+    assert_eq!(simplified("(-2)^(1/2)"), simplified("sqrt(-2)"));
+}
+
+#[test]
+fn test_evaluate_complex_relations() {
+    let holds = |input: &str| match parse_statement(input).unwrap() {
+        Statement::Relation(rel) => evaluate_relation(&rel, &Environment::new()),
+        other => panic!("{:?} is not a relation", other),
+    };
+    assert!(holds("i = i"));
+    assert!(holds("2i != i"));
+    assert!(!holds("2i = i"));
+    assert!(holds("exp(i pi) = -1"));
+    assert!(!holds("i < 2"));
+}
+
+#[test]
+fn test_simplify_complex_arithmetic() {
+    assert_eq!(simplified("(1 + i)^2"), simplified("2i"));
+    assert_eq!(simplified("(2 + 3i) / (1 - i)"), simplified("-1/2 + 5/2 i"));
+    assert_eq!(simplified("(1 + i) (1 - i)"), num(2.0));
+    assert_eq!(simplified("i * (2 + i)"), simplified("-1 + 2i"));
+}
+
+#[test]
+fn test_format_complex() {
+    assert_eq!(latex("3 + 2i"), "3 + 2i");
+    assert_eq!(latex("3 - 2i"), "3 - 2i");
+    assert_eq!(latex("1/3 - 2i"), "\\frac{1}{3} - 2i");
+    assert_eq!(latex("x i"), "x i");
+}
+
+#[test]
+fn test_registered_function_parses_and_evaluates() {
+    register_sech();
+    assert_eq!(
+        cliph::math::functions::lookup("sech").unwrap().arity,
+        Arity::Exact(1)
+    );
+    // Parsed as a known function, without parentheses too
+    assert_eq!(parse("sech x").unwrap(), func("sech", var("x")));
+    assert!(matches!(
+        parse("sech(1, 2)").unwrap_err(),
+        ParseError::ArityMismatch { .. }
+    ));
+    assert_eq!(evaluate(&parse("sech(0.5)").unwrap()), 1.0 / 0.5f64.cosh());
+    assert_eq!(
+        Environment::new().define("sech", vec!["x".into()], var("x")),
+        Err(EnvError::Builtin("sech".into()))
+    );
+}
+
+#[test]
+fn test_registered_function_simplifies_and_differentiates() {
+    register_sech();
+    assert_eq!(simplified("sech(0)"), num(1.0));
+    assert_eq!(simplified("sech(1)"), func("sech", num(1.0)));
+    assert_eq!(
+        differentiate(&parse("sech(2x)").unwrap(), "x"),
+        simplified("-(sech(2x) tanh(2x)) * 2")
+    );
+    assert_eq!(
+        format_expr_latex(&parse("sech(x)").unwrap()),
+        "\\operatorname{sech}\\left(x\\right)"
+    );
+}
+
+#[test]
+fn test_registered_inverse_and_layout() {
+    use cliph::math::functions::{register, FunctionInfo};
+
+    register(
+        FunctionInfo::new("cube", Arity::Exact(1))
+            .real(|xs| xs[0].powi(3))
+            .inverse("cuberoot")
+            .format(|args| match args {
+                [a] => Some(format!("({})^3", format_expr(a))),
+                _ => None,
+            }),
+    );
+    register(FunctionInfo::new("cuberoot", Arity::Exact(1)).real(|xs| xs[0].cbrt()));
+    assert_eq!(parse("cube^-1(8)").unwrap(), func("cuberoot", num(8.0)));
+    assert_eq!(
+        parse_latex("\\operatorname{cube}^{-1}\\left(8\\right)").unwrap(),
+        func("cuberoot", num(8.0))
+    );
+    assert_eq!(format_expr(&parse("cube(x + 1)").unwrap()), "(x + 1)^3");
+}
+
+#[test]
+fn test_format_unknown_functions() {
+    // Word names are operators, letters are named like variables
+    assert_eq!(
+        format_expr_latex(&parse("sinh(x)").unwrap()),
+        "\\operatorname{sinh}\\left(x\\right)"
+    );
+    assert_eq!(
+        format_expr_latex(&parse("f(x)").unwrap()),
+        "f\\left(x\\right)"
+    );
+}
+
+#[test]
+fn test_builtin_function_info() {
+    use cliph::math::functions::lookup;
+
+    let sqrt = lookup("sqrt").unwrap();
+    assert!(sqrt.defined_at(&[4.0]) && !sqrt.defined_at(&[-4.0]));
+    assert!(lookup("max").unwrap().takes_lists);
+    assert_eq!(lookup("sin").unwrap().inverse.as_deref(), Some("asin"));
+    assert_eq!(lookup("int").unwrap().arity, Arity::Range(2, 4));
+    assert!(lookup("range").unwrap().format.is_some());
+    assert_eq!(
+        differentiate(&parse("tan(x)").unwrap(), "x"),
+        simplified("1 / cos(x)^2")
+    );
+}
+
+#[test]
+fn test_canonical_order() {
+    assert_eq!(simplified("x + y"), simplified("y + x"));
+    assert_eq!(simplified("2*x*y"), simplified("y*x*2"));
+    assert_eq!(simplified("a - b + c"), simplified("c + a - b"));
+    assert_eq!(simplified("-x*y"), simplified("x*(-y)"));
+    assert_eq!(simplified("x*y + y*x"), simplified("2 x y"));
+    assert_eq!(simplified("x*y - y*x"), num(0.0));
+}
+
+#[test]
+fn test_format_descending_degree() {
+    // Constants last
+    assert_eq!(latex("1 + x + x^2"), "x^{2} + x + 1");
+    assert_eq!(latex("2x^2 - 5 + x^3 - x"), "x^{3} + 2 x^{2} - x - 5");
+    assert_eq!(latex("y^2 + 1 + x*y + x^2"), "x^{2} + x y + y^{2} + 1");
+    assert_eq!(latex("2 + pi + x"), "x + \\pi + 2");
+    assert_eq!(latex("2i + 3"), "3 + 2i");
+}
+
+#[test]
+fn test_expr_total_order() {
+    // Sorting is deterministic
+    let mut exprs = vec![
+        var("y"),
+        num(2.0),
+        func("sin", var("x")),
+        var("x"),
+        num(0.5),
+    ];
+    exprs.sort();
+    assert_eq!(
+        exprs,
+        vec![
+            num(0.5),
+            num(2.0),
+            var("x"),
+            var("y"),
+            func("sin", var("x"))
+        ]
+    );
+}
+
+#[test]
+fn test_combine_powers() {
+    same("x*x", "x^2");
+    same("x^2*x^3", "x^5");
+    same("x^a*x^b", "x^(a + b)");
+    same("e^x*e^y", "e^(x + y)");
+    same("(x^2)^3", "x^6");
+    same("(2^x)^y", "2^(x y)");
+}
+
+#[test]
+fn test_cancel_powers() {
+    same("x/x", "1");
+    same("x^3/x", "x^2");
+    same("x/x^3", "1/x^2");
+    same("x^2 y / (x y^2)", "x/y");
+    same("2 x^3 / (4 x)", "x^2/2");
+    same("x * (1/x)", "1");
+    same("-x/x", "-1");
+}
+
+#[test]
+fn test_combine_fractional_powers() {
+    // Even powers under a root come out as absolute values
+    same("(x^2)^(1/2)", "abs(x)");
+    // Only where both sides agree for every real `x`, negative ones
+    // through complex numbers: `sqrt(x)^2 = x` but `(x^3)^(1/3)` is not
+    // `x` at `x = -1`
+    same("x^(1/2) * x^(1/2)", "x");
+    same("(x^(1/2))^2", "x");
+    same("(x^(1/2))^(1/3)", "x^(1/6)");
+    assert!(matches!(simplified("(x^3)^(1/3)"), BinaryOp(Pow, _, _)));
+    assert!(matches!(simplified("(x^a)^(1/2)"), BinaryOp(Pow, _, _)));
+    same("x * x^(1/2)", "x^(3/2)");
+    same("x^(3/2) / x^(1/2)", "x");
+}
+
+#[test]
+fn test_readable_derivatives() {
+    assert_eq!(
+        format_expr_latex(&differentiate(&parse("x^3").unwrap(), "x")),
+        "3 x^{2}"
+    );
+    assert_eq!(
+        format_expr_latex(&differentiate(&parse("x/(x + 1)").unwrap(), "x")),
+        "\\frac{1}{\\left(x + 1\\right)^{2}}"
+    );
+}
+
+#[test]
+fn test_expand_products_and_powers() {
+    assert_eq!(expanded("(x + 1)^3"), "x^{3} + 3 x^{2} + 3 x + 1");
+    assert_eq!(expanded("(x - 2)(x + 3)"), "x^{2} + x - 6");
+    assert_eq!(
+        expanded("(a + b + c)^2"),
+        "a^{2} + 2 a b + 2 a c + b^{2} + 2 b c + c^{2}"
+    );
+    assert_eq!(expanded("(2x - 1)^3"), "8 x^{3} - 12 x^{2} + 6 x - 1");
+    assert_eq!(expanded("(x + y)^2 - (x - y)^2"), "4 x y");
+    assert_eq!(expanded("(x + i)(x - i)"), "x^{2} + 1");
+}
+
+#[test]
+fn test_expand_quotients() {
+    // Numerators split over their denominator
+    assert_eq!(expanded("(x^2 + x) / x"), "x + 1");
+    assert_eq!(expanded("(x + 1)^2 / x"), "x + 2 + \\frac{1}{x}");
+    assert_eq!(expanded("(x + 1)^(-2)"), "\\frac{1}{x^{2} + 2 x + 1}");
+}
+
+#[test]
+fn test_expand_inside_functions_only_whole_powers() {
+    assert_eq!(
+        expanded("sin((x + 1)^2)"),
+        "\\sin\\left(x^{2} + 2 x + 1\\right)"
+    );
+    assert_eq!(
+        expand(&parse("(x + 1)^(1/2)").unwrap()),
+        simplified("(x + 1)^(1/2)")
+    );
+    assert!(matches!(
+        expand(&parse("(a + b + c + d)^100").unwrap()),
+        BinaryOp(Pow, _, _)
+    ));
+}
+
+#[test]
+fn test_expand_long_sums_and_large_products() {
+    // Thousands of partial products, summed without deep recursion
+    assert_eq!(
+        expand(&parse("(x + 1)^50 (x - 1)^50").unwrap()),
+        expand(&parse("(x^2 - 1)^50").unwrap())
+    );
+    // More products than the term limit stay unexpanded
+    assert!(matches!(
+        expand(&parse("(x + 1)^200 (y + 1)^200").unwrap()),
+        BinaryOp(Mul, _, _)
+    ));
+}
+
+#[test]
+fn test_expand_keeps_value() {
+    let mut env = Environment::new();
+    env.set_var("x", 1.5);
+    let input = parse("(x - 2)^4 (x + 1) / (2 x)").unwrap();
+    let value = cliph::math::eval::evaluate_with_env(&input, &env);
+    assert!((cliph::math::eval::evaluate_with_env(&expand(&input), &env) - value).abs() < 1e-12);
+}
+
+#[test]
+fn test_polynomials_from_expressions() {
+    // Equal polynomials compare equal however they are written
+    same_poly(&poly("(x + 1)^2"), "x^2 + 2x + 1");
+    same_poly(&(&poly("x + y") * &poly("x - y")), "x^2 - y^2");
+    same_poly(&(&poly("x + y") - &poly("y")), "x");
+    same_poly(&poly("(x - 1) / 2"), "x/2 - 1/2");
+    assert!(Poly::from_expr(&parse("sin(x)").unwrap()).is_none());
+    assert!(Poly::from_expr(&parse("x^(1/2)").unwrap()).is_none());
+    assert!(Poly::from_expr(&parse("1/x").unwrap()).is_none());
+    assert!(Poly::from_expr(&parse("0.5 pi").unwrap()).is_none());
+
+    // And convert back to simplified expressions
+    assert_eq!(poly("(x - 2)(x + 3)").to_expr(), simplified("x^2 + x - 6"));
+    assert_eq!(Poly::zero().to_expr(), num(0.0));
+}
+
+#[test]
+fn test_polynomial_degrees_and_coefficients() {
+    use std::collections::HashMap;
+
+    let p = poly("3 x^2 y + x^2 + y^4");
+    assert_eq!(p.degree("x"), 2);
+    assert_eq!(p.degree("y"), 4);
+    assert_eq!(p.degree("z"), 0);
+    assert_eq!(p.total_degree(), 4);
+    same_poly(&p.leading_coefficient("x"), "3 y + 1");
+    same_poly(&p.coefficient("y", 1), "3 x^2");
+    same_poly(&p.substitute("y", &"2".parse().unwrap()), "7 x^2 + 16");
+    assert_eq!(p.evaluate(&HashMap::from([("x", 2.0), ("y", 1.0)])), 17.0);
+    assert!(p.evaluate(&HashMap::from([("x", 2.0)])).is_nan());
+}
+
+#[test]
+fn test_polynomial_division() {
+    // Long division, `a = q b + r`
+    let (a, b) = (poly("x^3 - 2x + 5"), poly("x - 3"));
+    let (q, r) = a.div_rem(&b).unwrap();
+    same_poly(&q, "x^2 + 3x + 7");
+    same_poly(&r, "26");
+    assert_eq!(&(&q * &b) + &r, a);
+    assert!(a.div_rem(&Poly::zero()).is_none());
+    same_poly(
+        &poly("x^2 - y^2").exact_div(&poly("x + y")).unwrap(),
+        "x - y",
+    );
+    assert!(poly("x^2 + 1").exact_div(&poly("x + 1")).is_none());
+
+    // Pseudo-remainders stay free of fractions in the other variables
+    same_poly(
+        &poly("x^2 + y").pseudo_rem(&poly("2 x y + 1"), "x"),
+        "4 y^3 + 1",
+    );
+}
+
+#[test]
+fn test_polynomial_gcd() {
+    // Monic
+    same_poly(&poly("x^3 - 1").gcd(&poly("x^2 - 1")), "x - 1");
+    same_poly(&poly("6 x^2 y + 3 x y^2").gcd(&poly("4 x y")), "x y");
+    same_poly(
+        &poly("(x + y)^2 (x - y)").gcd(&poly("(x + y) (x + 2y)^3")),
+        "x + y",
+    );
+    same_poly(
+        &poly("(a x + b)(x - 1)").gcd(&poly("(a x + b)(x + 1)")),
+        "a x + b",
+    );
+    same_poly(&poly("2x + 3").gcd(&poly("x^2")), "1");
+    same_poly(&Poly::zero().gcd(&poly("2x + 4")), "x + 2");
+}
+
+#[test]
+fn test_factor_over_the_rationals() {
+    assert_eq!(
+        factored("x^2 - 5x + 6"),
+        "\\left(x - 2\\right) \\left(x - 3\\right)"
+    );
+    assert_eq!(
+        factored("x^4 - 1"),
+        "\\left(x + 1\\right) \\left(x - 1\\right) \\left(x^{2} + 1\\right)"
+    );
+    assert_eq!(
+        factored("x^2 y - y"),
+        "y \\left(x + 1\\right) \\left(x - 1\\right)"
+    );
+    assert_eq!(
+        factored("x^2 - y^2"),
+        "\\left(x + y\\right) \\left(x - y\\right)"
+    );
+    assert_eq!(factored("x^3 - 3x^2 + 3x - 1"), "\\left(x - 1\\right)^{3}");
+    assert_eq!(
+        factored("2x^2 - 2"),
+        "2 \\left(x + 1\\right) \\left(x - 1\\right)"
+    );
+    assert_eq!(
+        factored("1 - x^2"),
+        "-\\left(x + 1\\right) \\left(x - 1\\right)"
+    );
+}
+
+#[test]
+fn test_factor_leaves_irreducibles_and_non_polynomials() {
+    // Irreducible over the rationals, though not modulo any prime
+    assert_eq!(factored("x^4 + 1"), "x^{4} + 1");
+    assert_eq!(
+        factored("x^4 + 4"),
+        "\\left(x^{2} - 2 x + 2\\right) \\left(x^{2} + 2 x + 2\\right)"
+    );
+    assert_eq!(factored("6"), "6");
+    assert_eq!(factored("sin(x)"), "\\sin\\left(x\\right)");
+    // Too large to multiply out, so left as it is
+    assert!(Poly::from_expr(&parse("(10^1000)^1000").unwrap()).is_none());
+    assert!(Poly::from_expr(&parse("(x + 1)^100000").unwrap()).is_none());
+    assert_eq!(
+        evaluate(&factor(&parse("((10^1000)^1000)^1000").unwrap())),
+        f64::INFINITY
+    );
+}
+
+#[test]
+fn test_factor_keeps_input_without_a_split() {
+    // Not split further, so not multiplied out either
+    let input = parse("(x^2 - y^2)(x + y + 1)").unwrap();
+    assert_eq!(factor(&input), simplify(&input));
+    assert_eq!(factored("2x^2 + 2x y + 2"), latex("2x^2 + 2x y + 2"));
+}
+
+#[test]
+fn test_factor_limits() {
+    // Past the degree limit, the input comes back as it is
+    for input in ["x^1000 - 1", "x^300 - 1", "(x + 1)^1000"] {
+        let expr = parse(input).unwrap();
+        assert_eq!(factor(&expr), simplify(&expr), "factor of {}", input);
+    }
+    let (_, factors) = factor_poly(&poly("x^101 - x"));
+    assert_eq!(factors.len(), 1);
+    // Within it, cyclotomic polynomials split completely
+    let (_, factors) = factor_poly(&poly("x^96 - 1"));
+    assert_eq!(factors.len(), 12);
+}
+
+#[test]
+fn test_factors_multiply_back() {
+    for input in [
+        "x^12 - 1",
+        "(3x^3 + 5)(7x^4 - 11)(x^3 - 2)",
+        "(x^4 + x + 1)(x^4 - x + 1)(x^5 + 2)",
+        "(x + 123456789)(x - 987654321)(x^2 + 3)",
+        "x^3 y^2 - x y^4",
+        "(x + y + 1)^3 (x - y) / 4",
+    ] {
+        let p = poly(input);
+        let (constant, factors) = factor_poly(&p);
+        let product = factors
+            .iter()
+            .fold(Poly::constant(constant), |q, (f, e)| &q * &f.pow(*e));
+        assert_eq!(product, p, "{}", input);
+    }
+    let (_, factors) = factor_poly(&poly("(3x^3 + 5)(7x^4 - 11)(x^3 - 2)"));
+    assert_eq!(factors.len(), 3);
+    let (_, factors) = factor_poly(&poly("x^12 - 1"));
+    assert_eq!(factors.len(), 6);
+}